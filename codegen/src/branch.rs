@@ -10,13 +10,16 @@ use std::collections::HashMap;
 pub struct BranchBlock {
     points: Vec<(usize, Token, Option<EntityKey>)>,
     label: Option<EntityKey>,
+    // the range loops the code in the block is in
+    range_depth: OpIndex,
 }
 
 impl BranchBlock {
-    pub fn new(label: Option<EntityKey>) -> BranchBlock {
+    pub fn new(label: Option<EntityKey>, range_depth: OpIndex) -> BranchBlock {
         BranchBlock {
             points: vec![],
             label: label,
+            range_depth: range_depth,
         }
     }
 }
@@ -42,8 +45,16 @@ impl BranchHelper {
         func: &mut FunctionVal,
         token: Token,
         label: Option<EntityKey>,
+        range_depth: OpIndex,
         pos: usize,
     ) {
+        // the range loops jumped out of are ended here
+        let target = self.block_stack.iter().rev().find(|b| b.label == label);
+        if let Some(b) = target {
+            if b.range_depth < range_depth {
+                func.emit_code_with_imm(Opcode::RANGE_END, b.range_depth, Some(pos));
+            }
+        }
         let index = func.code().len();
         func.emit_code_with_imm(Opcode::JUMP, 0, Some(pos));
         self.block_stack
@@ -67,9 +78,9 @@ impl BranchHelper {
         func.emit_code_with_imm(Opcode::JUMP, offset, Some(pos));
     }
 
-    pub fn enter_block(&mut self) {
+    pub fn enter_block(&mut self, range_depth: OpIndex) {
         self.block_stack
-            .push(BranchBlock::new(self.next_block_label.take()))
+            .push(BranchBlock::new(self.next_block_label.take(), range_depth))
     }
 
     pub fn leave_block(&mut self, func: &mut FunctionVal, begin: Option<usize>) {
@@ -57,6 +57,7 @@ pub struct CodeGen<'a> {
     pkg_key: PackageKey,
     func_stack: Vec<FunctionKey>,
     func_t_stack: Vec<TCTypeKey>, // for casting return values to interfaces
+    range_depths: Vec<OpIndex>,   // the range loops the code is in, for each function
     blank_ident: IdentKey,
 }
 
@@ -86,6 +87,7 @@ impl<'a> CodeGen<'a> {
             pkg_key: pkg,
             func_stack: Vec::new(),
             func_t_stack: Vec::new(),
+            range_depths: Vec::new(),
             blank_ident: bk,
         }
     }
@@ -104,33 +106,40 @@ impl<'a> CodeGen<'a> {
                     let meta = lookup.basic_type_from_tc(tctype, self.objects);
                     EntIndex::BuiltInType(meta)
                 }
-                OperandMode::Value => match &*id.name {
-                    "true" => EntIndex::BuiltInVal(Opcode::PUSH_TRUE),
-                    "false" => EntIndex::BuiltInVal(Opcode::PUSH_FALSE),
-                    "nil" => EntIndex::BuiltInVal(Opcode::PUSH_NIL),
-                    _ => unreachable!(),
-                },
-                _ => unreachable!(),
+                OperandMode::Value if &*id.name == "true" => {
+                    EntIndex::BuiltInVal(Opcode::PUSH_TRUE)
+                }
+                OperandMode::Value if &*id.name == "false" => {
+                    EntIndex::BuiltInVal(Opcode::PUSH_FALSE)
+                }
+                OperandMode::Value if &*id.name == "nil" => EntIndex::BuiltInVal(Opcode::PUSH_NIL),
+                _ => self.resolve_var_ident(ident),
             },
             Some(_) => self.resolve_var_ident(ident),
         }
     }
 
     fn resolve_var_ident(&mut self, ident: &IdentKey) -> EntIndex {
-        let entity_key = &self.ast_objs.idents[*ident].entity_key().unwrap();
+        let entity_key = match self.ast_objs.idents[*ident].entity_key() {
+            Some(key) => key,
+            // declared in a later file of the package, which the parser
+            // could not see yet
+            None => return EntIndex::PackageMember(self.pkg_key, *ident),
+        };
+        let entity_key = &entity_key;
         // 1. try local first
         if let Some(index) = current_func!(self).entity_index(&entity_key).map(|x| *x) {
             return index;
         }
         // 2. try upvalue
-        let upvalue = self
-            .func_stack
-            .clone()
+        let funcs = self.func_stack.clone();
+        let upvalue = funcs
             .iter()
+            .enumerate()
             .skip(1) // skip package constructor
             .rev()
             .skip(1) // skip itself
-            .find_map(|ifunc| {
+            .find_map(|(i, ifunc)| {
                 let f = &mut self.objects.functions[*ifunc];
                 let index = f.entity_index(&entity_key).map(|x| *x);
                 if let Some(ind) = index {
@@ -140,12 +149,17 @@ impl<'a> CodeGen<'a> {
                         self.tlookup.get_use_value_type(*ident),
                         true,
                     );
-                    Some(desc)
+                    Some((i, desc))
                 } else {
                     None
                 }
             });
-        if let Some(uv) = upvalue {
+        if let Some((i, uv)) = upvalue {
+            // the closures in between capture it too, so that it can be reached
+            // from a goroutine, or after the function defining it has returned
+            for f in funcs[i + 1..funcs.len() - 1].iter() {
+                self.objects.functions[*f].try_add_upvalue(&entity_key, uv.clone());
+            }
             let func = current_func_mut!(self);
             let index = func.try_add_upvalue(&entity_key, uv);
             return index;
@@ -204,11 +218,15 @@ impl<'a> CodeGen<'a> {
         self.gen_assign_def_var(&lhs, &vs.typ, &rhs);
     }
 
-    fn gen_def_const(&mut self, names: &Vec<IdentKey>, values: &Vec<Expr>) {
-        assert!(names.len() == values.len());
-        for i in 0..names.len() {
-            let ident = self.ast_objs.idents[names[i]].clone();
-            let val = self.tlookup.get_const_value(values[i].id());
+    // the values are the ones worked out by the checker, as a spec without
+    // any repeats the expressions of the one above it
+    fn gen_def_const(&mut self, names: &Vec<IdentKey>) {
+        for name in names.iter() {
+            let ident = self.ast_objs.idents[*name].clone();
+            if ident.is_blank() {
+                continue;
+            }
+            let val = self.tlookup.get_def_const_value(*name);
             self.current_func_add_const_def(&ident, val);
         }
     }
@@ -250,14 +268,8 @@ impl<'a> CodeGen<'a> {
                         let ind = &ind_expr.as_ref().index;
                         let pos = ind_expr.as_ref().l_brack;
 
-                        let mut index_const = None;
+                        let index_const = self.const_index(ind);
                         let mut index_typ = None;
-                        if let Some(const_val) = self.tlookup.get_tc_const_value(ind.id()) {
-                            let (ival, _) = const_val.to_int().int_as_i64();
-                            if let Ok(i) = OpIndex::try_from(ival) {
-                                index_const = Some(i);
-                            }
-                        }
                         if index_const.is_none() {
                             self.visit_expr(ind);
                             index_typ = Some(self.tlookup.get_expr_value_type(ind));
@@ -532,7 +544,15 @@ impl<'a> CodeGen<'a> {
             let pos = Some(*p);
             match l {
                 LeftHandSide::Primitive(_) => {
-                    current_func_emitter!(self).emit_store(l, rhs_index, None, None, typ, pos);
+                    let fkey = self.func_stack.last().unwrap();
+                    current_func_emitter!(self).emit_store(
+                        l,
+                        rhs_index,
+                        None,
+                        Some((self.pkg_util.pairs_mut(), *fkey)),
+                        typ,
+                        pos,
+                    );
                 }
                 LeftHandSide::IndexSelExpr(info) => {
                     current_func_emitter!(self).emit_store(
@@ -657,11 +677,10 @@ impl<'a> CodeGen<'a> {
                 None => has_default = true,
             }
         }
-        if has_default {
-            let func = current_func_mut!(self);
-            helper.tags.add_default(func.next_code_index());
-            func.emit_code(Opcode::JUMP, None);
-        }
+        // jumps to the default case, or to the end if there isn't one
+        let func = current_func_mut!(self);
+        helper.tags.add_default(func.next_code_index());
+        func.emit_code(Opcode::JUMP, None);
 
         for (i, stmt) in body.list.iter().enumerate() {
             let cc = SwitchHelper::to_case_clause(stmt);
@@ -687,17 +706,120 @@ impl<'a> CodeGen<'a> {
         }
         let end = current_func!(self).next_code_index();
         helper.patch_ends(current_func_mut!(self), end);
+        if !has_default {
+            helper.tags.patch_default(current_func_mut!(self), end);
+        }
 
         // pop the tag
         current_func_emitter!(self).emit_pop(1, None);
     }
 
+    fn pkg_path(&self, tcpkg: TCPackageKey) -> &str {
+        let pkg = &self.tc_objs.pkgs[tcpkg];
+        // the path of the main package is the one of its directory or file
+        match pkg.name().as_deref() {
+            Some("main") => "main",
+            _ => pkg.path(),
+        }
+    }
+
+    // the package path of decl, the name of the receiver type and if it's a
+    // pointer, and the name of the function
+    fn decl_names(&self, decl: &FuncDecl) -> (&str, Option<(&str, bool)>, &str) {
+        let okey = self.tlookup.type_info().defs[&decl.name].unwrap();
+        let path = self.pkg_path(self.tc_objs.lobjs[okey].pkg().unwrap());
+        let name = &self.ast_objs.idents[decl.name].name;
+        let recv = decl.recv.as_ref().map(|recv| {
+            let (typ, ptr) = match &self.ast_objs.fields[recv.list[0]].typ {
+                Expr::Star(s) => (&s.expr, true),
+                t => (t, false),
+            };
+            let tname = &self.ast_objs.idents[*typ.try_as_ident().unwrap()].name;
+            (tname.as_str(), ptr)
+        });
+        (path, recv, name)
+    }
+
+    // the name a function declared without a body is registered with in
+    // Rust: "pkgpath.FuncName", or "pkgpath.TypeName.MethodName"
+    fn native_func_name(&self, decl: &FuncDecl) -> String {
+        match self.decl_names(decl) {
+            (path, Some((tname, _)), name) => format!("{}.{}.{}", path, tname, name),
+            (path, None, name) => format!("{}.{}", path, name),
+        }
+    }
+
+    // the name of a function in the tracebacks, like Go does: "pkgpath.Func",
+    // "pkgpath.(*TypeName).Method" or "pkgpath.TypeName.Method"
+    fn go_func_name(&self, decl: &FuncDecl) -> String {
+        match self.decl_names(decl) {
+            (path, Some((tname, true)), name) => format!("{}.(*{}).{}", path, tname, name),
+            (path, Some((tname, false)), name) => format!("{}.{}.{}", path, tname, name),
+            (path, None, name) => format!("{}.{}", path, name),
+        }
+    }
+
     fn gen_func_def(
         &mut self,
         tc_type: TCTypeKey, // GosMetadata,
         fkey: FuncTypeKey,
         recv: Option<FieldList>,
+        go_name: String,
         body: &BlockStmt,
+    ) -> FunctionKey {
+        let fkey = self.gen_func_params(tc_type, fkey, recv, go_name);
+        self.gen_func_body(fkey, tc_type, body);
+        fkey
+    }
+
+    // generates the body of the function fkey
+    fn gen_func_body(&mut self, fkey: FunctionKey, tc_type: TCTypeKey, body: &BlockStmt) {
+        self.func_stack.push(fkey);
+        self.func_t_stack.push(tc_type);
+        self.range_depths.push(0);
+        // process function body
+        self.visit_stmt_block(body);
+        // it will not be executed if it's redundant
+        Emitter::new(&mut self.objects.functions[fkey]).emit_return(None, Some(body.r_brace));
+
+        self.func_stack.pop();
+        self.func_t_stack.pop();
+        self.range_depths.pop();
+    }
+
+    /// gen_native_func_def generates a function declared without a body, it
+    /// calls the Rust function registered as name.
+    fn gen_native_func_def(
+        &mut self,
+        tc_type: TCTypeKey,
+        fkey: FuncTypeKey,
+        recv: Option<FieldList>,
+        go_name: String,
+        name: String,
+        pos: usize,
+    ) -> FunctionKey {
+        let fkey = self.gen_func_params(tc_type, fkey, recv, go_name);
+        self.gen_native_func_body(fkey, name, pos);
+        fkey
+    }
+
+    // generates the body of the function fkey, which calls the Rust function
+    // registered as name
+    fn gen_native_func_body(&mut self, fkey: FunctionKey, name: String, pos: usize) {
+        let func = &mut self.objects.functions[fkey];
+        let i = func.add_const(None, GosValue::new_str(name));
+        func.emit_code_with_imm(Opcode::NATIVE, i.into(), Some(pos));
+        Emitter::new(func).emit_return(None, Some(pos));
+    }
+
+    // creates a function named go_name with the parameters and results of the
+    // signature
+    fn gen_func_params(
+        &mut self,
+        tc_type: TCTypeKey,
+        fkey: FuncTypeKey,
+        recv: Option<FieldList>,
+        go_name: String,
     ) -> FunctionKey {
         let typ = &self.ast_objs.ftypes[fkey];
         let fmeta = self
@@ -711,6 +833,7 @@ impl<'a> CodeGen<'a> {
             FuncFlag::Default,
         );
         let fkey = *f.as_function();
+        self.objects.functions[fkey].name = go_name;
         let mut emitter = Emitter::new(&mut self.objects.functions[fkey]);
         if let Some(fl) = &typ.results {
             emitter.add_params(&fl, self.ast_objs);
@@ -723,15 +846,6 @@ impl<'a> CodeGen<'a> {
             }
             None => emitter.add_params(&typ.params, self.ast_objs),
         };
-        self.func_stack.push(fkey);
-        self.func_t_stack.push(tc_type);
-        // process function body
-        self.visit_stmt_block(body);
-        // it will not be executed if it's redundant
-        Emitter::new(&mut self.objects.functions[fkey]).emit_return(None, Some(body.r_brace));
-
-        self.func_stack.pop();
-        self.func_t_stack.pop();
         fkey
     }
 
@@ -751,6 +865,8 @@ impl<'a> CodeGen<'a> {
                     Builtin::Recover => Opcode::RECOVER,
                     Builtin::Assert => Opcode::ASSERT,
                     Builtin::Ffi => Opcode::FFI,
+                    Builtin::Print => Opcode::PRINT,
+                    Builtin::Println => Opcode::PRINTLN,
                     _ => unimplemented!(),
                 };
                 for e in params.iter() {
@@ -759,8 +875,9 @@ impl<'a> CodeGen<'a> {
                 // some of the built in funcs are not recorded
                 if let Some(t) = self.tlookup.try_get_expr_tc_type(func_expr) {
                     self.try_cast_params_to_iface(t, params, ellipsis);
-                    if opcode == Opcode::FFI {
-                        // FFI needs the signature of the call
+                    if opcode == Opcode::FFI || opcode == Opcode::PRINT || opcode == Opcode::PRINTLN
+                    {
+                        // FFI and print need the signature of the call
                         let meta = self.tlookup.meta_from_tc(t, self.objects, self.dummy_gcv);
                         let mut emitter = current_func_emitter!(self);
                         let i = emitter.add_const(None, GosValue::Metadata(meta));
@@ -875,17 +992,28 @@ impl<'a> CodeGen<'a> {
         let t1 = self.tlookup.get_expr_value_type(index);
         self.visit_expr(expr);
         let pos = Some(expr.pos(&self.ast_objs));
-        if let Some(const_val) = self.tlookup.get_tc_const_value(index.id()) {
-            let (ival, _) = const_val.to_int().int_as_i64();
-            if let Ok(i) = OpIndex::try_from(ival) {
-                current_func_emitter!(self).emit_load_index_imm(i, t0, comma_ok, pos);
-                return;
-            }
+        if let Some(i) = self.const_index(index) {
+            current_func_emitter!(self).emit_load_index_imm(i, t0, comma_ok, pos);
+            return;
         }
         self.visit_expr(index);
         current_func_emitter!(self).emit_load_index(t0, t1, comma_ok, pos);
     }
 
+    // returns the index if it's a constant that can be used as the immediate
+    // of LOAD_INDEX_IMM and STORE_INDEX_IMM, which take it as an int, so that
+    // a map key of any other type, like string, is not mistaken for one
+    fn const_index(&mut self, index: &Expr) -> Option<OpIndex> {
+        if self.tlookup.get_expr_value_type(index) != ValueType::Int {
+            return None;
+        }
+        let const_val = self.tlookup.get_tc_const_value(index.id())?;
+        match const_val.to_int().int_as_i64() {
+            (ival, true) => OpIndex::try_from(ival).ok(),
+            _ => None,
+        }
+    }
+
     fn try_cast_to_iface(
         &mut self,
         lhs: Option<TCTypeKey>,
@@ -899,11 +1027,23 @@ impl<'a> CodeGen<'a> {
                 let (cast, typ) = match rhs {
                     Some(t1) => {
                         let vt1 = self.tlookup.underlying_value_type_from_tc(t1);
-                        (vt1 != ValueType::Interface && vt1 != ValueType::Nil, vt1)
+                        // an interface converted to another interface type
+                        // gets its methods looked up again at runtime
+                        let cast = match vt1 {
+                            ValueType::Nil => false,
+                            ValueType::Interface => t0 != t1,
+                            _ => true,
+                        };
+                        (cast, vt1)
                     }
                     None => (true, ValueType::Slice), // it must be a variadic parameter
                 };
                 if cast {
+                    let rhs = if typ == ValueType::Interface {
+                        None
+                    } else {
+                        rhs
+                    };
                     let index = self.iface_mapping.get_index(
                         &(t0, rhs),
                         &mut self.tlookup,
@@ -1013,18 +1153,17 @@ impl<'a> CodeGen<'a> {
             MetadataType::Struct(f, _) => {
                 let struct_type = typ.try_as_struct().unwrap();
                 for (i, expr) in clit.elts.iter().enumerate() {
-                    let field_type = self.tc_objs.lobjs[struct_type.fields()[i]].typ().unwrap();
-                    let index = match expr {
+                    // keyed elements can be in any order, or leave fields out
+                    let (index, val) = match expr {
                         Expr::KeyValue(kv) => {
-                            self.visit_composite_expr(&kv.val, field_type);
                             let ident = kv.key.try_as_ident().unwrap();
-                            f.mapping[&self.ast_objs.idents[*ident].name]
-                        }
-                        _ => {
-                            self.visit_composite_expr(expr, field_type);
-                            i as OpIndex
+                            (f.mapping[&self.ast_objs.idents[*ident].name], &kv.val)
                         }
+                        _ => (i as OpIndex, expr),
                     };
+                    let field = struct_type.fields()[index as usize];
+                    let field_type = self.tc_objs.lobjs[field].typ().unwrap();
+                    self.visit_composite_expr(val, field_type);
                     current_func_emitter!(self).emit_push_imm(ValueType::Uint, index, pos);
                 }
             }
@@ -1083,7 +1222,38 @@ impl<'a> CodeGen<'a> {
                     .tlookup
                     .gen_def_type_meta(*n, self.objects, self.dummy_gcv);
                 let val = zero_val!(meta, self.objects, self.dummy_gcv);
-                self.objects.packages[pkey].add_member(ident.name.clone(), val);
+                self.objects.packages[pkey].add_var_member(ident.name.clone(), val, meta);
+            }
+        }
+    }
+
+    // the metadata of the receiver type of the method decl
+    fn recv_meta(&mut self, decl: &FuncDecl) -> GosMetadata {
+        let field = &self.ast_objs.fields[decl.recv.as_ref().unwrap().list[0]];
+        self.tlookup
+            .get_meta_by_node_id(field.typ.id(), self.objects, self.dummy_gcv)
+    }
+
+    /// declare_methods creates the functions of the methods declared in files,
+    /// before the code of any package is generated, so that a method can be
+    /// bound wherever it's used, whatever the order of the declarations and
+    /// the packages. gen_with_files generates their bodies.
+    pub fn declare_methods(&mut self, files: &Vec<File>) {
+        for f in files.iter() {
+            for d in f.decls.iter() {
+                let decl = match d {
+                    Decl::Func(fdecl) => &self.ast_objs.fdecls[*fdecl],
+                    _ => continue,
+                };
+                if decl.recv.is_none() {
+                    continue;
+                }
+                let tc_type = self.tlookup.get_def_tc_type(decl.name);
+                let go_name = self.go_func_name(decl);
+                let fkey = self.gen_func_params(tc_type, decl.typ, decl.recv.clone(), go_name);
+                let meta = self.recv_meta(decl);
+                let name = &self.ast_objs.idents[decl.name].name;
+                meta.set_method_code(name, fkey, &mut self.objects.metas);
             }
         }
     }
@@ -1094,6 +1264,7 @@ impl<'a> CodeGen<'a> {
         let f =
             GosValue::new_function(pkey, fmeta, self.objects, self.dummy_gcv, FuncFlag::PkgCtor);
         let fkey = *f.as_function();
+        self.objects.functions[fkey].name = format!("{}.init", self.pkg_path(tcpkg));
         // the 0th member is the constructor
         self.objects.packages[pkey].add_member(
             String::new(),
@@ -1161,7 +1332,15 @@ impl<'a> ExprVisitor for CodeGen<'a> {
     /// Add function as a const and then generate a closure of it
     fn visit_expr_func_lit(&mut self, this: &Expr, flit: &FuncLit) {
         let tc_type = self.tlookup.get_node_tc_type(this.id());
-        let fkey = self.gen_func_def(tc_type, flit.typ, None, &flit.body);
+        // named after the enclosing function, like Go does
+        let parent = current_func!(self);
+        let count = parent
+            .consts
+            .iter()
+            .filter(|c| matches!(c, GosValue::Function(_)))
+            .count();
+        let name = format!("{}.func{}", parent.name, count + 1);
+        let fkey = self.gen_func_def(tc_type, flit.typ, None, name, &flit.body);
         let mut emitter = current_func_emitter!(self);
         let i = emitter.add_const(None, GosValue::Function(fkey));
         let pos = Some(flit.body.l_brace);
@@ -1197,7 +1376,7 @@ impl<'a> ExprVisitor for CodeGen<'a> {
             .tlookup
             .get_meta_by_node_id(expr.id(), self.objects, self.dummy_gcv);
         let name = &self.ast_objs.idents[*ident].name;
-        if t1 == ValueType::Closure {
+        if t1 == ValueType::Closure && !self.tlookup.is_field_selection(this.id()) {
             if meta
                 .get_underlying(&self.objects.metas)
                 .get_value_type(&self.objects.metas)
@@ -1214,7 +1393,8 @@ impl<'a> ExprVisitor for CodeGen<'a> {
             } else {
                 let i = meta.method_index(name, &self.objects.metas);
                 let method = meta.get_method(i, &self.objects.metas);
-                if method.borrow().pointer_recv {
+                let is_ptr = meta.get_value_type(&self.objects.metas) == ValueType::Pointer;
+                if method.borrow().pointer_recv && !is_ptr {
                     // desugar
                     self.visit_expr_unary(this, expr, &Token::AND);
                 } else {
@@ -1570,7 +1750,7 @@ impl<'a> StmtVisitor for CodeGen<'a> {
                             self.gen_def_var(vs);
                         }
                     }
-                    Token::CONST => self.gen_def_const(&vs.names, &vs.values),
+                    Token::CONST => self.gen_def_const(&vs.names),
                     _ => unreachable!(),
                 },
             }
@@ -1579,26 +1759,37 @@ impl<'a> StmtVisitor for CodeGen<'a> {
 
     fn visit_stmt_decl_func(&mut self, fdecl: &FuncDeclKey) -> Self::Result {
         let decl = &self.ast_objs.fdecls[*fdecl];
-        if decl.body.is_none() {
-            unimplemented!()
-        }
         let tc_type = self.tlookup.get_def_tc_type(decl.name);
-        let stmt = decl.body.as_ref().unwrap();
-        let fkey = self.gen_func_def(tc_type, decl.typ, decl.recv.clone(), stmt);
-        let cls = GosValue::new_closure(fkey, &self.objects.functions);
-        // this is a struct method
-        if let Some(self_ident) = &decl.recv {
-            let field = &self.ast_objs.fields[self_ident.list[0]];
+        // this is a struct method, its function is created by declare_methods
+        if decl.recv.is_some() {
+            let meta = self.recv_meta(decl);
             let name = &self.ast_objs.idents[decl.name].name;
-            let meta =
-                self.tlookup
-                    .get_meta_by_node_id(field.typ.id(), self.objects, self.dummy_gcv);
-            meta.set_method_code(name, fkey, &mut self.objects.metas);
-        } else {
-            let ident = &self.ast_objs.idents[decl.name];
-            let pkg = &mut self.objects.packages[self.pkg_key];
-            pkg.add_member(ident.name.clone(), cls);
+            let metas = &self.objects.metas;
+            let method = meta.get_method(meta.method_index(name, metas), metas);
+            let fkey = method.borrow().func.unwrap();
+            match &decl.body {
+                Some(stmt) => self.gen_func_body(fkey, tc_type, stmt),
+                None => {
+                    let name = self.native_func_name(decl);
+                    let pos = self.ast_objs.idents[decl.name].pos;
+                    self.gen_native_func_body(fkey, name, pos)
+                }
+            }
+            return;
         }
+        let go_name = self.go_func_name(decl);
+        let fkey = match &decl.body {
+            Some(stmt) => self.gen_func_def(tc_type, decl.typ, None, go_name, stmt),
+            None => {
+                let name = self.native_func_name(decl);
+                let pos = self.ast_objs.idents[decl.name].pos;
+                self.gen_native_func_def(tc_type, decl.typ, None, go_name, name, pos)
+            }
+        };
+        let cls = GosValue::new_closure(fkey, &self.objects.functions);
+        let ident = &self.ast_objs.idents[decl.name];
+        let pkg = &mut self.objects.packages[self.pkg_key];
+        pkg.add_member(ident.name.clone(), cls);
     }
 
     fn visit_stmt_labeled(&mut self, lstmt: &LabeledStmtKey) {
@@ -1667,6 +1858,35 @@ impl<'a> StmtVisitor for CodeGen<'a> {
         let types = self
             .tlookup
             .get_sig_returns_tc_types(*self.func_t_stack.last().unwrap());
+        if rstmt.results.len() == 1 && types.len() > 1 {
+            // returning the results of a call
+            let expr = &rstmt.results[0];
+            self.visit_expr(expr);
+            let tc_type = self.tlookup.get_expr_tc_type(expr);
+            let rhs_types = self.tlookup.tuple_tc_types(tc_type);
+            let count = types.len() as OpIndex;
+            for (i, rhs) in rhs_types.into_iter().enumerate() {
+                let index = i as OpIndex - count;
+                let t = self.try_cast_to_iface(
+                    Some(types[i]),
+                    Some(rhs),
+                    index,
+                    expr.pos(&self.ast_objs),
+                );
+                current_func_emitter!(self).emit_store(
+                    &LeftHandSide::Primitive(EntIndex::LocalVar(i as OpIndex)),
+                    index,
+                    None,
+                    None,
+                    t,
+                    pos,
+                );
+            }
+            let mut emitter = current_func_emitter!(self);
+            emitter.emit_pop(count, pos);
+            emitter.emit_return(None, pos);
+            return;
+        }
         for (i, expr) in rstmt.results.iter().enumerate() {
             self.visit_expr(expr);
             let tc_type = self.tlookup.get_expr_tc_type(expr);
@@ -1692,10 +1912,12 @@ impl<'a> StmtVisitor for CodeGen<'a> {
                 let entity = bstmt
                     .label
                     .map(|x| self.ast_objs.idents[x].entity_key().unwrap());
+                let depth = *self.range_depths.last().unwrap();
                 self.branch.add_point(
                     current_func_mut!(self),
                     bstmt.token.clone(),
                     entity,
+                    depth,
                     bstmt.token_pos,
                 );
             }
@@ -1758,7 +1980,7 @@ impl<'a> StmtVisitor for CodeGen<'a> {
     }
 
     fn visit_stmt_switch(&mut self, sstmt: &SwitchStmt) {
-        self.branch.enter_block();
+        self.branch.enter_block(*self.range_depths.last().unwrap());
 
         if let Some(init) = &sstmt.init {
             self.visit_stmt(init);
@@ -1843,7 +2065,7 @@ impl<'a> StmtVisitor for CodeGen<'a> {
         Since communication on nil channels can never proceed, a select with only nil
         channels and no default case blocks forever.
         */
-        self.branch.enter_block();
+        self.branch.enter_block(*self.range_depths.last().unwrap());
 
         let mut helper = SelectHelper::new();
         let comms: Vec<&CommClause> = sstmt
@@ -1852,6 +2074,13 @@ impl<'a> StmtVisitor for CodeGen<'a> {
             .iter()
             .map(|s| SelectHelper::to_comm_clause(s))
             .collect();
+        // select {} blocks forever, it's a SELECT of no blocks
+        if comms.is_empty() {
+            let func = current_func_mut!(self);
+            func.emit_code_with_imm(Opcode::SELECT, 0, Some(sstmt.select));
+            self.branch.leave_block(current_func_mut!(self), None);
+            return;
+        }
         for c in comms.iter() {
             let (typ, pos) = match &c.comm {
                 Some(comm) => match comm {
@@ -1922,7 +2151,7 @@ impl<'a> StmtVisitor for CodeGen<'a> {
     }
 
     fn visit_stmt_for(&mut self, fstmt: &ForStmt) {
-        self.branch.enter_block();
+        self.branch.enter_block(*self.range_depths.last().unwrap());
 
         if let Some(init) = &fstmt.init {
             self.visit_stmt(init);
@@ -1964,7 +2193,8 @@ impl<'a> StmtVisitor for CodeGen<'a> {
     }
 
     fn visit_stmt_range(&mut self, rstmt: &RangeStmt) {
-        self.branch.enter_block();
+        let depth = *self.range_depths.last().unwrap();
+        self.branch.enter_block(depth + 1);
 
         let blank = Expr::Ident(self.blank_ident);
         let lhs = vec![
@@ -1975,7 +2205,9 @@ impl<'a> StmtVisitor for CodeGen<'a> {
             .gen_assign(&rstmt.token, &lhs, RightHandSide::Range(&rstmt.expr))
             .unwrap();
 
+        *self.range_depths.last_mut().unwrap() += 1;
         self.visit_stmt_block(&rstmt.body);
+        *self.range_depths.last_mut().unwrap() -= 1;
         // jump to the top
         let func = current_func_mut!(self);
         let offset = -func.offset(marker) - 1;
@@ -1986,6 +2218,8 @@ impl<'a> StmtVisitor for CodeGen<'a> {
 
         self.branch
             .leave_block(current_func_mut!(self), Some(marker));
+        // where it jumps to when it's done or broken out of
+        current_func_mut!(self).emit_code_with_imm(Opcode::RANGE_END, depth, Some(rstmt.token_pos));
     }
 
     fn visit_empty_stmt(&mut self, _e: &EmptyStmt) {}
@@ -9,7 +9,7 @@ use goscript_parser::errors::ErrorList;
 use goscript_parser::objects::Objects as AstObjects;
 use goscript_parser::objects::*;
 use goscript_parser::FileSet;
-use goscript_types::{Config, PackageKey as TCPackageKey, TCObjects, TypeInfo};
+use goscript_types::{Config, PackageKey as TCPackageKey, SourceRead, TCObjects, TypeInfo};
 use goscript_vm::gc::GcoVec;
 use goscript_vm::instruction::*;
 use goscript_vm::null_key;
@@ -97,6 +97,22 @@ impl<'a> EntryGen<'a> {
         }
         let mut type_cache: TypeCache = HashMap::new();
         let mut pairs = PkgVarPairs::new();
+        for (i, (_, ti)) in checker_result.iter().enumerate() {
+            let mut cgen = CodeGen::new(
+                &mut self.objects,
+                self.ast_objs,
+                self.tc_objs,
+                &mut self.dummy_gcv,
+                &ti,
+                &mut type_cache,
+                &mut self.iface_mapping,
+                &self.pkg_indices,
+                &self.packages,
+                self.packages[i],
+                self.blank_ident,
+            );
+            cgen.declare_methods(&ti.ast_files);
+        }
         for (i, (tcpkg, ti)) in checker_result.iter().enumerate() {
             let mut cgen = CodeGen::new(
                 &mut self.objects,
@@ -127,9 +143,35 @@ impl<'a> EntryGen<'a> {
     }
 }
 
+/// parse_check parses and type checks the package at path and its imports,
+/// without generating any code.
+pub fn parse_check(
+    path: &str,
+    config: &Config,
+    reader: &dyn SourceRead,
+    fset: &mut FileSet,
+    el: &ErrorList,
+) -> Result<(), usize> {
+    let asto = &mut AstObjects::new();
+    let tco = &mut goscript_types::TCObjects::new();
+    let results = &mut HashMap::new();
+    let pkgs = &mut HashMap::new();
+
+    let importer =
+        &mut goscript_types::Importer::new(&config, reader, fset, pkgs, results, asto, tco, el, 0);
+    let key = goscript_types::ImportKey::new(path, "./");
+    let _ = importer.import(&key);
+    if el.len() > 0 {
+        Err(el.len())
+    } else {
+        Ok(())
+    }
+}
+
 pub fn parse_check_gen(
     path: &str,
     config: &Config,
+    reader: &dyn SourceRead,
     fset: &mut FileSet,
     el: &ErrorList,
 ) -> Result<ByteCode, usize> {
@@ -139,7 +181,7 @@ pub fn parse_check_gen(
     let pkgs = &mut HashMap::new();
 
     let importer =
-        &mut goscript_types::Importer::new(&config, fset, pkgs, results, asto, tco, el, 0);
+        &mut goscript_types::Importer::new(&config, reader, fset, pkgs, results, asto, tco, el, 0);
     let key = goscript_types::ImportKey::new(path, "./");
     let main_pkg = importer.import(&key);
 
@@ -134,9 +134,10 @@ impl<'a> PkgUtil<'a> {
                                 let spec = &self.ast_objs.specs[*spec_key];
                                 match spec {
                                     Spec::Value(v) => {
+                                        // the ones without initializers are not in
+                                        // init_order, they go first, as None
                                         let name = &self.ast_objs.idents[v.names[0]].name;
-                                        let order = orders[name];
-                                        decls.push((v.clone(), order));
+                                        decls.push((v.clone(), orders.get(name).copied()));
                                     }
                                     _ => unimplemented!(),
                                 }
@@ -4,7 +4,7 @@ use goscript_parser::ast::{Expr, NodeId};
 use goscript_parser::objects::IdentKey;
 use goscript_types::{
     BasicType, ChanDir, ConstValue, EntityType, ObjKey, OperandMode, PackageKey as TCPackageKey,
-    TCObjects, Type, TypeInfo, TypeKey as TCTypeKey,
+    SelectionKind, TCObjects, Type, TypeInfo, TypeKey as TCTypeKey,
 };
 use goscript_vm::gc::GcoVec;
 use goscript_vm::instruction::{OpIndex, ValueType};
@@ -48,6 +48,11 @@ impl<'a> TypeLookup<'a> {
         self.const_value(typ_val.typ, const_val)
     }
 
+    pub fn get_def_const_value(&mut self, ikey: IdentKey) -> GosValue {
+        let obj = &self.tc_objs.lobjs[self.ti.defs[&ikey].unwrap()];
+        self.const_value(obj.typ().unwrap(), obj.const_val())
+    }
+
     pub fn get_expr_tc_type(&self, e: &Expr) -> TCTypeKey {
         self.get_node_tc_type(e.id())
     }
@@ -166,6 +171,15 @@ impl<'a> TypeLookup<'a> {
         (t0, t1)
     }
 
+    /// is_field_selection tells if the selector expression id selects a struct
+    /// field, rather than a method.
+    pub fn is_field_selection(&self, id: NodeId) -> bool {
+        match self.ti.selections[&id].kind() {
+            SelectionKind::FieldVal => true,
+            _ => false,
+        }
+    }
+
     pub fn meta_from_tc(
         &mut self,
         typ: TCTypeKey,
@@ -463,7 +477,10 @@ impl<'a> TypeLookup<'a> {
             Type::Chan(_) => ValueType::Channel,
             Type::Signature(_) => ValueType::Closure,
             Type::Pointer(_) => ValueType::Pointer,
-            Type::Named(_) => ValueType::Named,
+            Type::Named(n) => match self.value_type_from_tc(n.underlying()) {
+                t if t.is_basic() => t,
+                _ => ValueType::Named,
+            },
             _ => {
                 dbg!(&self.tc_objs.types[typ]);
                 unimplemented!()
@@ -480,6 +497,8 @@ impl<'a> TypeLookup<'a> {
             },
             Type::Slice(detail) => [typ, t_int, detail.elem()],
             Type::Map(detail) => [typ, detail.key(), detail.elem()],
+            // there is no second value, the type is just a placeholder
+            Type::Chan(detail) => [typ, detail.elem(), detail.elem()],
             _ => {
                 dbg!(&self.tc_objs.types[typ]);
                 unreachable!()
@@ -487,7 +506,7 @@ impl<'a> TypeLookup<'a> {
         }
     }
 
-    fn tuple_tc_types(&self, typ: TCTypeKey) -> Vec<TCTypeKey> {
+    pub fn tuple_tc_types(&self, typ: TCTypeKey) -> Vec<TCTypeKey> {
         match &self.tc_objs.types[typ] {
             Type::Tuple(detail) => detail
                 .vars()
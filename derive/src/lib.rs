@@ -0,0 +1,174 @@
+//! The derive macros of goscript, the generated code refers to the items
+//! exported by goscript_engine.
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, LitStr};
+
+/// GoStruct implements goscript_engine::GoStruct for a struct with named
+/// fields, so that it converts to and from the Go struct type of the same
+/// name, declared with the source returned by go_decl.
+#[proc_macro_derive(GoStruct, attributes(gos))]
+pub fn derive_go_struct(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_go_struct(&input) {
+        Ok(ts) => ts.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_go_struct(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "GoStruct cannot be derived for generic structs",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Named(f) => &f.named,
+            _ => return Err(named_fields_only(input)),
+        },
+        _ => return Err(named_fields_only(input)),
+    };
+
+    let ident = &input.ident;
+    let rust_name = ident.to_string();
+    let go_name = rename(&input.attrs)?.unwrap_or_else(|| rust_name.clone());
+    let idents: Vec<_> = fields.iter().map(|f| f.ident.clone().unwrap()).collect();
+    let types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+    let go_names = fields
+        .iter()
+        .map(|f| Ok(rename(&f.attrs)?.unwrap_or_else(|| f.ident.as_ref().unwrap().to_string())))
+        .collect::<syn::Result<Vec<String>>>()?;
+    let indices: Vec<usize> = (0..fields.len()).collect();
+
+    Ok(quote! {
+        impl ::goscript_engine::GoType for #ident {
+            fn go_type() -> ::std::string::String {
+                #go_name.to_string()
+            }
+        }
+
+        impl ::goscript_engine::GoStruct for #ident {
+            fn go_decl() -> ::std::string::String {
+                let mut s = format!("type {} struct {{\n", #go_name);
+                #(
+                    s.push_str(&format!(
+                        "    {} {}\n",
+                        #go_names,
+                        <#types as ::goscript_engine::GoType>::go_type()
+                    ));
+                )*
+                s.push_str("}\n");
+                s
+            }
+        }
+
+        impl ::goscript_engine::FromGos for #ident {
+            fn from_gos(
+                _v: ::goscript_engine::GosValue,
+            ) -> ::goscript_engine::FfiResult<Self> {
+                Err(format!(
+                    "converting a Go struct to {} needs the types of the VM",
+                    #rust_name
+                ))
+            }
+
+            fn from_gos_typed(
+                v: ::goscript_engine::GosValue,
+                metas: &::goscript_engine::MetadataObjs,
+            ) -> ::goscript_engine::FfiResult<Self> {
+                let names = [#(#go_names),*];
+                let fields = ::goscript_engine::struct_from_gos::<Self>(v, metas, &names)?;
+                let mut fields = fields.into_iter();
+                Ok(#ident {
+                    #(
+                        #idents: ::goscript_engine::FromGos::from_gos_typed(
+                            fields.next().unwrap(),
+                            metas,
+                        )?,
+                    )*
+                })
+            }
+
+            fn converts_from(
+                meta: ::goscript_engine::GosMetadata,
+                metas: &::goscript_engine::MetadataObjs,
+            ) -> bool {
+                if ::goscript_engine::is_interface(meta, metas) {
+                    return true;
+                }
+                let names = [#(#go_names),*];
+                match ::goscript_engine::struct_field_types(meta, metas, &names) {
+                    Some(types) => true #(
+                        && <#types as ::goscript_engine::FromGos>::converts_from(
+                            types[#indices],
+                            metas,
+                        )
+                    )*,
+                    None => false,
+                }
+            }
+        }
+
+        impl ::goscript_engine::ToGos for #ident {
+            fn to_gos(
+                self,
+                ctx: &::goscript_engine::FfiCtx,
+                meta: ::goscript_engine::GosMetadata,
+            ) -> ::goscript_engine::FfiResult<::goscript_engine::GosValue> {
+                let names = [#(#go_names),*];
+                let metas = ctx.struct_fields(meta, &names, #rust_name)?;
+                let vals = vec![
+                    #(
+                        ::goscript_engine::ToGos::to_gos(self.#idents, ctx, metas[#indices])?
+                    ),*
+                ];
+                ctx.new_struct(meta, &names, vals)
+            }
+
+            fn converts_to(
+                meta: ::goscript_engine::GosMetadata,
+                metas: &::goscript_engine::MetadataObjs,
+            ) -> bool {
+                let names = [#(#go_names),*];
+                match ::goscript_engine::struct_field_types(meta, metas, &names) {
+                    Some(types) => true #(
+                        && <#types as ::goscript_engine::ToGos>::converts_to(
+                            types[#indices],
+                            metas,
+                        )
+                    )*,
+                    None => false,
+                }
+            }
+        }
+    })
+}
+
+fn named_fields_only(input: &DeriveInput) -> syn::Error {
+    syn::Error::new_spanned(
+        &input.ident,
+        "GoStruct can only be derived for structs with named fields",
+    )
+}
+
+// returns the name given by #[gos(rename = "...")]
+fn rename(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    let mut name = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("gos")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let s: LitStr = meta.value()?.parse()?;
+                name = Some(s.value());
+                Ok(())
+            } else {
+                Err(meta.error("unsupported gos attribute"))
+            }
+        })?;
+    }
+    Ok(name)
+}
@@ -0,0 +1,193 @@
+extern crate goscript_engine as engine;
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "usage: goscript <command> [flags] [arguments]
+
+commands:
+    run <file|dir> [args...]  compile and run the package, args are passed to it as os.Args
+    check <file|dir>          parse and type check the package, print the diagnostics
+    disasm <file|dir>         print the disassembly of the compiled package
+    version                   print the version
+
+flags:
+    --work-dir <dir>          the working directory, local imports are resolved against it
+    --base-path <dir>         where to find non-local imports, the std packages embedded
+                              in goscript are used if it's not set
+    --max-instructions <n>    stop the script after executing n instructions
+    --timeout <seconds>       stop the script after running for this long
+    --trace-parser            print debug info of the parser
+    --trace-checker           print debug info of the type checker
+    --trace-vm                print debug info of the vm
+";
+
+// exit codes, following the go command and the go runtime
+const EXIT_COMPILE_ERROR: i32 = 1;
+const EXIT_PANIC: i32 = 2;
+const EXIT_USAGE: i32 = 2;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    process::exit(run(args));
+}
+
+fn run(args: Vec<String>) -> i32 {
+    let (cmd, rest) = match args.split_first() {
+        Some((cmd, rest)) => (cmd.as_str(), rest),
+        None => return usage_error("no command given"),
+    };
+    match cmd {
+        "version" => {
+            println!(
+                "goscript {} (bytecode format {})",
+                env!("CARGO_PKG_VERSION"),
+                engine::BYTECODE_FORMAT_VERSION
+            );
+            0
+        }
+        "help" | "-h" | "--help" => {
+            print!("{}", USAGE);
+            0
+        }
+        "run" | "check" | "disasm" => {
+            let (config, rest) = match parse_flags(rest) {
+                Ok(x) => x,
+                Err(msg) => return usage_error(&msg),
+            };
+            let (path, script_args) = match rest.split_first() {
+                Some((p, a)) => (import_path(p), a),
+                None => return usage_error("no file or directory given"),
+            };
+            if cmd != "run" && !script_args.is_empty() {
+                return usage_error(&format!("unexpected argument: {}", script_args[0]));
+            }
+            // with trace_vm, the engine prints the compile errors itself
+            let quiet = config.trace_vm;
+            let mut eng = engine::Engine::new(config);
+            match cmd {
+                "run" => {
+                    let mut os_args = vec![rest[0].clone()];
+                    os_args.extend_from_slice(script_args);
+                    eng.set_args(os_args);
+                    exit_code(eng.run(&path).map(|o| o.exit_code), quiet)
+                }
+                "check" => exit_code(eng.check(&path).map(|_| 0), false),
+                _ => match eng.compile(&path) {
+                    Ok(program) => {
+                        let stdout = io::stdout();
+                        let mut w = stdout.lock();
+                        match program.disassemble(&mut w).and_then(|_| w.flush()) {
+                            Ok(_) => 0,
+                            // the reader has gone, like when piped to head
+                            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => 0,
+                            Err(e) => {
+                                eprintln!("goscript: {}", e);
+                                1
+                            }
+                        }
+                    }
+                    Err(e) => exit_code(Err(e), quiet),
+                },
+            }
+        }
+        _ => usage_error(&format!("unknown command: {}", cmd)),
+    }
+}
+
+// parses the flags in front of the first non-flag argument, returns the
+// config and the remaining arguments
+fn parse_flags(args: &[String]) -> Result<(engine::Config, &[String]), String> {
+    let mut config = engine::Config {
+        work_dir: None,
+        base_path: None,
+        trace_parser: false,
+        trace_checker: false,
+        trace_vm: false,
+        ..Default::default()
+    };
+    let mut i = 0;
+    while i < args.len() && args[i].starts_with("--") {
+        let value = |i: usize| {
+            args.get(i + 1)
+                .cloned()
+                .ok_or(format!("flag needs an argument: {}", args[i]))
+        };
+        match args[i].as_str() {
+            "--work-dir" => {
+                config.work_dir = Some(value(i)?);
+                i += 1;
+            }
+            "--base-path" => {
+                config.base_path = Some(value(i)?);
+                i += 1;
+            }
+            "--max-instructions" => {
+                let n = value(i)?;
+                let n = n
+                    .parse()
+                    .map_err(|_| format!("invalid instruction count: {}", n))?;
+                config.max_instructions = Some(n);
+                i += 1;
+            }
+            "--timeout" => {
+                let t = value(i)?;
+                let secs: f64 = t
+                    .parse()
+                    .ok()
+                    .filter(|s: &f64| s.is_finite() && *s >= 0.0)
+                    .ok_or(format!("invalid timeout: {}", t))?;
+                config.timeout = Some(Duration::from_secs_f64(secs));
+                i += 1;
+            }
+            "--trace-parser" => config.trace_parser = true,
+            "--trace-checker" => config.trace_checker = true,
+            "--trace-vm" => config.trace_vm = true,
+            "--" => {
+                i += 1;
+                break;
+            }
+            flag => return Err(format!("unknown flag: {}", flag)),
+        }
+        i += 1;
+    }
+    Ok((config, &args[i..]))
+}
+
+// the importer treats paths not starting with "./" or "../" as non-local
+// imports, which is not what the user means on the command line
+fn import_path(p: &str) -> String {
+    if p == "." || p == ".." || p.starts_with("./") || p.starts_with("../") {
+        p.to_string()
+    } else if Path::new(p).is_absolute() {
+        p.to_string()
+    } else {
+        format!("./{}", p)
+    }
+}
+
+fn exit_code(result: Result<i32, engine::EngineError>, quiet: bool) -> i32 {
+    match result {
+        Ok(code) => code,
+        Err(engine::EngineError::Compile(el)) => {
+            if !quiet {
+                eprint!("{}", el);
+            }
+            EXIT_COMPILE_ERROR
+        }
+        // the vm has printed the panic or the deadlock, and the call stacks
+        Err(engine::EngineError::Panic(_)) | Err(engine::EngineError::Deadlock(_)) => EXIT_PANIC,
+        Err(e) => {
+            eprintln!("goscript: {}", e);
+            EXIT_PANIC
+        }
+    }
+}
+
+fn usage_error(msg: &str) -> i32 {
+    eprintln!("goscript: {}\n", msg);
+    eprint!("{}", USAGE);
+    EXIT_USAGE
+}
@@ -0,0 +1,111 @@
+extern crate goscript_types as types;
+use std::io;
+use std::path::{Path, PathBuf};
+use types::{MemReader, SourceRead};
+
+/// the source of the std packages, compiled into the binary so that scripts
+/// can be run without a std directory next to the executable
+const STD_SOURCES: &[(&str, &str)] = &[
+    (
+        "errors/errors.gos",
+        include_str!("../std/errors/errors.gos"),
+    ),
+    ("fmt/fmt.gos", include_str!("../std/fmt/fmt.gos")),
+    ("math/math.gos", include_str!("../std/math/math.gos")),
+    ("math/math2.gos", include_str!("../std/math/math2.gos")),
+    ("os/os.gos", include_str!("../std/os/os.gos")),
+    ("sync/sync.gos", include_str!("../std/sync/sync.gos")),
+    (
+        "sync/atomic/atomic.gos",
+        include_str!("../std/sync/atomic/atomic.gos"),
+    ),
+    ("time/format.gos", include_str!("../std/time/format.gos")),
+    ("time/sleep.gos", include_str!("../std/time/sleep.gos")),
+    ("time/time.gos", include_str!("../std/time/time.gos")),
+    (
+        "unicode/casetables.go",
+        include_str!("../std/unicode/casetables.go"),
+    ),
+    ("unicode/digit.go", include_str!("../std/unicode/digit.go")),
+    (
+        "unicode/graphic.go",
+        include_str!("../std/unicode/graphic.go"),
+    ),
+    (
+        "unicode/letter.go",
+        include_str!("../std/unicode/letter.go"),
+    ),
+    (
+        "unicode/tables.go",
+        include_str!("../std/unicode/tables.go"),
+    ),
+    (
+        "unicode/utf16/utf16.go",
+        include_str!("../std/unicode/utf16/utf16.go"),
+    ),
+    (
+        "unicode/utf8/utf8.go",
+        include_str!("../std/unicode/utf8/utf8.go"),
+    ),
+];
+
+/// add_std_sources adds the embedded std packages to the reader, under dir
+pub fn add_std_sources(reader: &mut MemReader, dir: &str) {
+    for (path, content) in STD_SOURCES.iter() {
+        reader.add_file(&format!("{}/{}", dir, path), content.to_string());
+    }
+}
+
+/// StdOverlay serves the embedded std packages under root, and everything
+/// else from inner
+pub struct StdOverlay<'a> {
+    inner: &'a dyn SourceRead,
+    root: PathBuf,
+    std: MemReader,
+}
+
+impl<'a> StdOverlay<'a> {
+    pub fn new(inner: &'a dyn SourceRead, root: &str) -> StdOverlay<'a> {
+        let mut std = MemReader::new();
+        add_std_sources(&mut std, root);
+        StdOverlay {
+            inner: inner,
+            root: PathBuf::from(root),
+            std: std,
+        }
+    }
+
+    fn reader(&self, path: &Path) -> &dyn SourceRead {
+        if path.starts_with(&self.root) {
+            &self.std
+        } else {
+            self.inner
+        }
+    }
+}
+
+impl<'a> SourceRead for StdOverlay<'a> {
+    fn working_dir(&self) -> io::Result<PathBuf> {
+        self.inner.working_dir()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.reader(path).is_file(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.reader(path).is_dir(path)
+    }
+
+    fn canonicalize_path(&self, path: &Path) -> io::Result<PathBuf> {
+        self.reader(path).canonicalize_path(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.reader(path).read_dir(path)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        self.reader(path).read_file(path)
+    }
+}
@@ -2,8 +2,36 @@ extern crate goscript_codegen as cg;
 extern crate goscript_parser as fe;
 extern crate goscript_types as types;
 extern crate goscript_vm as vm;
-use super::std::fmt;
+use super::embed;
+use super::host::PackageDecl;
+pub use super::host::{GoConst, HostPackage};
+use super::std::{fmt, math, os, sync, time};
+pub use goscript_derive::GoStruct;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt as sfmt;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+pub use types::{FsReader, MemReader, SourceRead};
+pub use vm::ffi::{
+    is_interface, struct_field_types, struct_from_gos, AsyncFfiFn, Chan, Ffi, FfiCtx, FfiFn,
+    FfiFuture, FfiResult, FromGos, GoStruct, GoType, ToGos, ToGosResults,
+};
+pub use vm::metadata::GosMetadata;
+pub use vm::objects::MetadataObjs;
+pub use vm::serialize::FORMAT_VERSION as BYTECODE_FORMAT_VERSION;
+pub use vm::value::GosValue;
+pub use vm::vm::{Frame, Goroutine, InterruptHandle};
 
+// where run_source finds the embedded std packages
+const EMBEDDED_STD_DIR: &str = "std";
+// where compile finds the embedded std packages when base_path is not set,
+// it's not supposed to exist in the file system
+const EMBEDDED_STD_ROOT: &str = "/goscript-embedded/std";
+
+#[derive(Default)]
 pub struct Config {
     // working directory
     pub work_dir: Option<String>,
@@ -15,43 +43,521 @@ pub struct Config {
     pub trace_checker: bool,
     // proint debug info for vm
     pub trace_vm: bool,
+    // stop the script after executing this many instructions in total, in
+    // every run or call
+    pub max_instructions: Option<u64>,
+    // stop the script when any goroutine executes this many instructions, in
+    // every run or call
+    pub max_instructions_per_goroutine: Option<u64>,
+    // stop the script when it has run for this long, in every run or call
+    pub timeout: Option<Duration>,
+    // where fmt.Println and friends write, the stdout of the process if None,
+    // for every Script unless it's given its own with Script::set_stdout
+    pub stdout: Option<Box<dyn io::Write>>,
+    // where the print and println builtins write, and unrecovered panics are
+    // reported, the stderr of the process if None, see Script::set_stderr
+    pub stderr: Option<Box<dyn io::Write>>,
+}
+
+/// RunOutcome is returned by Engine::run when the script ran to completion.
+#[derive(Debug)]
+pub struct RunOutcome {
+    // 0 when main returns, or the code given to os.Exit
+    pub exit_code: i32,
+}
+
+/// EngineError tells the host why a script did not run to completion.
+#[derive(Debug)]
+pub enum EngineError {
+    // parse or type check errors, nothing was executed
+    Compile(fe::errors::ErrorList),
+    // an unrecovered panic at runtime
+    Panic(vm::vm::PanicInfo),
+    // Script::call is given a function that doesn't exist, or wrong arguments
+    InvalidCall(String),
+    // Script::get_global or Script::set_global is given a package member that
+    // doesn't exist, or a value of the wrong type
+    InvalidGlobal(String),
+    // the host sends on a closed channel, or a value that doesn't match the
+    // channel, or receives one it can't convert
+    Channel(String),
+    // the instruction budget set in Config is used up, with where the script
+    // was stopped
+    BudgetExhausted(vm::vm::StopInfo),
+    // stopped by an InterruptHandle or the timeout in Config, with where the
+    // script was stopped
+    Interrupted(vm::vm::StopInfo),
+    // a Rust panic, in goscript or the FFI code, stopped the script
+    Internal(String),
+    // Script::call ended with os.Exit, with the exit code
+    Exit(i32),
+    // all the goroutines are blocked for good, with where they are
+    Deadlock(Vec<vm::vm::Goroutine>),
+    // functions declared without a body are not registered with
+    // register_native, with their names, nothing was executed
+    MissingNatives(Vec<String>),
+    // the Go signatures of functions declared without a body don't match
+    // the Rust functions registered for them, with why, nothing was executed
+    MismatchedNatives(Vec<String>),
+}
+
+impl From<vm::vm::RuntimeError> for EngineError {
+    fn from(e: vm::vm::RuntimeError) -> EngineError {
+        match e {
+            vm::vm::RuntimeError::InvalidCall(msg) => EngineError::InvalidCall(msg),
+            vm::vm::RuntimeError::InvalidGlobal(msg) => EngineError::InvalidGlobal(msg),
+            vm::vm::RuntimeError::Channel(msg) => EngineError::Channel(msg),
+            vm::vm::RuntimeError::Panic(p) => EngineError::Panic(p),
+            vm::vm::RuntimeError::BudgetExhausted(s) => EngineError::BudgetExhausted(s),
+            vm::vm::RuntimeError::Interrupted(s) => EngineError::Interrupted(s),
+            vm::vm::RuntimeError::Internal(msg) => EngineError::Internal(msg),
+            vm::vm::RuntimeError::Exit(code) => EngineError::Exit(code),
+            vm::vm::RuntimeError::Deadlock(f) => EngineError::Deadlock(f),
+            vm::vm::RuntimeError::MissingNatives(names) => EngineError::MissingNatives(names),
+        }
+    }
+}
+
+impl sfmt::Display for EngineError {
+    fn fmt(&self, f: &mut sfmt::Formatter) -> sfmt::Result {
+        match self {
+            EngineError::Compile(el) => write!(f, "{}", el),
+            EngineError::Panic(p) => write!(f, "{}", p),
+            EngineError::InvalidCall(msg) => write!(f, "invalid call: {}", msg),
+            EngineError::InvalidGlobal(msg) => write!(f, "invalid global: {}", msg),
+            EngineError::Channel(msg) => write!(f, "channel: {}", msg),
+            EngineError::BudgetExhausted(s) => {
+                writeln!(f, "instruction budget exhausted\n")?;
+                write!(f, "{}", s)
+            }
+            EngineError::Interrupted(s) => {
+                writeln!(f, "interrupted\n")?;
+                write!(f, "{}", s)
+            }
+            EngineError::Internal(msg) => write!(f, "internal error: {}", msg),
+            EngineError::Exit(code) => write!(f, "exit status {}", code),
+            EngineError::Deadlock(fibers) => {
+                writeln!(f, "fatal error: all goroutines are asleep - deadlock!")?;
+                for g in fibers.iter() {
+                    write!(f, "\n{}", g)?;
+                }
+                Ok(())
+            }
+            EngineError::MissingNatives(names) => {
+                write!(f, "native functions not registered: {}", names.join(", "))
+            }
+            EngineError::MismatchedNatives(errs) => {
+                write!(f, "native functions not matching: {}", errs.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+pub type RunResult = Result<RunOutcome, EngineError>;
+
+/// Program is the result of compiling a package and its imports. It's never
+/// modified by running it, so that it can be compiled once and then
+/// instantiated as many times as needed, see Engine::instantiate.
+pub struct Program {
+    code: vm::vm::ByteCode,
+    // debug info, None if the program is loaded without it
+    fs: Option<fe::FileSet>,
+}
+
+impl Program {
+    /// save writes the program to w in a versioned binary format, so that it
+    /// can be loaded and run later without the source code. When debug_info
+    /// is false, runtime panics won't be able to report source positions.
+    pub fn save(&self, w: &mut dyn io::Write, debug_info: bool) -> io::Result<()> {
+        let fs = if debug_info { self.fs.as_ref() } else { None };
+        vm::serialize::write_bytecode(&self.code, fs, w)
+    }
+
+    /// load reads a program written by save. It fails with
+    /// io::ErrorKind::InvalidData if the data is malformed or is written by
+    /// an incompatible version of the format.
+    pub fn load(r: &mut dyn io::Read) -> io::Result<Program> {
+        let (code, fs) = vm::serialize::read_bytecode(r)?;
+        Ok(Program { code: code, fs: fs })
+    }
+
+    /// disassemble writes the disassembly of the program to w, with the source
+    /// lines read from the file system interleaved.
+    pub fn disassemble(&self, w: &mut dyn io::Write) -> io::Result<()> {
+        self.disassemble_with_reader(&FsReader::new(), w)
+    }
+
+    /// disassemble_with_reader is like disassemble, but the source lines are
+    /// read through reader.
+    pub fn disassemble_with_reader(
+        &self,
+        reader: &dyn SourceRead,
+        w: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        let source = |name: &str| reader.read_file(Path::new(name)).ok();
+        vm::disasm::disassemble(&self.code, self.fs.as_ref(), Some(&source), w)
+    }
+}
+
+/// Script is an instance of a Program, with its own package level state.
+/// It can be run as a whole, or used as a library by calling its functions,
+/// the package level state persists across calls.
+pub struct Script<'a> {
+    vm: vm::vm::GosVM<'a>,
+}
+
+impl<'a> Script<'a> {
+    /// run runs the main function of the main package.
+    pub fn run(&self) -> RunResult {
+        let exit_code = self.vm.run()?;
+        Ok(RunOutcome {
+            exit_code: exit_code,
+        })
+    }
+
+    /// run_async is the async version of run, to be run by a single-threaded
+    /// executor of the host, which then runs the futures of the async FFI
+    /// calls along with its other tasks. Only then the host can use the
+    /// channels it shares with the script while it runs, under run the
+    /// goroutines waiting for them may be deadlocked.
+    pub async fn run_async(&self) -> RunResult {
+        let exit_code = self.vm.run_async().await?;
+        Ok(RunOutcome {
+            exit_code: exit_code,
+        })
+    }
+
+    /// call calls the package level function func of the package named pkg,
+    /// with args, and returns its results.
+    pub fn call(
+        &self,
+        pkg: &str,
+        func: &str,
+        args: Vec<GosValue>,
+    ) -> Result<Vec<GosValue>, EngineError> {
+        Ok(self.vm.call(pkg, func, args)?)
+    }
+
+    /// call_async is the async version of call, see run_async.
+    pub async fn call_async(
+        &self,
+        pkg: &str,
+        func: &str,
+        args: Vec<GosValue>,
+    ) -> Result<Vec<GosValue>, EngineError> {
+        Ok(self.vm.call_async(pkg, func, args).await?)
+    }
+
+    /// call_closure calls a Go func value with args and returns its results,
+    /// it's how a func value kept by an FFI function is called back later.
+    pub fn call_closure(
+        &self,
+        cls: &GosValue,
+        args: Vec<GosValue>,
+    ) -> Result<Vec<GosValue>, EngineError> {
+        Ok(self.vm.call_closure(cls, args)?)
+    }
+
+    /// get_global returns the package level variable name of the package
+    /// named pkg, like `script.get_global::<i64>("main", "Threshold")`.
+    pub fn get_global<T: FromGos>(&self, pkg: &str, name: &str) -> Result<T, EngineError> {
+        Ok(self.vm.get_global(pkg, name)?)
+    }
+
+    /// set_global sets the package level variable name of the package named
+    /// pkg, like `script.set_global("config", "Debug", true)`, val must
+    /// convert to the type of the variable. It can be called before run, the
+    /// initializers of the package are run first.
+    pub fn set_global<T: ToGos>(&self, pkg: &str, name: &str, val: T) -> Result<(), EngineError> {
+        Ok(self.vm.set_global(pkg, name, val)?)
+    }
+
+    /// try_send sends val to the channel ch without blocking, it returns false
+    /// if the channel is full, see vm::vm::GosVM::try_send.
+    pub fn try_send<T: ToGos>(&self, ch: &Chan, val: T) -> Result<bool, EngineError> {
+        Ok(self.vm.try_send(ch, val)?)
+    }
+
+    /// send sends val to the channel ch, waiting until there is room for it.
+    /// It's to be awaited along with run_async on the same executor, so that
+    /// the script can receive the value meanwhile.
+    pub async fn send<T: ToGos>(&self, ch: &Chan, val: T) -> Result<(), EngineError> {
+        Ok(self.vm.send(ch, val).await?)
+    }
+
+    /// try_recv receives a value from the channel ch without blocking, None if
+    /// there is none.
+    pub fn try_recv<T: FromGos>(&self, ch: &Chan) -> Result<Option<T>, EngineError> {
+        Ok(self.vm.try_recv(ch)?)
+    }
+
+    /// recv receives a value from the channel ch, waiting until there is one,
+    /// it returns None when ch is closed. Like send, it's to be awaited along
+    /// with run_async.
+    pub async fn recv<T: FromGos>(&self, ch: &Chan) -> Result<Option<T>, EngineError> {
+        Ok(self.vm.recv(ch).await?)
+    }
+
+    /// interrupt_handle returns a handle that can stop the running script from
+    /// another thread, see vm::vm::InterruptHandle.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.vm.interrupt_handle()
+    }
+
+    /// set_stdout sets where fmt.Println and friends write for this Script
+    /// only, instead of Config::stdout.
+    pub fn set_stdout(&mut self, w: Box<dyn io::Write>) {
+        self.vm.set_stdout(Rc::new(RefCell::new(w)));
+    }
+
+    /// set_stderr sets where the print builtins write and unrecovered panics
+    /// are reported for this Script only, instead of Config::stderr.
+    pub fn set_stderr(&mut self, w: Box<dyn io::Write>) {
+        self.vm.set_stderr(Rc::new(RefCell::new(w)));
+    }
 }
 
 pub struct Engine {
     config: Config,
     ffi: vm::ffi::FfiFactory,
+    stdout: Rc<RefCell<dyn io::Write>>,
+    stderr: Rc<RefCell<dyn io::Write>>,
+    // the packages declared with host_package, by import path
+    host_pkgs: HashMap<String, PackageDecl>,
 }
 
 impl Engine {
-    pub fn new(config: Config) -> Engine {
+    pub fn new(mut config: Config) -> Engine {
+        let stdout: Rc<RefCell<dyn io::Write>> = match config.stdout.take() {
+            Some(w) => Rc::new(RefCell::new(w)),
+            None => Rc::new(RefCell::new(io::stdout())),
+        };
+        let stderr: Rc<RefCell<dyn io::Write>> = match config.stderr.take() {
+            Some(w) => Rc::new(RefCell::new(w)),
+            None => Rc::new(RefCell::new(io::stderr())),
+        };
         let mut ffi = vm::ffi::FfiFactory::new();
-        ffi.register("fmt", Box::new(fmt::Fmt::new));
-        Engine {
+        ffi.register("fmt", Box::new(|_| fmt::Fmt::new()));
+        math::register(&mut ffi);
+        os::register(&mut ffi);
+        sync::register(&mut ffi);
+        let start = Instant::now();
+        time::register(&mut ffi, start);
+        let mut engine = Engine {
             config: config,
             ffi: ffi,
-        }
+            stdout: stdout,
+            stderr: stderr,
+            host_pkgs: HashMap::new(),
+        };
+        engine.set_args(vec![]);
+        engine
+    }
+
+    /// set_args sets the command-line arguments seen by the scripts as os.Args,
+    /// the first one is supposed to be the program name.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        let args = Rc::new(args);
+        self.ffi
+            .register("os", Box::new(move |_| os::Os::new(args.clone())));
+    }
+
+    /// register_fn adds f as the method func_name of the FFI object named name,
+    /// see vm::ffi::FfiFactory::register_fn.
+    pub fn register_fn<Args, F>(&mut self, name: &'static str, func_name: &str, f: F)
+    where
+        F: FfiFn<Args> + 'static,
+    {
+        self.ffi.register_fn(name, func_name, f);
+    }
+
+    /// register_async_fn is like register_fn, but f returns a future, which
+    /// only the goroutine calling it waits for.
+    pub fn register_async_fn<Args, F>(&mut self, name: &'static str, func_name: &str, f: F)
+    where
+        F: AsyncFfiFn<Args> + 'static,
+    {
+        self.ffi.register_async_fn(name, func_name, f);
+    }
+
+    /// register_native adds f as the implementation of the Go function
+    /// declared without a body, like `func Sqrt(x float64) float64`, name is
+    /// "pkgpath.FuncName", see vm::ffi::FfiFactory::register_native.
+    pub fn register_native<Args, F>(&mut self, name: &str, f: F)
+    where
+        F: FfiFn<Args> + 'static,
+    {
+        self.ffi.register_native(name, f);
+    }
+
+    /// register_async_native is like register_native, but f returns a future,
+    /// which only the goroutine calling it waits for.
+    pub fn register_async_native<Args, F>(&mut self, name: &str, f: F)
+    where
+        F: AsyncFfiFn<Args> + 'static,
+    {
+        self.ffi.register_async_native(name, f);
+    }
+
+    /// host_package returns the builder of the package with the import path
+    /// path, which is declared in Rust instead of being read from the source
+    /// files, it's extended if it's already declared.
+    pub fn host_package(&mut self, path: &str) -> HostPackage<'_> {
+        let decl = self.host_pkgs.entry(path.to_string()).or_default();
+        HostPackage::new(path, decl, &mut self.ffi)
+    }
+
+    /// run compiles and runs the package at path, reading the source code from
+    /// the file system.
+    pub fn run(&self, path: &str) -> RunResult {
+        self.instantiate(&self.compile(path)?).run()
+    }
+
+    /// run_source runs a single file script held in memory, its imports are
+    /// resolved against the std packages embedded in the engine. name is
+    /// used as the file name in error messages and debug info.
+    pub fn run_source(&self, name: &str, source: &str) -> RunResult {
+        self.instantiate(&self.compile_source(name, source)?).run()
+    }
+
+    /// run_with_reader is like run, but the source code of the package and
+    /// all its imports is read through reader.
+    pub fn run_with_reader(&self, path: &str, reader: &dyn SourceRead) -> RunResult {
+        self.instantiate(&self.compile_with_reader(path, reader)?)
+            .run()
+    }
+
+    /// compile compiles the package at path into a Program, without running it.
+    /// When Config::base_path is not set, the std packages embedded in the
+    /// engine are used.
+    pub fn compile(&self, path: &str) -> Result<Program, EngineError> {
+        self.with_fs_reader(|reader, config| self.compile_impl(path, config, reader))
+    }
+
+    /// check parses and type checks the package at path, like compile, but
+    /// without generating any code.
+    pub fn check(&self, path: &str) -> Result<(), EngineError> {
+        self.with_fs_reader(|reader, config| {
+            let el = &mut fe::errors::ErrorList::new();
+            let mut fs = fe::FileSet::new();
+            cg::entry::parse_check(path, config, reader, &mut fs, el).map_err(|_| {
+                el.sort();
+                EngineError::Compile(el.clone())
+            })
+        })
     }
 
-    pub fn run(&self, path: &str) -> usize {
+    /// compile_source is the compile version of run_source.
+    pub fn compile_source(&self, name: &str, source: &str) -> Result<Program, EngineError> {
+        let mut reader = MemReader::new();
+        reader.add_file(name, source.to_string());
+        embed::add_std_sources(&mut reader, EMBEDDED_STD_DIR);
         let config = types::Config {
-            work_dir: self.config.work_dir.clone(),
-            base_path: self.config.base_path.clone(),
+            work_dir: None,
+            base_path: Some(EMBEDDED_STD_DIR.to_string()),
             trace_parser: self.config.trace_parser,
             trace_checker: self.config.trace_checker,
+            host_pkgs: self.host_sources(),
         };
+        self.compile_impl(&format!("./{}", name), &config, &reader)
+    }
+
+    /// compile_with_reader is the compile version of run_with_reader.
+    pub fn compile_with_reader(
+        &self,
+        path: &str,
+        reader: &dyn SourceRead,
+    ) -> Result<Program, EngineError> {
+        let config = self.types_config(self.config.base_path.clone());
+        self.compile_impl(path, &config, reader)
+    }
+
+    /// instantiate creates a fresh Script from program, it's cheap compared to
+    /// compiling, as nothing is parsed, checked or generated again.
+    pub fn instantiate<'a>(&'a self, program: &'a Program) -> Script<'a> {
+        let mut vm = vm::vm::GosVM::new(&program.code, &self.ffi, program.fs.as_ref());
+        vm.set_limits(vm::vm::Limits {
+            max_instructions: self.config.max_instructions,
+            max_fiber_instructions: self.config.max_instructions_per_goroutine,
+            timeout: self.config.timeout,
+        });
+        vm.set_stdout(self.stdout.clone());
+        vm.set_stderr(self.stderr.clone());
+        Script { vm: vm }
+    }
+
+    /// add_std_sources adds the std packages embedded in the engine to reader,
+    /// under dir, for hosts that build their own MemReader.
+    pub fn add_std_sources(reader: &mut MemReader, dir: &str) {
+        embed::add_std_sources(reader, dir);
+    }
+
+    // calls f with a reader of the file system, which serves the embedded std
+    // packages if base_path is not set, and the config that goes with it
+    fn with_fs_reader<T>(&self, f: impl FnOnce(&dyn SourceRead, &types::Config) -> T) -> T {
+        let fs_reader = FsReader::new();
+        match &self.config.base_path {
+            Some(_) => f(
+                &fs_reader,
+                &self.types_config(self.config.base_path.clone()),
+            ),
+            None => {
+                let reader = embed::StdOverlay::new(&fs_reader, EMBEDDED_STD_ROOT);
+                f(
+                    &reader,
+                    &self.types_config(Some(EMBEDDED_STD_ROOT.to_string())),
+                )
+            }
+        }
+    }
+
+    fn types_config(&self, base_path: Option<String>) -> types::Config {
+        types::Config {
+            work_dir: self.config.work_dir.clone(),
+            base_path: base_path,
+            trace_parser: self.config.trace_parser,
+            trace_checker: self.config.trace_checker,
+            host_pkgs: self.host_sources(),
+        }
+    }
+
+    fn host_sources(&self) -> HashMap<String, String> {
+        self.host_pkgs
+            .iter()
+            .map(|(path, decl)| (path.clone(), decl.source(path)))
+            .collect()
+    }
+
+    fn compile_impl(
+        &self,
+        path: &str,
+        config: &types::Config,
+        reader: &dyn SourceRead,
+    ) -> Result<Program, EngineError> {
         let mut fs = fe::FileSet::new();
         let el = &mut fe::errors::ErrorList::new();
-        let code = cg::entry::parse_check_gen(path, &config, &mut fs, el);
+        let code = cg::entry::parse_check_gen(path, config, reader, &mut fs, el);
         if let Ok(bc) = code {
-            let vm = vm::vm::GosVM::new(bc, &self.ffi, Some(&fs));
-            vm.run();
-            0
+            let missing = bc.missing_natives(&self.ffi);
+            if !missing.is_empty() {
+                return Err(EngineError::MissingNatives(missing));
+            }
+            let mismatched = bc.mismatched_natives(&self.ffi);
+            if !mismatched.is_empty() {
+                return Err(EngineError::MismatchedNatives(mismatched));
+            }
+            Ok(Program {
+                code: bc,
+                fs: Some(fs),
+            })
         } else {
+            el.sort();
             if self.config.trace_vm {
-                el.sort();
                 print!("{}", el);
             }
-            code.unwrap_err()
+            Err(EngineError::Compile(el.clone()))
         }
     }
 
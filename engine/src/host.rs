@@ -0,0 +1,177 @@
+extern crate goscript_vm as vm;
+use vm::ffi::{AsyncFfiFn, FfiFactory, FfiFn, GoStruct, GoType};
+
+/// GoConst is implemented by the Rust types of the values of Go constants,
+/// go_literal writes the value the way it's written in Go source.
+pub trait GoConst: GoType {
+    fn go_literal(&self) -> Result<String, String>;
+}
+
+macro_rules! impl_go_const_int {
+    ($($t:ty),*) => {
+        $(
+            impl GoConst for $t {
+                fn go_literal(&self) -> Result<String, String> {
+                    Ok(self.to_string())
+                }
+            }
+        )*
+    };
+}
+
+impl_go_const_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! impl_go_const_float {
+    ($($t:ty),*) => {
+        $(
+            impl GoConst for $t {
+                // Debug writes the shortest digits that read back the same
+                // value, with an exponent or a decimal point
+                fn go_literal(&self) -> Result<String, String> {
+                    if self.is_finite() {
+                        Ok(format!("{:?}", self))
+                    } else {
+                        Err(format!("{} is not a Go constant", self))
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_go_const_float!(f32, f64);
+
+impl GoConst for bool {
+    fn go_literal(&self) -> Result<String, String> {
+        Ok(self.to_string())
+    }
+}
+
+impl GoConst for String {
+    fn go_literal(&self) -> Result<String, String> {
+        let mut lit = String::with_capacity(self.len() + 2);
+        lit.push('"');
+        for c in self.chars() {
+            match c {
+                '"' => lit.push_str("\\\""),
+                '\\' => lit.push_str("\\\\"),
+                '\n' => lit.push_str("\\n"),
+                '\r' => lit.push_str("\\r"),
+                '\t' => lit.push_str("\\t"),
+                c if c.is_control() => lit.push_str(&format!("\\u{:04x}", c as u32)),
+                c => lit.push(c),
+            }
+        }
+        lit.push('"');
+        Ok(lit)
+    }
+}
+
+/// PackageDecl is the Go source of a package provided by the host, it has
+/// declarations only, the functions are implemented in Rust.
+#[derive(Default)]
+pub struct PackageDecl {
+    imports: Vec<String>,
+    decls: String,
+}
+
+impl PackageDecl {
+    /// source returns the Go source of the package with the import path path,
+    /// its name is the last element of the path.
+    pub fn source(&self, path: &str) -> String {
+        let name = path.rsplit('/').next().unwrap();
+        let mut s = format!("package {}\n\n", name);
+        for i in self.imports.iter() {
+            s.push_str(&format!("import \"{}\"\n", i));
+        }
+        s.push('\n');
+        s.push_str(&self.decls);
+        s
+    }
+}
+
+/// HostPackage declares a package importable by scripts, which is not read
+/// from any source file, see Engine::host_package:
+///
+/// ```ignore
+/// engine
+///     .host_package("host/db")
+///     .constant("MaxConns", 16i64)
+///     .go_struct::<Row>()
+///     .function("Query", "(q string) ([]Row, error)", query);
+/// ```
+///
+/// Scripts see it as if it was written in Go, with full static typing.
+pub struct HostPackage<'a> {
+    path: String,
+    decl: &'a mut PackageDecl,
+    ffi: &'a mut FfiFactory,
+}
+
+impl<'a> HostPackage<'a> {
+    pub fn new(path: &str, decl: &'a mut PackageDecl, ffi: &'a mut FfiFactory) -> HostPackage<'a> {
+        HostPackage {
+            path: path.to_string(),
+            decl: decl,
+            ffi: ffi,
+        }
+    }
+
+    /// import imports the package path, for the types used in the other
+    /// declarations.
+    pub fn import(&mut self, path: &str) -> &mut Self {
+        self.decl.imports.push(path.to_string());
+        self
+    }
+
+    /// constant declares a constant of a basic type, it panics if val can't
+    /// be a Go constant, like NaN.
+    pub fn constant<T: GoConst>(&mut self, name: &str, val: T) -> &mut Self {
+        match val.go_literal() {
+            Ok(lit) => self.decl(&format!("const {} {} = {}", name, T::go_type(), lit)),
+            Err(e) => panic!("constant {}: {}", name, e),
+        }
+    }
+
+    /// variable declares a variable of the Go type of T, it starts with the
+    /// zero value, the host can change it with Script::set_global.
+    pub fn variable<T: GoType>(&mut self, name: &str) -> &mut Self {
+        self.decl(&format!("var {} {}", name, T::go_type()))
+    }
+
+    /// go_struct declares the Go struct type of T.
+    pub fn go_struct<T: GoStruct>(&mut self) -> &mut Self {
+        self.decl(&T::go_decl())
+    }
+
+    /// decl adds any other declaration written in Go, like an interface type.
+    pub fn decl(&mut self, src: &str) -> &mut Self {
+        self.decl.decls.push_str(src);
+        self.decl.decls.push('\n');
+        self
+    }
+
+    /// function declares the function name with the Go signature sig, like
+    /// "(a, b int) (int, error)", and implements it with f, whose arguments
+    /// and results are converted as those of FfiFactory::register_native.
+    /// Engine::compile checks sig against the arguments and results of f.
+    pub fn function<Args, F>(&mut self, name: &str, sig: &str, f: F) -> &mut Self
+    where
+        F: FfiFn<Args> + 'static,
+    {
+        self.ffi
+            .register_native(&format!("{}.{}", self.path, name), f);
+        self.decl(&format!("func {}{}", name, sig))
+    }
+
+    /// async_function is like function, but f returns a future, which only
+    /// the goroutine calling the function waits for.
+    pub fn async_function<Args, F>(&mut self, name: &str, sig: &str, f: F) -> &mut Self
+    where
+        F: AsyncFfiFn<Args> + 'static,
+    {
+        self.ffi
+            .register_async_native(&format!("{}.{}", self.path, name), f);
+        self.decl(&format!("func {}{}", name, sig))
+    }
+}
@@ -1,4 +1,6 @@
+mod embed;
 mod engine;
+mod host;
 mod std;
 
 pub use engine::*;
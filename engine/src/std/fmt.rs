@@ -1,4 +1,4 @@
-use goscript_vm::ffi::{Ffi, FfiResult};
+use goscript_vm::ffi::{Ffi, FfiCtx, FfiResult};
 use goscript_vm::value::GosValue;
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -6,22 +6,22 @@ use std::rc::Rc;
 pub struct Fmt {}
 
 impl Ffi for Fmt {
-    fn call(&self, func_name: &str, params: Vec<GosValue>) -> Vec<GosValue> {
-        match func_name {
-            "println" => self.println(params),
-            "printf" => self.printf(params),
-            _ => unreachable!(),
+    fn call(&self, ctx: &FfiCtx, params: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+        match ctx.func_name {
+            "println" => self.println(ctx, params),
+            "printf" => self.printf(params)?,
+            _ => return Err(format!("unknown function fmt.{}", ctx.func_name)),
         }
-        vec![]
+        Ok(vec![])
     }
 }
 
 impl Fmt {
-    pub fn new(_v: Vec<GosValue>) -> FfiResult<Rc<RefCell<dyn Ffi>>> {
+    pub fn new() -> FfiResult<Rc<RefCell<dyn Ffi>>> {
         Ok(Rc::new(RefCell::new(Fmt {})))
     }
 
-    fn println(&self, params: Vec<GosValue>) {
+    fn println(&self, ctx: &FfiCtx, params: Vec<GosValue>) {
         let vec = params[0].as_slice().0.get_vec();
         let strs: Vec<String> = vec
             .iter()
@@ -33,11 +33,11 @@ impl Fmt {
                 }
             })
             .collect();
-        println!("{}", strs.join(", "));
+        let _ = writeln!(ctx.stdout().borrow_mut(), "{}", strs.join(", "));
     }
 
-    fn printf(&self, params: Vec<GosValue>) {
+    fn printf(&self, params: Vec<GosValue>) -> FfiResult<()> {
         let _vec = params[0].as_slice().0.get_vec();
-        unimplemented!();
+        Err("fmt.Printf is not implemented yet".to_string())
     }
 }
@@ -0,0 +1,8 @@
+use goscript_vm::ffi::FfiFactory;
+
+/// register adds the functions of the math package implemented in Rust.
+pub fn register(ffi: &mut FfiFactory) {
+    ffi.register_native("math.Sqrt", f64::sqrt);
+    ffi.register_native("math.Floor", f64::floor);
+    ffi.register_native("math.Pow", f64::powf);
+}
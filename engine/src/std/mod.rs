@@ -1 +1,5 @@
 pub mod fmt;
+pub mod math;
+pub mod os;
+pub mod sync;
+pub mod time;
@@ -0,0 +1,32 @@
+use goscript_vm::ffi::{Ffi, FfiCtx, FfiFactory, FfiResult};
+use goscript_vm::value::GosValue;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct Os {
+    args: Rc<Vec<String>>,
+}
+
+impl Ffi for Os {
+    fn call(&self, ctx: &FfiCtx, params: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+        match ctx.func_name {
+            "argCount" => Ok(vec![GosValue::Int(self.args.len() as isize)]),
+            "arg" => {
+                let i = *params[0].as_int() as usize;
+                Ok(vec![GosValue::new_str(self.args[i].clone())])
+            }
+            _ => Err(format!("unknown function os.{}", ctx.func_name)),
+        }
+    }
+}
+
+/// register adds the functions of the os package implemented in Rust.
+pub fn register(ffi: &mut FfiFactory) {
+    ffi.register_native("os.exit", |ctx: &FfiCtx, code: isize| ctx.exit(code as i32));
+}
+
+impl Os {
+    pub fn new(args: Rc<Vec<String>>) -> FfiResult<Rc<RefCell<dyn Ffi>>> {
+        Ok(Rc::new(RefCell::new(Os { args: args })))
+    }
+}
@@ -0,0 +1,58 @@
+use goscript_vm::ffi::{FfiCtx, FfiFactory, FromGos};
+use goscript_vm::value::GosValue;
+
+macro_rules! register_atomic {
+    ($ffi:ident, $($name:literal: $t:ty => $v:ident),*) => {
+        $(
+            $ffi.register_native(
+                concat!("sync/atomic.Swap", $name),
+                |ctx: &FfiCtx, addr: GosValue, new: $t| {
+                    let old = <$t>::from_gos(ctx.load(&addr)?)?;
+                    ctx.store(&addr, GosValue::$v(new))?;
+                    Ok::<$t, String>(old)
+                },
+            );
+            $ffi.register_native(
+                concat!("sync/atomic.CompareAndSwap", $name),
+                |ctx: &FfiCtx, addr: GosValue, old: $t, new: $t| {
+                    if <$t>::from_gos(ctx.load(&addr)?)? != old {
+                        return Ok(false);
+                    }
+                    ctx.store(&addr, GosValue::$v(new))?;
+                    Ok::<bool, String>(true)
+                },
+            );
+            $ffi.register_native(
+                concat!("sync/atomic.Add", $name),
+                |ctx: &FfiCtx, addr: GosValue, delta: $t| {
+                    let new = <$t>::from_gos(ctx.load(&addr)?)?.wrapping_add(delta);
+                    ctx.store(&addr, GosValue::$v(new))?;
+                    Ok::<$t, String>(new)
+                },
+            );
+            $ffi.register_native(
+                concat!("sync/atomic.Load", $name),
+                |ctx: &FfiCtx, addr: GosValue| <$t>::from_gos(ctx.load(&addr)?),
+            );
+            $ffi.register_native(
+                concat!("sync/atomic.Store", $name),
+                |ctx: &FfiCtx, addr: GosValue, val: $t| ctx.store(&addr, GosValue::$v(val)),
+            );
+        )*
+    };
+}
+
+/// register adds the functions of the sync and sync/atomic packages
+/// implemented in Rust. Nothing else runs during a native call, so the
+/// atomic operations are just loads and stores.
+pub fn register(ffi: &mut FfiFactory) {
+    ffi.register_native("sync.lockState", |ctx: &FfiCtx| ctx.begin_atomic());
+    ffi.register_native("sync.unlockState", |ctx: &FfiCtx| ctx.end_atomic());
+    register_atomic!(ffi,
+        "Int32": i32 => Int32,
+        "Int64": i64 => Int64,
+        "Uint32": u32 => Uint32,
+        "Uint64": u64 => Uint64,
+        "Uintptr": usize => Uint
+    );
+}
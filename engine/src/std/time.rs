@@ -0,0 +1,33 @@
+use goscript_vm::ffi::{FfiCtx, FfiFactory};
+use goscript_vm::value::GosValue;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// register adds the functions of the time package implemented in Rust, the
+/// monotonic clock reads the time since start.
+pub fn register(ffi: &mut FfiFactory, start: Instant) {
+    // the readings start from 1, as 0 means there is none
+    let monotonic = move || start.elapsed().as_nanos() as i64 + 1;
+    ffi.register_native("time.now", move || {
+        let d = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?;
+        Ok::<_, String>((d.as_secs() as i64, d.subsec_nanos() as i64, monotonic()))
+    });
+    ffi.register_native("time.monotonic", monotonic);
+    // the deadline is a reading of the monotonic clock
+    ffi.register_async_native("time.sleepUntil", move |ctx: &FfiCtx, mono: i64| {
+        ctx.sleep_until(start + Duration::from_nanos(mono.max(0) as u64))
+    });
+    ffi.register_native(
+        "time.startTimer",
+        move |ctx: &FfiCtx, mono: i64, period: i64, f: GosValue| {
+            let deadline = start + Duration::from_nanos(mono.max(0) as u64);
+            let period = match period > 0 {
+                true => Some(Duration::from_nanos(period as u64)),
+                false => None,
+            };
+            ctx.start_timer(deadline, period, f)
+        },
+    );
+    ffi.register_native("time.stopTimer", |ctx: &FfiCtx, id: u64| ctx.stop_timer(id));
+}
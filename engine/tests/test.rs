@@ -1,17 +1,46 @@
 #[macro_use]
 extern crate time_test;
 extern crate goscript_engine as engine;
+use engine::{Ffi, FfiCtx, FfiResult, FromGos, GoConst, GoStruct, GosValue, ToGos};
+use goscript_vm::instruction::{Instruction, Opcode, ValueType};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
 
-fn run(path: &str, trace: bool) -> usize {
-    let cfg = engine::Config {
+// the config of the engines of the tests, which read the std packages from
+// the source tree
+fn test_config() -> engine::Config {
+    engine::Config {
         work_dir: Some("./".to_string()),
         base_path: Some("./std/".to_string()),
+        ..Default::default()
+    }
+}
+
+fn test_engine() -> engine::Engine {
+    engine::Engine::new(test_config())
+}
+
+fn run(path: &str, trace: bool) -> usize {
+    let cfg = engine::Config {
         trace_parser: trace,
         trace_checker: trace,
         trace_vm: true,
+        ..test_config()
     };
     let engine = engine::Engine::new(cfg);
-    engine.run(path)
+    match engine.run(path) {
+        Ok(_) => 0,
+        Err(engine::EngineError::Compile(el)) => el.len(),
+        Err(_) => 1,
+    }
+}
+
+fn run_result(path: &str) -> engine::RunResult {
+    test_engine().run(path)
 }
 
 #[test]
@@ -50,6 +79,332 @@ fn test_g2display() {
     assert!(err_cnt == 0);
 }
 
+#[test]
+fn test_g2panic() {
+    match run_result("./tests/group2/panic.gos") {
+        Err(engine::EngineError::Panic(p)) => {
+            assert_eq!(p.msg, "index 3 out of range");
+            assert_eq!(p.goroutine.id, 1);
+            // the generated entry function is left out
+            let frames: Vec<(&str, usize)> = p
+                .goroutine
+                .call_stack
+                .iter()
+                .map(|x| (x.func.as_str(), x.pos.as_ref().unwrap().line))
+                .collect();
+            assert_eq!(
+                frames,
+                vec![("main.get", 5), ("main.outer", 9), ("main.main", 13)]
+            );
+        }
+        _ => panic!("expected a runtime panic"),
+    }
+}
+
+#[test]
+fn test_g2compile_error() {
+    match run_result("./tests/group2/compile_error.gos") {
+        Err(engine::EngineError::Compile(el)) => {
+            assert_eq!(el.len(), 1);
+            assert_eq!(el.borrow()[0].pos.line, 4);
+        }
+        _ => panic!("expected a compile error"),
+    }
+}
+
+fn new_engine() -> engine::Engine {
+    engine::Engine::new(engine::Config {
+        work_dir: None,
+        base_path: None,
+        trace_parser: false,
+        trace_checker: false,
+        trace_vm: false,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_run_source() {
+    let src = r#"
+package main
+
+import (
+    "fmt"
+    "math"
+)
+
+func main() {
+    assert(math.Plus4(1) == 5)
+    fmt.Println("hello from memory")
+}
+"#;
+    assert!(new_engine().run_source("hello.gos", src).is_ok());
+
+    let src = "package main\n\nfunc main() {\n    var a int = \"not an int\"\n}\n";
+    match new_engine().run_source("bad.gos", src) {
+        Err(engine::EngineError::Compile(el)) => {
+            let pos = &el.borrow()[0].pos;
+            assert_eq!((pos.filename.as_str(), pos.line), ("bad.gos", 4));
+        }
+        _ => panic!("expected a compile error"),
+    }
+}
+
+#[test]
+fn test_g2call() {
+    let eng = test_engine();
+    let program = eng.compile("./tests/group2/call.gos").unwrap();
+    let script = eng.instantiate(&program);
+    let int = |i| engine::GosValue::Int(i);
+
+    let ret = script.call("main", "Add", vec![int(1), int(2)]).unwrap();
+    assert_eq!(*ret[0].as_int(), 3);
+    script.call("main", "Add", vec![int(3), int(4)]).unwrap();
+    let ret = script.call("main", "Count", vec![]).unwrap();
+    assert_eq!(*ret[0].as_int(), 2);
+
+    let name = engine::GosValue::new_str("gopher".to_string());
+    let ret = script.call("main", "Greet", vec![name]).unwrap();
+    assert_eq!(ret[0].as_str().as_str(), "hello gopher");
+    assert!(*ret[1].as_bool());
+
+    let msg = engine::GosValue::new_str("boom".to_string());
+    match script.call("main", "Fail", vec![msg]) {
+        Err(engine::EngineError::Panic(p)) => assert_eq!(p.msg, "boom"),
+        _ => panic!("expected a panic"),
+    }
+    match script.call("main", "Nope", vec![]) {
+        Err(engine::EngineError::InvalidCall(_)) => {}
+        _ => panic!("expected an invalid call"),
+    }
+    match script.call("main", "Add", vec![int(1)]) {
+        Err(engine::EngineError::InvalidCall(_)) => {}
+        _ => panic!("expected an invalid call"),
+    }
+
+    // arguments are checked against the types of the parameters, not only
+    // their kinds
+    let p = script
+        .call("main", "NewPoint", vec![int(1), int(2)])
+        .unwrap();
+    let ret = script.call("main", "Norm1", p).unwrap();
+    assert_eq!(*ret[0].as_int(), 3);
+    let size = script.call("main", "NewSize", vec![]).unwrap();
+    match script.call("main", "Norm1", size) {
+        Err(engine::EngineError::InvalidCall(_)) => {}
+        _ => panic!("expected an invalid call"),
+    }
+
+    // a new instance starts with fresh package level state
+    let script = eng.instantiate(&program);
+    let ret = script.call("main", "Count", vec![]).unwrap();
+    assert_eq!(*ret[0].as_int(), 0);
+    script.call("main", "Add", vec![int(1), int(2)]).unwrap();
+    let ret = script.call("main", "Count", vec![]).unwrap();
+    assert_eq!(*ret[0].as_int(), 1);
+    assert!(script.run().is_ok());
+}
+
+fn save_load(program: &engine::Program, debug_info: bool) -> engine::Program {
+    let mut buf = vec![];
+    program.save(&mut buf, debug_info).unwrap();
+    engine::Program::load(&mut buf.as_slice()).unwrap()
+}
+
+#[test]
+fn test_g2serialize() {
+    let eng = test_engine();
+    for path in [
+        "./tests/group1/closure4.gos",
+        "./tests/group1/composite.gos",
+        "./tests/group1/interface.gos",
+        "./tests/group1/map1.gos",
+        "./tests/group1/typeswitch.gos",
+        "./tests/group2/unicode0.gos",
+    ]
+    .iter()
+    {
+        let program = save_load(&eng.compile(path).unwrap(), false);
+        assert!(eng.instantiate(&program).run().is_ok(), "{}", path);
+    }
+
+    let program = save_load(&eng.compile("./tests/group2/call.gos").unwrap(), true);
+    let script = eng.instantiate(&program);
+    let int = |i| engine::GosValue::Int(i);
+    let ret = script.call("main", "Add", vec![int(1), int(2)]).unwrap();
+    assert_eq!(*ret[0].as_int(), 3);
+
+    let program = eng.compile("./tests/group2/panic.gos").unwrap();
+    let stack = |program: &engine::Program| match eng.instantiate(program).run() {
+        Err(engine::EngineError::Panic(p)) => p
+            .goroutine
+            .call_stack
+            .iter()
+            .map(|x| format!("{} {:?}", x.func, x.pos.as_ref().map(|p| p.line)))
+            .collect::<Vec<String>>(),
+        _ => panic!("expected a runtime panic"),
+    };
+    assert_eq!(
+        stack(&save_load(&program, true)),
+        vec![
+            "main.get Some(5)",
+            "main.outer Some(9)",
+            "main.main Some(13)"
+        ]
+    );
+    // the function names are kept without the debug info
+    assert_eq!(
+        stack(&save_load(&program, false)),
+        vec!["main.get None", "main.outer None", "main.main None"]
+    );
+
+    match engine::Program::load(&mut &b"not bytecode"[..]) {
+        Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+        Ok(_) => panic!("expected an error"),
+    }
+
+    // an instruction with an unknown opcode is rejected when loaded
+    let program = eng
+        .compile_source("empty.gos", "package main\n\nfunc main() {}\n")
+        .unwrap();
+    let mut buf = vec![];
+    program.save(&mut buf, false).unwrap();
+    let ret = Instruction::new(Opcode::RETURN, Some(ValueType::Zero), None, None, None);
+    let word = ret.get_u64().to_le_bytes();
+    let mut rejected = 0;
+    for at in 0..buf.len() - 7 {
+        if buf[at..at + 8] != word {
+            continue;
+        }
+        let mut bad = buf.clone();
+        bad[at + 7] = 0xff;
+        match engine::Program::load(&mut bad.as_slice()) {
+            Err(e) if e.to_string().starts_with("invalid instruction") => rejected += 1,
+            Err(_) => {}
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+    assert!(rejected > 0);
+}
+
+#[test]
+fn test_g2disasm() {
+    let program = test_engine().compile("./tests/group2/call.gos").unwrap();
+    let mut buf = vec![];
+    program.disassemble(&mut buf).unwrap();
+    let text = String::from_utf8(buf).unwrap();
+    assert!(text.contains("func main.Add func(int, int) int"));
+    assert!(text.contains("func main.init"));
+    assert!(text.contains("func entry"));
+    assert!(text.contains("call.gos:8    count++"));
+    assert!(text.contains("(main.count)"));
+    assert!(text.contains("string \"hello \""));
+    assert!(text.contains("RANGE_END"));
+    assert!(!text.contains(".word"));
+}
+
+#[test]
+fn test_run_with_reader() {
+    let mut reader = engine::MemReader::new();
+    reader.add_file(
+        "app/main.gos",
+        "package main\n\nimport \"./util\"\n\nfunc main() {\n    assert(util.Twice(2) == 4)\n}\n"
+            .to_string(),
+    );
+    reader.add_file(
+        "app/util/util.gos",
+        "package util\n\nfunc Twice(i int) int {\n    return i * 2\n}\n".to_string(),
+    );
+    engine::Engine::add_std_sources(&mut reader, "std");
+    assert!(new_engine()
+        .run_with_reader("./app/main.gos", &reader)
+        .is_ok());
+}
+
+#[test]
+fn test_g2args() {
+    // the std packages embedded in the engine are used without base_path
+    let mut eng = new_engine();
+    eng.set_args(vec![
+        "args".to_string(),
+        "hello".to_string(),
+        "world".to_string(),
+    ]);
+    assert!(eng.run("./tests/group2/args.gos").is_ok());
+    assert!(eng.check("./tests/group2/args.gos").is_ok());
+    match eng.check("./tests/group2/compile_error.gos") {
+        Err(engine::EngineError::Compile(el)) => assert_eq!(el.len(), 1),
+        _ => panic!("expected a compile error"),
+    }
+    // a missing entry package is reported like any other compile error
+    match eng.run("./tests/group2/nope.gos") {
+        Err(engine::EngineError::Compile(el)) => assert_eq!(el.len(), 1),
+        _ => panic!("expected a compile error"),
+    }
+}
+
+fn goscript(args: &[&str]) -> (i32, String, String) {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_goscript"))
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn test_cli() {
+    let (code, stdout, _) = goscript(&["run", "tests/group2/args.gos", "hello", "world"]);
+    assert_eq!((code, stdout.as_str()), (0, "hello, world\n"));
+    let (code, _, _) = goscript(&["run", "tests/group2/args.gos", "hello"]);
+    assert_eq!(code, 2);
+    let (code, stdout, stderr) =
+        goscript(&["run", "--base-path", "./std/", "tests/group2/panic.gos"]);
+    assert_eq!((code, stdout.as_str()), (2, ""));
+    assert!(stderr.contains("panic.gos:5"));
+    let (code, _, stderr) = goscript(&[
+        "run",
+        "--max-instructions",
+        "100",
+        "tests/group2/budget.gos",
+    ]);
+    assert_eq!(code, 2);
+    assert!(stderr.contains("instruction budget exhausted"));
+    let (code, _, stderr) = goscript(&["run", "--timeout", "0.05", "tests/group2/spin.gos"]);
+    assert_eq!(code, 2);
+    assert!(stderr.contains("interrupted"));
+
+    let (code, _, stderr) = goscript(&["check", "tests/group2/compile_error.gos"]);
+    assert_eq!(code, 1);
+    assert!(stderr.contains("compile_error.gos:4:17"));
+    let (code, _, stderr) = goscript(&["check", "tests/group2/call.gos"]);
+    assert_eq!((code, stderr.as_str()), (0, ""));
+
+    let (code, stdout, _) = goscript(&["disasm", "tests/group2/call.gos"]);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("func main.Add func(int, int) int"));
+    let (code, stdout, _) = goscript(&["version"]);
+    assert_eq!(code, 0);
+    assert!(stdout.starts_with("goscript "));
+
+    let (code, _, stderr) = goscript(&["run", "tests/group2/nope.gos"]);
+    assert_eq!(code, 1);
+    assert!(stderr.contains("failed to locate path"), "{}", stderr);
+    let empty = std::env::temp_dir().join("goscript_empty_dir");
+    std::fs::create_dir_all(&empty).unwrap();
+    assert_eq!(goscript(&["run", empty.to_str().unwrap()]).0, 1);
+
+    assert_eq!(goscript(&[]).0, 2);
+    assert_eq!(goscript(&["bogus"]).0, 2);
+    assert_eq!(goscript(&["run", "--bogus", "tests/group2/call.gos"]).0, 2);
+    assert_eq!(goscript(&["check", "tests/group2/call.gos", "extra"]).0, 2);
+    assert_eq!(goscript(&["run", "--max-instructions", "x", "a.gos"]).0, 2);
+    assert_eq!(goscript(&["run", "--timeout", "-1", "a.gos"]).0, 2);
+}
+
 #[test]
 fn test_unicode0() {
     time_test!();
@@ -244,6 +599,30 @@ fn test_select() {
     assert!(err_cnt == 0);
 }
 
+#[test]
+fn test_gc() {
+    let err_cnt = run("./tests/group1/gc.gos", true);
+    assert!(err_cnt == 0);
+}
+
+#[test]
+fn test_named() {
+    let err_cnt = run("./tests/group1/named.gos", true);
+    assert!(err_cnt == 0);
+}
+
+#[test]
+fn test_ffi() {
+    let err_cnt = run("./tests/group1/ffi.gos", true);
+    assert!(err_cnt == 0);
+}
+
+#[test]
+fn test_multifile() {
+    let err_cnt = run("./tests/group1/multifile.gos", true);
+    assert!(err_cnt == 0);
+}
+
 #[test]
 fn test_linked() {
     let err_cnt = run("./tests/demo/linked.gos", true);
@@ -265,3 +644,1017 @@ fn test_fibonacci() {
     let err_cnt = run("./tests/demo/fibonacci.gos", true);
     assert!(err_cnt == 0);
 }
+
+#[test]
+fn test_g2budget() {
+    let eng = |total: Option<u64>, per_goroutine: Option<u64>| {
+        engine::Engine::new(engine::Config {
+            max_instructions: total,
+            max_instructions_per_goroutine: per_goroutine,
+            ..test_config()
+        })
+    };
+    let path = "./tests/group2/budget.gos";
+    let lines = |err: Option<engine::EngineError>| match err {
+        Some(engine::EngineError::BudgetExhausted(s)) => s.goroutines[0]
+            .call_stack
+            .iter()
+            .map(|x| x.pos.as_ref().map(|p| p.line).unwrap())
+            .collect::<Vec<usize>>(),
+        _ => panic!("expected the budget to be exhausted"),
+    };
+
+    assert!(eng(None, None).run(path).is_ok());
+    assert!(eng(Some(1_000_000), Some(1_000_000)).run(path).is_ok());
+    // stopped in the loop of sum, called by the goroutine
+    let stack = lines(eng(Some(1000), None).run(path).err());
+    assert_eq!(stack.len(), 2);
+    assert!((5..=6).contains(&stack[0]) && stack[1] == 23);
+    // main is blocked most of the time, it's the goroutine that is stopped
+    let stack = lines(eng(None, Some(1000)).run(path).err());
+    assert!((5..=6).contains(&stack[0]) && stack[1] == 23);
+
+    // the budget is per call, and the script is still usable after running out
+    let e = eng(Some(100_000), None);
+    let program = e.compile(path).unwrap();
+    let script = e.instantiate(&program);
+    assert_eq!(lines(script.call("main", "Spin", vec![]).err()), vec![12]);
+    let ret = script
+        .call("main", "Sum", vec![engine::GosValue::Int(100)])
+        .unwrap();
+    assert_eq!(*ret[0].as_int(), 4950);
+    assert_eq!(lines(script.call("main", "Spin", vec![]).err()), vec![12]);
+}
+
+#[test]
+fn test_g2interrupt() {
+    let eng = |timeout: Option<std::time::Duration>| {
+        engine::Engine::new(engine::Config {
+            timeout: timeout,
+            ..test_config()
+        })
+    };
+    let path = "./tests/group2/budget.gos";
+    let interrupted = |err: Option<engine::EngineError>| match err {
+        Some(engine::EngineError::Interrupted(s)) => {
+            assert_eq!(s.goroutines[0].call_stack.len(), 1);
+            assert_eq!(s.goroutines[0].call_stack[0].pos.as_ref().unwrap().line, 12);
+        }
+        _ => panic!("expected an interrupt"),
+    };
+    let sum = |script: &engine::Script| {
+        let ret = script
+            .call("main", "Sum", vec![engine::GosValue::Int(100)])
+            .unwrap();
+        assert_eq!(*ret[0].as_int(), 4950);
+    };
+
+    let e = eng(None);
+    let program = e.compile(path).unwrap();
+    let script = e.instantiate(&program);
+    let handle = script.interrupt_handle();
+    let t = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.interrupt();
+    });
+    interrupted(script.call("main", "Spin", vec![]).err());
+    t.join().unwrap();
+    // the interrupt is cleared once it has stopped the script
+    sum(&script);
+    // an interrupt that comes early stops the next call
+    script.interrupt_handle().interrupt();
+    interrupted(script.call("main", "Spin", vec![]).err());
+    sum(&script);
+
+    let e = eng(Some(std::time::Duration::from_millis(50)));
+    let program = e.compile(path).unwrap();
+    let script = e.instantiate(&program);
+    interrupted(script.call("main", "Spin", vec![]).err());
+    sum(&script);
+    assert!(script.run().is_ok());
+
+    // the goroutines waiting for the host are interrupted too
+    let mut e = eng(Some(std::time::Duration::from_millis(300)));
+    e.host_package("host/actor")
+        .function("Replies", "(c chan string)", |_: engine::Chan| {});
+    let program = e.compile("./tests/group2/chan.gos").unwrap();
+    let script = e.instantiate(&program);
+    script
+        .set_global("main", "Inbox", engine::Chan::new(0))
+        .unwrap();
+    let ex = async_executor::LocalExecutor::new();
+    match futures_lite::future::block_on(ex.run(script.run_async())) {
+        Err(engine::EngineError::Interrupted(s)) => {
+            assert_eq!(s.goroutines[0].status, "chan receive")
+        }
+        r => panic!("expected an interrupt, got {:?}", r),
+    }
+
+    let mut e = eng(None);
+    e.host_package("host/never")
+        .async_function("Wait", "() string", || {
+            futures_lite::future::pending::<String>()
+        });
+    let src = "package main\nimport \"host/never\"\nfunc main() {\n    never.Wait()\n}\n";
+    let program = e.compile_source("never.gos", src).unwrap();
+    let script = e.instantiate(&program);
+    let handle = script.interrupt_handle();
+    let t = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.interrupt();
+    });
+    match script.run() {
+        Err(engine::EngineError::Interrupted(s)) => {
+            assert_eq!(s.goroutines[0].status, "async call")
+        }
+        r => panic!("expected an interrupt, got {:?}", r),
+    }
+    t.join().unwrap();
+}
+
+// a writer that can be read after it's handed over to the engine
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl std::io::Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Capture {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+#[test]
+fn test_g2output() {
+    let (stdout, stderr) = (Capture::default(), Capture::default());
+    let eng = engine::Engine::new(engine::Config {
+        stdout: Some(Box::new(stdout.clone())),
+        stderr: Some(Box::new(stderr.clone())),
+        ..test_config()
+    });
+    let program = eng.compile("./tests/group2/output.gos").unwrap();
+    match eng.instantiate(&program).run() {
+        Err(engine::EngineError::Panic(_)) => {}
+        _ => panic!("expected a runtime panic"),
+    }
+    assert_eq!(stdout.text(), "to stdout, 1\n");
+    let err = stderr.text();
+    assert!(err.starts_with("a1true\nb 2 2.5\n\n"), "{}", err);
+    assert!(err.contains("output.gos:11"), "{}", err);
+
+    // every instance writes to the writers of the config
+    eng.instantiate(&program).run().unwrap_err();
+    assert_eq!(stdout.text(), "to stdout, 1\n".repeat(2));
+
+    // unless it's given writers of its own
+    let (own_out, own_err) = (Capture::default(), Capture::default());
+    let mut script = eng.instantiate(&program);
+    script.set_stdout(Box::new(own_out.clone()));
+    script.set_stderr(Box::new(own_err.clone()));
+    script.run().unwrap_err();
+    assert_eq!(own_out.text(), "to stdout, 1\n");
+    assert!(own_err.text().starts_with("a1true\n"));
+    assert_eq!(stdout.text(), "to stdout, 1\n".repeat(2));
+    assert_eq!(stderr.text().matches("a1true").count(), 2);
+}
+
+#[test]
+fn test_g2ffi() {
+    use std::collections::HashMap;
+
+    let mut eng = test_engine();
+    eng.register_fn("host", "add", |a: i64, b: i64| a + b);
+    eng.register_fn("host", "divmod", |a: i64, b: i64| (a / b, a % b));
+    eng.register_fn("host", "shout", |s: String, n: u8| {
+        s.to_uppercase() + &"!".repeat(n as usize)
+    });
+    eng.register_fn("host", "split", |s: String| {
+        s.split(' ').map(|x| x.to_string()).collect::<Vec<String>>()
+    });
+    eng.register_fn("host", "count", |words: Vec<String>| {
+        let mut m = HashMap::new();
+        for w in words {
+            *m.entry(w).or_insert(0) += 1;
+        }
+        m
+    });
+    eng.register_fn(
+        "host",
+        "lookup",
+        |m: Option<HashMap<String, i64>>, key: String| match m.and_then(|m| m.get(&key).cloned()) {
+            Some(n) => (n, true),
+            None => (0, false),
+        },
+    );
+    eng.register_fn("host", "warmer", |c: f64, by: f32| c + by as f64);
+    eng.register_fn("host", "describe", |v: Option<engine::GosValue>| match v {
+        None => "nil".to_string(),
+        Some(v) => match (i64::from_gos(v.clone()), String::from_gos(v)) {
+            (Ok(i), _) => format!("int {}", i),
+            (_, Ok(s)) => format!("string {}", s),
+            _ => "other".to_string(),
+        },
+    });
+    eng.register_fn("host", "join", |sep: String, a: Vec<engine::GosValue>| {
+        a.iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(&sep)
+    });
+    eng.register_fn("host", "nothing", || {});
+    let program = eng.compile("./tests/group2/ffi.gos").unwrap();
+    assert!(eng.instantiate(&program).run().is_ok());
+}
+
+#[test]
+fn test_g2ffi_error() {
+    let mut eng = test_engine();
+    let positive = |n: i64| {
+        if n < 0 {
+            Err("negative")
+        } else {
+            Ok(n)
+        }
+    };
+    eng.register_fn("host", "parse", |s: String| {
+        s.parse::<i64>().map_err(|_| format!("not a number: {}", s))
+    });
+    eng.register_fn("host", "check", move |n: i64| positive(n).map(|_| ()));
+    eng.register_fn("host", "must", positive);
+    eng.register_fn("host", "wrong", |_: i64| {});
+    let program = eng.compile("./tests/group2/ffi_error.gos").unwrap();
+    assert!(eng.instantiate(&program).run().is_ok());
+}
+
+#[test]
+fn test_g2callback() {
+    let mut eng = test_engine();
+    eng.register_fn("host", "each", |ctx: &FfiCtx, n: i64, f: GosValue| {
+        for i in 0..n {
+            ctx.call(&f, vec![GosValue::Int(i as isize)])?;
+        }
+        Ok::<(), String>(())
+    });
+    eng.register_fn("host", "apply", |ctx: &FfiCtx, f: GosValue, x: i64| {
+        let r = ctx.call(&f, vec![GosValue::Int(x as isize)])?;
+        i64::from_gos(r[0].clone())
+    });
+    eng.register_fn("host", "guard", |ctx: &FfiCtx, f: GosValue| {
+        ctx.call(&f, vec![]).map(|_| ())
+    });
+    let handler = Rc::new(RefCell::new(None));
+    let h = handler.clone();
+    eng.register_fn("host", "onMessage", move |f: GosValue| {
+        *h.borrow_mut() = Some(f);
+    });
+    eng.register_fn("host", "crash", || -> () { panic!("crashed") });
+    let program = eng.compile("./tests/group2/callback.gos").unwrap();
+    let script = eng.instantiate(&program);
+    script.run().unwrap();
+
+    // the host calls back after the FFI call has returned
+    let f = handler.borrow().clone().unwrap();
+    let r = script
+        .call_closure(&f, vec![GosValue::new_str("hello".to_string())])
+        .unwrap();
+    assert_eq!(i64::from_gos(r[0].clone()), Ok(5));
+    let r = script
+        .call_closure(&f, vec![GosValue::new_str("go".to_string())])
+        .unwrap();
+    assert_eq!(i64::from_gos(r[0].clone()), Ok(7));
+    assert!(script.call_closure(&f, vec![]).is_err());
+    assert!(script.call_closure(&GosValue::Int(1), vec![]).is_err());
+
+    // a Rust panic stops the script instead of going unnoticed
+    match script.call("main", "Crash", vec![]) {
+        Err(engine::EngineError::Internal(msg)) => assert_eq!(msg, "crashed"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // a callback can wait for a timer, but not on the executor of the host
+    let script = eng.instantiate(&program);
+    let r = script.call("main", "SleepyCallback", vec![]).unwrap();
+    assert_eq!(r[0].as_str().as_str(), "");
+    let ex = async_executor::LocalExecutor::new();
+    let call = script.call_async("main", "SleepyCallback", vec![]);
+    let r = futures_lite::future::block_on(ex.run(call)).unwrap();
+    assert!(r[0].as_str().as_str().contains("run_async"), "{}", r[0]);
+}
+
+// a host object Go code sees as a Conn
+struct Connection {
+    addr: String,
+    buf: RefCell<String>,
+    closed: Cell<bool>,
+}
+
+impl Ffi for Connection {
+    fn call(&self, ctx: &FfiCtx, params: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+        match ctx.func_name {
+            "Read" => Ok(vec![GosValue::new_str(self.buf.borrow().clone())]),
+            "Write" => {
+                let s = String::from_gos(params[0].clone())?;
+                *self.buf.borrow_mut() = s.clone();
+                Ok(vec![s.len().to_gos(ctx, ctx.results[0])?])
+            }
+            "Close" => {
+                self.closed.set(true);
+                Ok(vec![])
+            }
+            _ => Err(format!("unexpected call: {}", ctx.func_name)),
+        }
+    }
+}
+
+#[test]
+fn test_g2host() {
+    let mut eng = test_engine();
+    let conns = Rc::new(RefCell::new(vec![]));
+    let c = conns.clone();
+    eng.register_fn("net", "dial", move |addr: String| {
+        let conn = Rc::new(RefCell::new(Connection {
+            addr: addr,
+            buf: RefCell::new(String::new()),
+            closed: Cell::new(false),
+        }));
+        c.borrow_mut().push(conn.clone());
+        conn
+    });
+    eng.register_fn("net", "addr", |c: Rc<RefCell<Connection>>| {
+        c.borrow().addr.clone()
+    });
+    eng.register_fn("net", "closed", |c: Rc<RefCell<Connection>>| {
+        c.borrow().closed.get()
+    });
+    let program = eng.compile("./tests/group2/host.gos").unwrap();
+    eng.instantiate(&program).run().unwrap();
+
+    // the objects Go code got are the ones the host created
+    let conns = conns.borrow();
+    assert_eq!(conns.len(), 2);
+    assert_eq!(*conns[1].borrow().buf.borrow(), "there");
+    assert!(conns.iter().all(|c| c.borrow().closed.get()));
+}
+
+#[test]
+fn test_g2native() {
+    let mut eng = test_engine();
+    eng.register_native("main.hypot", |x: f64, y: f64| x.hypot(y));
+    eng.register_native("main.divmod", |a: i64, b: i64| (a / b, a % b));
+    eng.register_native("main.parse", |s: String| {
+        s.parse::<i64>().map_err(|_| format!("not a number: {}", s))
+    });
+    eng.register_native("main.sum", |a: Vec<i64>| a.iter().sum::<i64>());
+    eng.register_native("main.point.dist", |p: GosValue| {
+        // the receiver is of the named type point
+        let fields = &p.as_named().0.as_struct().0.borrow().fields;
+        let x = f64::from_gos(fields[0].clone())?;
+        let y = f64::from_gos(fields[1].clone())?;
+        Ok::<f64, String>(x.hypot(y))
+    });
+    let program = eng.compile("./tests/group2/native.gos").unwrap();
+    eng.instantiate(&program).run().unwrap();
+
+    // the functions not registered are reported before anything runs
+    let names = vec![
+        "main.divmod",
+        "main.hypot",
+        "main.parse",
+        "main.point.dist",
+        "main.sum",
+    ];
+    let eng = test_engine();
+    match eng.compile("./tests/group2/native.gos") {
+        Err(engine::EngineError::MissingNatives(missing)) => assert_eq!(missing, names),
+        _ => panic!("expected missing natives"),
+    }
+    match eng.instantiate(&program).run() {
+        Err(engine::EngineError::MissingNatives(missing)) => assert_eq!(missing, names),
+        _ => panic!("expected missing natives"),
+    }
+}
+
+#[derive(GoStruct, Clone, Debug, PartialEq)]
+struct Point {
+    #[gos(rename = "X")]
+    x: f64,
+    #[gos(rename = "Y")]
+    y: f64,
+}
+
+#[derive(GoStruct, Clone, Debug, PartialEq)]
+struct Row {
+    #[gos(rename = "Name")]
+    name: String,
+    #[gos(rename = "Count")]
+    count: i64,
+    #[gos(rename = "Tags")]
+    tags: Vec<String>,
+    #[gos(rename = "Pos")]
+    pos: Point,
+}
+
+#[test]
+fn test_g2gostruct() {
+    assert_eq!(
+        Point::go_decl(),
+        "type Point struct {\n    X float64\n    Y float64\n}\n"
+    );
+    let mut eng = test_engine();
+    let rows = vec![
+        Row {
+            name: "a".to_string(),
+            count: 1,
+            tags: vec![],
+            pos: Point { x: 0.0, y: 0.0 },
+        },
+        Row {
+            name: "b".to_string(),
+            count: 2,
+            tags: vec!["y".to_string(), "z".to_string()],
+            pos: Point { x: 1.5, y: -1.0 },
+        },
+    ];
+    let input = rows.clone();
+    eng.register_native("main.load", move || input.clone());
+    let saved = Rc::new(RefCell::new(vec![]));
+    let s = saved.clone();
+    eng.register_native("main.save", move |rows: Vec<Row>| {
+        *s.borrow_mut() = rows;
+    });
+    let script = std::fs::read_to_string("./tests/group2/gostruct.gos").unwrap();
+    let source = format!("{}\n{}\n{}", script, Row::go_decl(), Point::go_decl());
+    let program = eng.compile_source("gostruct.gos", &source).unwrap();
+    eng.instantiate(&program).run().unwrap();
+
+    let mut want = rows;
+    want[0].count = 11;
+    want[0].tags = vec!["new".to_string()];
+    want[1].count = 12;
+    want.push(Row {
+        name: "c".to_string(),
+        count: 3,
+        tags: vec![],
+        pos: Point { x: 1.0, y: 2.0 },
+    });
+    assert_eq!(*saved.borrow(), want);
+}
+
+#[test]
+fn test_g2globals() {
+    #[derive(GoStruct, Debug, PartialEq)]
+    struct Point {
+        #[gos(rename = "X")]
+        x: f64,
+        #[gos(rename = "Y")]
+        y: f64,
+    }
+
+    #[derive(GoStruct, Clone, Debug, PartialEq)]
+    struct Pair {
+        #[gos(rename = "A")]
+        a: i64,
+        #[gos(rename = "B")]
+        b: i64,
+    }
+
+    let eng = test_engine();
+    let program = eng.compile("./tests/group2/globals.gos").unwrap();
+    let script = eng.instantiate(&program);
+
+    // the initializers run before the first get or set
+    assert_eq!(script.get_global::<i64>("main", "Threshold").unwrap(), 10);
+    script.set_global("main", "Threshold", 2i64).unwrap();
+    script.set_global("main", "Debug", true).unwrap();
+    let names: Vec<String> = vec!["a".into(), "b".into(), "c".into()];
+    script.set_global("main", "Names", names).unwrap();
+    script
+        .set_global("main", "Origin", Point { x: 0.5, y: 2.0 })
+        .unwrap();
+    script.run().unwrap();
+    assert_eq!(script.get_global::<i64>("main", "Passed").unwrap(), 2);
+    let log: Vec<String> = script.get_global("main", "Log").unwrap();
+    assert_eq!(log, vec!["a".to_string(), "b".to_string()]);
+    let origin: Point = script.get_global("main", "Origin").unwrap();
+    assert_eq!(origin, Point { x: 10.5, y: 2.0 });
+    assert_eq!(
+        script.get_global::<String>("main", "Version").unwrap(),
+        "1.0"
+    );
+
+    // the fields are matched by name
+    let pairs = vec![Pair { a: 1, b: 2 }, Pair { a: 3, b: 4 }];
+    script.set_global("main", "Pairs", pairs.clone()).unwrap();
+    assert_eq!(
+        script.get_global::<Vec<Pair>>("main", "Pairs").unwrap(),
+        pairs
+    );
+
+    let invalid = |r: Result<(), engine::EngineError>| match r {
+        Err(engine::EngineError::InvalidGlobal(_)) => {}
+        r => panic!("expected an invalid global, got {:?}", r),
+    };
+    invalid(script.set_global("main", "Threshold", "ten".to_string()));
+    invalid(script.set_global("main", "Debug", GosValue::Int(1)));
+    invalid(script.set_global("main", "Version", "2.0".to_string()));
+    invalid(script.set_global("main", "main", 0i64));
+    invalid(script.set_global("main", "Nope", 0i64));
+    invalid(script.set_global("nope", "Debug", true));
+    invalid(script.get_global::<bool>("main", "Threshold").map(|_| ()));
+
+    // a new instance starts with the initial values
+    let script = eng.instantiate(&program);
+    script.run().unwrap();
+    assert_eq!(script.get_global::<i64>("main", "Passed").unwrap(), 1);
+    assert!(script
+        .get_global::<Vec<String>>("main", "Log")
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_g2hostpkg() {
+    let mut eng = test_engine();
+    let row = |name: &str, count, y| Row {
+        name: name.to_string(),
+        count: count,
+        tags: vec![],
+        pos: Point { x: 0.0, y: y },
+    };
+    let inserted = Rc::new(RefCell::new(vec![]));
+    let ins = inserted.clone();
+    eng.host_package("host/db")
+        .constant("MaxConns", 16i64)
+        .constant("Driver", "mem".to_string())
+        .constant("Quoted", "\"a\\b\"\n\u{1b}\u{0}é".to_string())
+        .constant("Ratio", 0.1f64)
+        .constant("Huge", 1e300f64)
+        .constant("Strict", true)
+        .variable::<i64>("Inserted")
+        .go_struct::<Row>()
+        .go_struct::<Point>()
+        .decl("type Store interface {\n    Get(key string) Row\n}")
+        .function("Query", "(q string) ([]Row, error)", move |q: String| {
+            if q.is_empty() {
+                Err("empty query".to_string())
+            } else {
+                Ok(vec![row("a", 1, 1.0), row("b", 2, 2.0)])
+            }
+        })
+        .function("Insert", "(r Row) int64", move |r: Row| {
+            ins.borrow_mut().push(r);
+            ins.borrow().len() as i64
+        });
+
+    let program = eng.compile("./tests/group2/hostpkg.gos").unwrap();
+    let script = eng.instantiate(&program);
+    script.run().unwrap();
+    assert_eq!(script.get_global::<i64>("db", "Inserted").unwrap(), 3);
+    let names: Vec<String> = inserted.borrow().iter().map(|r| r.name.clone()).collect();
+    assert_eq!(names, vec!["a".to_string(), "c".to_string()]);
+    assert_eq!(inserted.borrow()[0].count, 2);
+
+    // constants are written as Go literals, or rejected
+    assert_eq!(u8::MAX.go_literal().unwrap(), "255");
+    assert_eq!((-2.5f32).go_literal().unwrap(), "-2.5");
+    assert!(f64::NAN.go_literal().is_err());
+    assert!(f64::INFINITY.go_literal().is_err());
+
+    // calls are type checked against the declarations
+    let src = "package main\nimport \"host/db\"\nfunc main() {\n    db.Insert(1)\n}\n";
+    match eng.compile_source("bad.gos", src) {
+        Err(engine::EngineError::Compile(el)) => {
+            assert!(el.to_string().contains("to host/db.Row"), "{}", el)
+        }
+        _ => panic!("expected a compile error"),
+    }
+
+    // the signatures are checked against the Rust functions
+    let mut eng = test_engine();
+    eng.host_package("host/bad")
+        .go_struct::<Point>()
+        .decl("type Size struct {\n    X string\n    Y float64\n}")
+        .function("Count", "(q string) int", |n: i64| n)
+        .function("Args", "(a, b int) int", |a: i64| a)
+        .function("Results", "() (int, error)", || "a".to_string())
+        .function("Move", "(p Point) bool", |p: Point| p.x > 0.0)
+        .function("Resize", "(s Size) bool", |p: Point| p.x > 0.0)
+        .function("Checked", "(q string) (int64, error)", |q: String| {
+            q.parse::<i64>().map_err(|e| e.to_string())
+        });
+    let src = "package main\nimport \"host/bad\"\nfunc main() {\n    bad.Checked(\"1\")\n}\n";
+    match eng.compile_source("bad.gos", src) {
+        Err(engine::EngineError::MismatchedNatives(errs)) => {
+            let names: Vec<&str> = errs.iter().map(|e| e.split(':').next().unwrap()).collect();
+            assert_eq!(
+                names,
+                vec![
+                    "host/bad.Args",
+                    "host/bad.Count",
+                    "host/bad.Resize",
+                    "host/bad.Results"
+                ],
+                "{:?}",
+                errs
+            );
+        }
+        r => panic!("expected mismatched natives, got {:?}", r.err()),
+    }
+}
+
+// Gate is a future that is ready when the host opens it
+#[derive(Default)]
+struct Gate {
+    value: Option<String>,
+    waker: Option<Waker>,
+}
+
+struct GateFuture(Rc<RefCell<Gate>>);
+
+impl Future for GateFuture {
+    type Output = String;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<String> {
+        let mut gate = self.0.borrow_mut();
+        match gate.value.take() {
+            Some(v) => Poll::Ready(v),
+            None => {
+                gate.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// Sleep is a future that is woken by another thread
+struct Sleep(Arc<Mutex<(bool, Option<Waker>)>>);
+
+impl Sleep {
+    fn new(ms: u64) -> Sleep {
+        let state = Arc::new(Mutex::new((false, None::<Waker>)));
+        let s = state.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(ms));
+            let mut s = s.lock().unwrap();
+            s.0 = true;
+            if let Some(w) = s.1.take() {
+                w.wake();
+            }
+        });
+        Sleep(state)
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut s = self.0.lock().unwrap();
+        if s.0 {
+            Poll::Ready(())
+        } else {
+            s.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[test]
+fn test_g2deadlock() {
+    let eng = engine::Engine::new(engine::Config {
+        stderr: Some(Box::new(std::io::sink())),
+        ..test_config()
+    });
+    let program = eng.compile("./tests/group2/deadlock.gos").unwrap();
+    let deadlock = |func: &str| -> Vec<(usize, &'static str, usize)> {
+        let script = eng.instantiate(&program);
+        match script.call("main", func, vec![]) {
+            Err(engine::EngineError::Deadlock(fibers)) => fibers
+                .into_iter()
+                .map(|g| (g.id, g.status, g.call_stack[0].pos.as_ref().unwrap().line))
+                .collect(),
+            r => panic!("expected a deadlock, got {:?}", r),
+        }
+    };
+
+    assert_eq!(
+        deadlock("Recv"),
+        vec![(1, "chan receive", 14), (2, "chan send", 12)]
+    );
+    assert_eq!(deadlock("NilChan"), vec![(1, "chan send (nil chan)", 19)]);
+    assert_eq!(
+        deadlock("Select"),
+        vec![(1, "select", 29), (2, "select (no cases)", 24)]
+    );
+    assert_eq!(deadlock("Range"), vec![(1, "chan receive", 41)]);
+
+    let script = eng.instantiate(&program);
+    script.run().unwrap();
+}
+
+#[test]
+fn test_g2exit() {
+    let eng = test_engine();
+    let program = eng.compile("./tests/group2/exit.gos").unwrap();
+
+    let script = eng.instantiate(&program);
+    assert_eq!(script.run().unwrap().exit_code, 0);
+    assert!(script.get_global::<bool>("main", "Deferred").unwrap());
+
+    let script = eng.instantiate(&program);
+    script.set_global("main", "Code", 3i64).unwrap();
+    assert_eq!(script.run().unwrap().exit_code, 3);
+    assert!(!script.get_global::<bool>("main", "Deferred").unwrap());
+
+    let script = eng.instantiate(&program);
+    script.set_global("main", "Code", 4i64).unwrap();
+    match script.call("main", "main", vec![]) {
+        Err(engine::EngineError::Exit(4)) => {}
+        r => panic!("expected exit status 4, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_g2chan() {
+    let replies: Rc<RefCell<Option<engine::Chan>>> = Rc::new(RefCell::new(None));
+    let r = replies.clone();
+    let mut eng = test_engine();
+    eng.host_package("host/actor").function(
+        "Replies",
+        "(c chan string)",
+        move |c: engine::Chan| {
+            *r.borrow_mut() = Some(c);
+        },
+    );
+    let program = eng.compile("./tests/group2/chan.gos").unwrap();
+
+    // the messages are all sent before the script runs
+    let script = eng.instantiate(&program);
+    let inbox = engine::Chan::new(4);
+    let channel_err = |r: Result<bool, engine::EngineError>| match r {
+        Err(engine::EngineError::Channel(_)) => {}
+        r => panic!("expected a channel error, got {:?}", r),
+    };
+    channel_err(script.try_send(&inbox, "a"));
+    script.set_global("main", "Inbox", inbox.clone()).unwrap();
+    assert_eq!(script.try_send(&inbox, "a").unwrap(), true);
+    assert_eq!(script.try_send(&inbox, "b".to_string()).unwrap(), true);
+    channel_err(script.try_send(&inbox, 1i64));
+    inbox.close();
+    channel_err(script.try_send(&inbox, "c"));
+    script.run().unwrap();
+    let out: Vec<String> = script.get_global("main", "Out").unwrap();
+    assert_eq!(out, vec!["a".to_string(), "b".to_string()]);
+    let ch = replies.borrow_mut().take().unwrap();
+    assert_eq!(
+        script.try_recv::<String>(&ch).unwrap(),
+        Some("got a".to_string())
+    );
+    assert_eq!(
+        script.try_recv::<String>(&ch).unwrap(),
+        Some("got b".to_string())
+    );
+    assert_eq!(script.try_recv::<String>(&ch).unwrap(), None);
+    assert!(ch.is_closed());
+
+    // the host can't send anything while run blocks, the script waiting for
+    // it is deadlocked
+    let script = eng.instantiate(&program);
+    let inbox = engine::Chan::new(0);
+    script.set_global("main", "Inbox", inbox.clone()).unwrap();
+    match script.run() {
+        Err(engine::EngineError::Deadlock(gs)) => {
+            assert_eq!(gs.len(), 1);
+            assert_eq!(gs[0].status, "chan receive");
+        }
+        r => panic!("expected a deadlock, got {:?}", r),
+    }
+
+    // the host feeds the script while it runs, on the same executor
+    let script = eng.instantiate(&program);
+    let inbox = engine::Chan::new(0);
+    script.set_global("main", "Inbox", inbox.clone()).unwrap();
+    let host = async {
+        let mut got = vec![];
+        for msg in ["x", "y", "z"].iter() {
+            script.send(&inbox, *msg).await.unwrap();
+        }
+        inbox.close();
+        let ch = replies.borrow_mut().take().unwrap();
+        while let Some(s) = script.recv::<String>(&ch).await.unwrap() {
+            got.push(s);
+        }
+        got
+    };
+    let ex = async_executor::LocalExecutor::new();
+    let (result, got) =
+        futures_lite::future::block_on(ex.run(futures_lite::future::zip(script.run_async(), host)));
+    result.unwrap();
+    assert_eq!(got, vec!["got x", "got y", "got z"]);
+}
+
+#[test]
+fn test_g2async() {
+    let mut eng = test_engine();
+    let gate = Rc::new(RefCell::new(Gate::default()));
+    let (g0, g1, g2) = (gate.clone(), gate.clone(), gate.clone());
+    eng.host_package("host/io")
+        .async_function("Wait", "() string", move || GateFuture(g0.clone()))
+        .function("Waiting", "() bool", move || g1.borrow().waker.is_some())
+        .function("Open", "(v string)", move |v: String| {
+            let mut gate = g2.borrow_mut();
+            gate.value = Some(v);
+            gate.waker.take().unwrap().wake();
+        })
+        .async_function("Sleep", "(ms int) string", |ms: i64| async move {
+            Sleep::new(ms as u64).await;
+            format!("slept {}ms", ms)
+        })
+        .async_function("Fail", "() (int, error)", || async {
+            Err::<i64, String>("boom".to_string())
+        });
+    let program = eng.compile("./tests/group2/async.gos").unwrap();
+
+    eng.instantiate(&program).run().unwrap();
+
+    // run by an executor of the host, along with its own tasks
+    let ex = async_executor::LocalExecutor::new();
+    let ticks = Rc::new(Cell::new(0));
+    let t = ticks.clone();
+    ex.spawn(async move {
+        loop {
+            t.set(t.get() + 1);
+            futures_lite::future::yield_now().await;
+        }
+    })
+    .detach();
+    let script = eng.instantiate(&program);
+    futures_lite::future::block_on(ex.run(script.run_async())).unwrap();
+    assert!(ticks.get() > 0);
+}
+
+#[test]
+fn test_g2traceback() {
+    let eng = engine::Engine::new(engine::Config {
+        stderr: Some(Box::new(std::io::sink())),
+        max_instructions: Some(100_000),
+        ..test_config()
+    });
+    let program = eng.compile("./tests/group2/traceback.gos").unwrap();
+    let script = eng.instantiate(&program);
+    let funcs = |g: &engine::Goroutine| -> Vec<String> {
+        g.call_stack.iter().map(|f| f.func.clone()).collect()
+    };
+
+    match script.call("main", "Goroutine", vec![]) {
+        Err(engine::EngineError::Panic(p)) => {
+            assert_eq!(p.goroutine.id, 2);
+            assert_eq!(
+                funcs(&p.goroutine),
+                vec!["main.(*T).Fail", "main.worker.func1", "main.worker"]
+            );
+            let s = p.to_string();
+            assert!(s.starts_with("panic: failed\n\ngoroutine 2 [running]:\nmain.(*T).Fail()\n\t"));
+            assert!(s.contains("traceback.gos:8:5\n"), "{}", s);
+        }
+        r => panic!("expected a runtime panic, got {:?}", r),
+    }
+    match script.call("main", "Value", vec![]) {
+        Err(engine::EngineError::Panic(p)) => {
+            assert_eq!(p.goroutine.id, 1);
+            assert_eq!(funcs(&p.goroutine), vec!["main.T.Get", "main.Value"]);
+        }
+        r => panic!("expected a runtime panic, got {:?}", r),
+    }
+    // all the goroutines are dumped, the one stopped first
+    match script.call("main", "Spin", vec![]) {
+        Err(engine::EngineError::BudgetExhausted(s)) => {
+            let gs: Vec<(usize, &str)> = s.goroutines.iter().map(|g| (g.id, g.status)).collect();
+            assert_eq!(gs, vec![(2, "running"), (1, "chan receive")]);
+            assert_eq!(funcs(&s.goroutines[0]), vec!["main.Spin.func1"]);
+            assert_eq!(funcs(&s.goroutines[1]), vec!["main.Spin"]);
+            assert_eq!(s.goroutines[1].call_stack[0].pos.as_ref().unwrap().line, 42);
+        }
+        r => panic!("expected the budget to be exhausted, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_g2sync() {
+    let eng = engine::Engine::new(engine::Config {
+        stderr: Some(Box::new(std::io::sink())),
+        ..test_config()
+    });
+    let program = eng.compile("./tests/group2/sync.gos").unwrap();
+    let script = eng.instantiate(&program);
+    script.run().unwrap();
+    assert_eq!(
+        *script.call("main", "Mutex", vec![]).unwrap()[0].as_int(),
+        1000
+    );
+    let n = script.call("main", "Atomic", vec![]).unwrap().remove(0);
+    assert_eq!(i64::from_gos(n).unwrap(), 1000);
+
+    // a goroutine waiting for a lock is parked, not spinning
+    match script.call("main", "DoubleLock", vec![]) {
+        Err(engine::EngineError::Deadlock(gs)) => {
+            assert_eq!(gs.len(), 1);
+            assert_eq!(gs[0].status, "chan send");
+            let funcs: Vec<&str> = gs[0].call_stack.iter().map(|f| &*f.func).collect();
+            assert_eq!(funcs, vec!["sync.(*Mutex).Lock", "main.DoubleLock"]);
+        }
+        r => panic!("expected a deadlock, got {:?}", r),
+    }
+    match script.call("main", "BadUnlock", vec![]) {
+        Err(engine::EngineError::Panic(p)) => {
+            assert_eq!(p.msg, "sync: unlock of unlocked mutex");
+        }
+        r => panic!("expected a runtime panic, got {:?}", r),
+    }
+
+    // a call stopped anywhere in the sync functions leaves nothing locked
+    let eng = engine::Engine::new(engine::Config {
+        stderr: Some(Box::new(std::io::sink())),
+        max_instructions: Some(5_000),
+        ..test_config()
+    });
+    let script = eng.instantiate(&program);
+    for skip in 0..50 {
+        match script.call("main", "Churn", vec![engine::GosValue::Int(skip)]) {
+            Err(engine::EngineError::BudgetExhausted(_)) => {}
+            r => panic!("expected the budget to be exhausted, got {:?}", r),
+        }
+        let ret = script.call("main", "Churned", vec![]).unwrap();
+        assert!(*ret[0].as_bool());
+    }
+}
+
+#[test]
+fn test_g2time() {
+    let eng = |timeout: Option<std::time::Duration>| {
+        engine::Engine::new(engine::Config {
+            stderr: Some(Box::new(std::io::sink())),
+            timeout: timeout,
+            ..test_config()
+        })
+    };
+    let e = eng(None);
+    let program = e.compile("./tests/group2/time.gos").unwrap();
+    let script = e.instantiate(&program);
+    script.run().unwrap();
+
+    // a sleeping goroutine is not deadlocked, the ones left waiting after it
+    // is done are
+    match script.call("main", "SleepDeadlock", vec![]) {
+        Err(engine::EngineError::Deadlock(gs)) => {
+            assert_eq!(gs.len(), 1);
+            assert_eq!(gs[0].status, "chan receive");
+            assert_eq!(gs[0].call_stack[0].pos.as_ref().unwrap().line, 148);
+        }
+        r => panic!("expected a deadlock, got {:?}", r),
+    }
+
+    // stopped timers are not pending, the ones stopped by Reset neither
+    let e = eng(Some(std::time::Duration::from_secs(5)));
+    let program = e.compile("./tests/group2/time.gos").unwrap();
+    let script = e.instantiate(&program);
+    for f in ["StopDeadlock", "ResetDeadlock"].iter() {
+        match script.call("main", f, vec![]) {
+            Err(engine::EngineError::Deadlock(gs)) => {
+                assert_eq!(gs.len(), 1);
+                assert_eq!(gs[0].status, "chan receive");
+            }
+            r => panic!("expected a deadlock in {}, got {:?}", f, r),
+        }
+    }
+
+    // a long sleep can be interrupted
+    let e = eng(Some(std::time::Duration::from_millis(50)));
+    let program = e.compile("./tests/group2/time.gos").unwrap();
+    let script = e.instantiate(&program);
+    let start = std::time::Instant::now();
+    match script.call("main", "LongSleep", vec![]) {
+        Err(engine::EngineError::Interrupted(s)) => {
+            assert_eq!(s.goroutines[0].status, "sleep");
+            let funcs: Vec<&str> = s.goroutines[0]
+                .call_stack
+                .iter()
+                .map(|f| &*f.func)
+                .collect();
+            assert_eq!(
+                funcs,
+                vec!["time.sleepUntil", "time.Sleep", "main.LongSleep"]
+            );
+        }
+        r => panic!("expected an interrupt, got {:?}", r),
+    }
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+}
@@ -72,6 +72,10 @@ impl File {
         self.lines.len()
     }
 
+    pub fn lines(&self) -> &Vec<usize> {
+        &self.lines
+    }
+
     pub fn add_line(&mut self, offset: usize) {
         let i = self.line_count();
         if (i == 0 || self.lines[i - 1] < offset) && offset < self.size {
@@ -102,8 +106,8 @@ impl File {
 
     pub fn set_lines(&mut self, lines: Vec<usize>) -> bool {
         let size = self.size;
-        for (i, &offset) in self.lines.iter().enumerate() {
-            if (i == 0 && size <= offset) || offset < lines[i - 1] {
+        for (i, &offset) in lines.iter().enumerate() {
+            if (i > 0 && offset <= lines[i - 1]) || size <= offset {
                 return false;
             }
         }
@@ -4,6 +4,7 @@ use super::super::importer::{Config, ImportKey, Importer};
 use super::super::objects::{DeclInfoKey, ObjKey, PackageKey, ScopeKey, TCObjects, TypeKey};
 use super::super::operand::OperandMode;
 use super::super::selection::Selection;
+use super::super::source::SourceRead;
 use super::interface::IfaceInfo;
 use goscript_parser::ast;
 use goscript_parser::ast::Node;
@@ -215,6 +216,8 @@ pub struct Checker<'a> {
     pub octx: ObjContext,
     // import config
     config: &'a Config,
+    // where the source code of imported packages is read from
+    reader: &'a dyn SourceRead,
     // result of type checking
     pub result: TypeInfo,
     // for debug
@@ -397,6 +400,7 @@ impl<'a> Checker<'a> {
         all_results: &'a mut HashMap<PackageKey, TypeInfo>,
         pkg: PackageKey,
         cfg: &'a Config,
+        reader: &'a dyn SourceRead,
     ) -> Checker<'a> {
         Checker {
             tc_objs: tc_objs,
@@ -410,6 +414,7 @@ impl<'a> Checker<'a> {
             imp_map: HashMap::new(),
             octx: ObjContext::new(),
             config: cfg,
+            reader: reader,
             result: TypeInfo::new(),
             indent: Rc::new(RefCell::new(0)),
         }
@@ -450,6 +455,7 @@ impl<'a> Checker<'a> {
     pub fn new_importer(&mut self, pos: Pos) -> Importer {
         Importer::new(
             self.config,
+            self.reader,
             self.fset,
             self.all_pkgs,
             self.all_results,
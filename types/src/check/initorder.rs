@@ -129,8 +129,15 @@ impl<'a> Checker<'a> {
         let map: HashMap<ObjKey, GraphEdges> = self.obj_map.iter().fold(
             HashMap::new(),
             |mut init: HashMap<ObjKey, GraphEdges>, (&x, &decl_key)| {
+                // variables without initializers are nodes too, with no
+                // dependencies, so that what depends on them is not left
+                // waiting for them
                 let decl = &self.tc_objs.decls[decl_key];
-                if decl.has_initializer(self.ast_objs) {
+                let is_var = match decl {
+                    DeclInfo::Var(_) => true,
+                    _ => false,
+                };
+                if is_var || decl.has_initializer(self.ast_objs) {
                     let deps: HashSet<ObjKey> = decl.deps().iter().map(|z| *z).collect();
                     init.insert(x, GraphEdges::new(Rc::new(RefCell::new(deps))));
                 }
@@ -138,13 +145,12 @@ impl<'a> Checker<'a> {
             },
         );
 
-        // add the edges for the other direction
+        // add the edges for the other direction, and drop the ones to objects
+        // that are not in the graph
         for (o, node) in map.iter() {
+            node.succ.borrow_mut().retain(|s| map.contains_key(s));
             for s in node.succ.borrow().iter() {
-                let decl = &self.tc_objs.decls[self.obj_map[s]];
-                if decl.has_initializer(self.ast_objs) {
-                    map[s].pred.borrow_mut().insert(*o);
-                }
+                map[s].pred.borrow_mut().insert(*o);
             }
         }
 
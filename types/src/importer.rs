@@ -1,16 +1,16 @@
 #![allow(dead_code)]
 use super::check::check::{Checker, TypeInfo};
 use super::objects::{PackageKey, TCObjects};
+use super::source::SourceRead;
 use goscript_parser::ast;
 use goscript_parser::errors::{ErrorList, FilePosErrors};
 use goscript_parser::objects::Objects as AstObjects;
 use goscript_parser::position;
 use goscript_parser::{FileSet, Parser};
 use std::collections::HashMap;
-use std::env;
-use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 pub struct Config {
     // working directory
@@ -21,16 +21,19 @@ pub struct Config {
     pub trace_parser: bool,
     // print debug info in checker
     pub trace_checker: bool,
+    // the source of the packages provided by the host instead of being read
+    // by the SourceRead, by import path
+    pub host_pkgs: HashMap<String, String>,
 }
 
 impl Config {
-    fn get_working_dir(&self) -> io::Result<PathBuf> {
+    fn get_working_dir(&self, reader: &dyn SourceRead) -> io::Result<PathBuf> {
         if let Some(wd) = &self.work_dir {
             let mut buf = PathBuf::new();
             buf.push(wd);
             Ok(buf)
         } else {
-            env::current_dir()
+            reader.working_dir()
         }
     }
 }
@@ -58,6 +61,7 @@ impl ImportKey {
 
 pub struct Importer<'a> {
     config: &'a Config,
+    reader: &'a dyn SourceRead,
     fset: &'a mut FileSet,
     pkgs: &'a mut HashMap<String, PackageKey>,
     all_results: &'a mut HashMap<PackageKey, TypeInfo>,
@@ -70,6 +74,7 @@ pub struct Importer<'a> {
 impl<'a> Importer<'a> {
     pub fn new(
         config: &'a Config,
+        reader: &'a dyn SourceRead,
         fset: &'a mut FileSet,
         pkgs: &'a mut HashMap<String, PackageKey>,
         all_results: &'a mut HashMap<PackageKey, TypeInfo>,
@@ -80,6 +85,7 @@ impl<'a> Importer<'a> {
     ) -> Importer<'a> {
         Importer {
             config: config,
+            reader: reader,
             fset: fset,
             pkgs: pkgs,
             all_results: all_results,
@@ -94,12 +100,22 @@ impl<'a> Importer<'a> {
         if key.path == "unsafe" {
             return Ok(*self.tc_objs.universe().unsafe_pkg());
         }
-        let pb = self.validate_path(key)?;
-        let path = pb.0.as_path();
-        let import_path = pb.1;
-        let pkg = self.tc_objs.new_package(import_path.clone());
-        self.pkgs.insert(import_path, pkg);
-        let files = self.parse_dir(path)?;
+        let (pkg, files) = match self.config.host_pkgs.get(&key.path) {
+            Some(content) => {
+                let pkg = self.tc_objs.new_package(key.path.clone());
+                self.pkgs.insert(key.path.clone(), pkg);
+                let name = format!("{}.gos", key.path);
+                (pkg, vec![self.parse_file(name, content)?])
+            }
+            None => {
+                let pb = self.validate_path(key)?;
+                let path = pb.0.as_path();
+                let import_path = pb.1;
+                let pkg = self.tc_objs.new_package(import_path.clone());
+                self.pkgs.insert(import_path, pkg);
+                (pkg, self.parse_dir(path)?)
+            }
+        };
         Checker::new(
             self.tc_objs,
             self.ast_objs,
@@ -109,6 +125,7 @@ impl<'a> Importer<'a> {
             self.all_results,
             pkg,
             self.config,
+            self.reader,
         )
         .check(files)
     }
@@ -116,7 +133,7 @@ impl<'a> Importer<'a> {
     fn validate_path(&mut self, key: &'a ImportKey) -> Result<(PathBuf, String), ()> {
         let mut import_path = key.path.clone();
         let path = if is_local(&key.path) {
-            let working_dir = self.config.get_working_dir();
+            let working_dir = self.config.get_working_dir(self.reader);
             if working_dir.is_err() {
                 self.error(format!("failed to get working dir for: {}", key.path));
                 return Err(());
@@ -141,11 +158,11 @@ impl<'a> Importer<'a> {
                 return Err(());
             }
         };
-        if !path.exists() {
+        if !self.reader.is_file(&path) && !self.reader.is_dir(&path) {
             self.error(format!("failed to locate path: {}", key.path));
             return Err(());
         }
-        match path.canonicalize() {
+        match self.reader.canonicalize_path(&path) {
             Ok(p) => Ok((p, import_path)),
             Err(_) => {
                 self.error(format!("failed to canonicalize path: {}", key.path));
@@ -157,11 +174,11 @@ impl<'a> Importer<'a> {
     fn parse_dir(&mut self, path: &Path) -> Result<Vec<ast::File>, ()> {
         let working_dir = self
             .config
-            .get_working_dir()
+            .get_working_dir(self.reader)
             .ok()
-            .map(|x| x.canonicalize().ok())
+            .map(|x| self.reader.canonicalize_path(&x).ok())
             .flatten();
-        match read_content(path) {
+        match read_content(self.reader, path) {
             Ok(contents) => {
                 if contents.len() == 0 {
                     self.error(format!("no source file found in dir: {}", path.display()));
@@ -177,26 +194,7 @@ impl<'a> Importer<'a> {
                         }
                         .to_string_lossy()
                         .to_string();
-                        let mut pfile = self.fset.add_file(
-                            full_name,
-                            Some(self.fset.base()),
-                            content.chars().count(),
-                        );
-                        let afile = Parser::new(
-                            self.ast_objs,
-                            &mut pfile,
-                            self.errors,
-                            &content,
-                            self.config.trace_parser,
-                        )
-                        .parse_file();
-                        if afile.is_none() {
-                            // parse error, the details should be in the errorlist already.
-                            // give up
-                            return Err(());
-                        } else {
-                            afiles.push(afile.unwrap());
-                        }
+                        afiles.push(self.parse_file(full_name, &content)?);
                     }
                     Ok(afiles)
                 }
@@ -208,13 +206,40 @@ impl<'a> Importer<'a> {
         }
     }
 
+    // parse errors are added to the errorlist
+    fn parse_file(&mut self, name: String, content: &str) -> Result<ast::File, ()> {
+        let mut pfile = self
+            .fset
+            .add_file(name, Some(self.fset.base()), content.chars().count());
+        Parser::new(
+            self.ast_objs,
+            &mut pfile,
+            self.errors,
+            content,
+            self.config.trace_parser,
+        )
+        .parse_file()
+        .ok_or(())
+    }
+
     fn error(&self, err: String) {
-        let pos_file = self.fset.file(self.pos).unwrap();
-        FilePosErrors::new(pos_file, self.errors).add(self.pos, err, false);
+        match self.fset.file(self.pos) {
+            Some(pos_file) => FilePosErrors::new(pos_file, self.errors).add(self.pos, err, false),
+            // there is no file to point at when the entry package fails
+            None => {
+                let p = position::Position {
+                    filename: Rc::new(String::new()),
+                    offset: 0,
+                    line: 0,
+                    column: 0,
+                };
+                self.errors.add(p, err, false, false);
+            }
+        }
     }
 }
 
-fn read_content(p: &Path) -> io::Result<Vec<(PathBuf, String)>> {
+fn read_content(reader: &dyn SourceRead, p: &Path) -> io::Result<Vec<(PathBuf, String)>> {
     let mut result = vec![];
     let mut read = |path: PathBuf| -> io::Result<()> {
         if let Some(ext) = path.extension() {
@@ -222,7 +247,7 @@ fn read_content(p: &Path) -> io::Result<Vec<(PathBuf, String)>> {
                 if let Some(fs) = path.file_stem() {
                     let s = fs.to_str();
                     if s.is_some() && !s.unwrap().ends_with("_test") {
-                        let content = fs::read_to_string(path.as_path())?;
+                        let content = reader.read_file(path.as_path())?;
                         result.push((path, content))
                     }
                 }
@@ -231,12 +256,10 @@ fn read_content(p: &Path) -> io::Result<Vec<(PathBuf, String)>> {
         Ok(())
     };
 
-    if p.is_dir() {
+    if reader.is_dir(p) {
         let mut paths = vec![];
-        for entry in fs::read_dir(p)? {
-            let entry = entry?;
-            let path = entry.path();
-            if !path.is_dir() {
+        for path in reader.read_dir(p)?.into_iter() {
+            if !reader.is_dir(&path) {
                 paths.push(path);
             }
         }
@@ -244,7 +267,7 @@ fn read_content(p: &Path) -> io::Result<Vec<(PathBuf, String)>> {
         for p in paths.into_iter() {
             read(p)?;
         }
-    } else if p.is_file() {
+    } else if reader.is_file(p) {
         read(p.to_path_buf())?;
     }
     if result.len() == 0 {
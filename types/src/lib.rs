@@ -26,11 +26,15 @@ mod check;
 
 mod importer;
 
+mod source;
+
 pub use check::{DeclInfo, TypeInfo};
 pub use constant::Value as ConstValue;
 pub use importer::{Config, ImportKey, Importer};
 pub use obj::EntityType;
 pub use objects::{DeclInfoKey, ObjKey, PackageKey, ScopeKey, TCObjects, TypeKey};
 pub use operand::OperandMode;
+pub use selection::SelectionKind;
+pub use source::{FsReader, MemReader, SourceRead};
 pub use typ::{identical, BasicType, ChanDir, Type};
 pub use universe::{Builtin, Universe};
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// SourceRead abstracts where the source code of the packages comes from.
+/// The Importer does all its path resolving and reading through it, so
+/// that packages can be loaded from the file system, from memory, or from
+/// anywhere else the host application keeps them.
+pub trait SourceRead {
+    /// the working directory used when Config::work_dir is not set
+    fn working_dir(&self) -> io::Result<PathBuf>;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// returns the absolute path with all intermediate components normalized
+    fn canonicalize_path(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// returns the paths of all the entries in the directory
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    fn read_file(&self, path: &Path) -> io::Result<String>;
+}
+
+/// FsReader reads source code from the file system
+pub struct FsReader {}
+
+impl FsReader {
+    pub fn new() -> FsReader {
+        FsReader {}
+    }
+}
+
+impl SourceRead for FsReader {
+    fn working_dir(&self) -> io::Result<PathBuf> {
+        env::current_dir()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn canonicalize_path(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut result = vec![];
+        for entry in fs::read_dir(path)? {
+            result.push(entry?.path());
+        }
+        Ok(result)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+/// MemReader serves source code from memory, directories are implied by the
+/// paths of the files added to it. The working directory is the empty path,
+/// so "./main.gos" and "main.gos" refer to the same file.
+pub struct MemReader {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemReader {
+    pub fn new() -> MemReader {
+        MemReader {
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn add_file(&mut self, path: &str, content: String) {
+        self.files.insert(normalize(Path::new(path)), content);
+    }
+}
+
+impl SourceRead for MemReader {
+    fn working_dir(&self) -> io::Result<PathBuf> {
+        Ok(PathBuf::new())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        let p = normalize(path);
+        self.files.keys().any(|k| k != &p && k.starts_with(&p))
+    }
+
+    fn canonicalize_path(&self, path: &Path) -> io::Result<PathBuf> {
+        Ok(normalize(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let p = normalize(path);
+        let mut result: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter_map(|k| k.strip_prefix(&p).ok())
+            .filter_map(|rel| rel.components().next())
+            .map(|c| p.join(c))
+            .collect();
+        if result.len() == 0 {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "dir not found"));
+        }
+        result.sort();
+        result.dedup();
+        Ok(result)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        self.files
+            .get(&normalize(path))
+            .cloned()
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, "file not found"))
+    }
+}
+
+/// lexically resolves "." and "..", without looking at the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
@@ -67,6 +67,7 @@ fn test_file(path: &str, trace: bool) {
         base_path: None,
         trace_parser: trace,
         trace_checker: trace,
+        host_pkgs: HashMap::new(),
     };
     let fs = &mut fe::FileSet::new();
     let asto = &mut fe::objects::Objects::new();
@@ -74,7 +75,8 @@ fn test_file(path: &str, trace: bool) {
     let tco = &mut types::TCObjects::new();
     let results = &mut HashMap::new();
 
-    let importer = &mut types::Importer::new(&config, fs, pkgs, results, asto, tco, el, 0);
+    let reader = types::FsReader::new();
+    let importer = &mut types::Importer::new(&config, &reader, fs, pkgs, results, asto, tco, el, 0);
     let key = types::ImportKey::new(path, "./");
     let _ = importer.import(&key);
 
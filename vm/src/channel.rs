@@ -5,6 +5,7 @@ use rand::prelude::*;
 use std::cell::RefCell;
 use std::mem;
 use std::rc::Rc;
+use std::task::{Poll, Waker};
 
 #[derive(Clone, Debug)]
 pub enum RendezvousState {
@@ -14,7 +15,7 @@ pub enum RendezvousState {
 }
 
 #[derive(Clone, Debug)]
-pub enum Channel {
+enum Queue {
     Bounded(
         async_channel::Sender<GosValue>,
         async_channel::Receiver<GosValue>,
@@ -22,46 +23,72 @@ pub enum Channel {
     Rendezvous(Rc<RefCell<RendezvousState>>),
 }
 
+/// Channel is the queue of a Go channel, shared by its clones. The goroutines
+/// and the host waiting for it are parked, and woken when it changes.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    queue: Queue,
+    // the wakers of the tasks waiting for the channel, they are all woken
+    // when a value is sent or received, or the channel is closed
+    waiters: Rc<RefCell<Vec<Waker>>>,
+}
+
 impl Channel {
     pub fn new(cap: usize) -> Channel {
-        if cap == 0 {
-            Channel::Rendezvous(Rc::new(RefCell::new(RendezvousState::Empty)))
+        let queue = if cap == 0 {
+            Queue::Rendezvous(Rc::new(RefCell::new(RendezvousState::Empty)))
         } else {
             let (s, r) = async_channel::bounded(cap);
-            Channel::Bounded(s, r)
+            Queue::Bounded(s, r)
+        };
+        Channel {
+            queue: queue,
+            waiters: Rc::new(RefCell::new(vec![])),
         }
     }
 
     #[inline]
     pub fn len(&self) -> usize {
-        match self {
-            Channel::Bounded(s, _) => s.len(),
-            Channel::Rendezvous(_) => 0,
+        match &self.queue {
+            Queue::Bounded(s, _) => s.len(),
+            Queue::Rendezvous(_) => 0,
         }
     }
 
     #[inline]
     pub fn cap(&self) -> usize {
-        match self {
-            Channel::Bounded(s, _) => s.capacity().unwrap(),
-            Channel::Rendezvous(_) => 0,
+        match &self.queue {
+            Queue::Bounded(s, _) => s.capacity().unwrap(),
+            Queue::Rendezvous(_) => 0,
         }
     }
 
     #[inline]
     pub fn close(&self) {
-        match self {
-            Channel::Bounded(s, _) => {
+        match &self.queue {
+            Queue::Bounded(s, _) => {
                 s.close();
             }
-            Channel::Rendezvous(state) => *state.borrow_mut() = RendezvousState::Closed,
+            Queue::Rendezvous(state) => *state.borrow_mut() = RendezvousState::Closed,
+        }
+        self.wake();
+    }
+
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        match &self.queue {
+            Queue::Bounded(s, _) => s.is_closed(),
+            Queue::Rendezvous(state) => match &*state.borrow() {
+                RendezvousState::Closed => true,
+                _ => false,
+            },
         }
     }
 
     pub fn try_send(&self, v: GosValue) -> Result<(), async_channel::TrySendError<GosValue>> {
-        match self {
-            Channel::Bounded(s, _) => s.try_send(v),
-            Channel::Rendezvous(state) => {
+        let result = match &self.queue {
+            Queue::Bounded(s, _) => s.try_send(v),
+            Queue::Rendezvous(state) => {
                 let state_ref = state.borrow();
                 let s: &RendezvousState = &state_ref;
                 match s {
@@ -74,13 +101,17 @@ impl Channel {
                     RendezvousState::Closed => Err(async_channel::TrySendError::Closed(v)),
                 }
             }
+        };
+        if result.is_ok() {
+            self.wake();
         }
+        result
     }
 
     pub fn try_recv(&self) -> Result<GosValue, async_channel::TryRecvError> {
-        match self {
-            Channel::Bounded(_, r) => r.try_recv(),
-            Channel::Rendezvous(state) => {
+        let result = match &self.queue {
+            Queue::Bounded(_, r) => r.try_recv(),
+            Queue::Rendezvous(state) => {
                 let state_ref = state.borrow();
                 let s: &RendezvousState = &state_ref;
                 match s {
@@ -98,36 +129,66 @@ impl Channel {
                     RendezvousState::Closed => Err(async_channel::TryRecvError::Closed),
                 }
             }
+        };
+        if result.is_ok() {
+            self.wake();
         }
+        result
     }
 
     pub async fn send(&self, v: &GosValue) -> EmptyResult {
-        loop {
-            match self.try_send(v.clone()) {
-                Ok(()) => return Ok(()),
-                Err(e) => match e {
-                    async_channel::TrySendError::Full(_) => {
-                        future::yield_now().await;
-                    }
-                    async_channel::TrySendError::Closed(_) => {
-                        return Err("channel closed!".to_string());
-                    }
-                },
-            }
+        self.wait_for(|| match self.try_send(v.clone()) {
+            Ok(()) => Some(Ok(())),
+            Err(async_channel::TrySendError::Full(_)) => None,
+            Err(async_channel::TrySendError::Closed(_)) => Some(Err("channel closed!".to_string())),
+        })
+        .await?;
+        // without a buffer, the value is handed over only when it's
+        // received, so that closing the channel then doesn't drop it
+        if let Queue::Rendezvous(state) = &self.queue {
+            self.wait_for(|| match &*state.borrow() {
+                RendezvousState::Full(_) => None,
+                _ => Some(()),
+            })
+            .await;
         }
+        Ok(())
     }
 
     pub async fn recv(&self) -> Option<GosValue> {
-        loop {
-            match self.try_recv() {
-                Ok(v) => return Some(v),
-                Err(e) => match e {
-                    async_channel::TryRecvError::Empty => {
-                        future::yield_now().await;
-                    }
-                    async_channel::TryRecvError::Closed => return None,
-                },
+        self.wait_for(|| match self.try_recv() {
+            Ok(v) => Some(Some(v)),
+            Err(async_channel::TryRecvError::Empty) => None,
+            Err(async_channel::TryRecvError::Closed) => Some(None),
+        })
+        .await
+    }
+
+    // has w woken the next time the channel changes
+    fn wake_on(&self, w: &Waker) {
+        let mut waiters = self.waiters.borrow_mut();
+        if !waiters.iter().any(|x| x.will_wake(w)) {
+            waiters.push(w.clone());
+        }
+    }
+
+    // waits until f returns a value, it's called again whenever the channel
+    // changes
+    async fn wait_for<T>(&self, mut f: impl FnMut() -> Option<T>) -> T {
+        future::poll_fn(|cx| match f() {
+            Some(v) => Poll::Ready(v),
+            None => {
+                self.wake_on(cx.waker());
+                Poll::Pending
             }
+        })
+        .await
+    }
+
+    fn wake(&self) {
+        let waiters = mem::take(&mut *self.waiters.borrow_mut());
+        for w in waiters.into_iter() {
+            w.wake();
         }
     }
 }
@@ -153,7 +214,7 @@ impl Selector {
     pub async fn select(&self) -> RuntimeResult<(usize, Option<GosValue>)> {
         let count = self.comms.len();
         let mut rng = rand::thread_rng();
-        loop {
+        future::poll_fn(|cx| {
             for (i, entry) in self
                 .comms
                 .iter()
@@ -161,31 +222,88 @@ impl Selector {
                 .choose_multiple(&mut rng, count)
             {
                 match entry {
+                    // a nil channel is never ready
+                    SelectComm::Send(GosValue::Nil(_), _, _)
+                    | SelectComm::Recv(GosValue::Nil(_), _, _) => {}
                     SelectComm::Send(c, val, _) => {
                         match c.as_channel().chan.try_send(val.clone()) {
-                            Ok(_) => return Ok((i, None)),
+                            Ok(_) => return Poll::Ready(Ok((i, None))),
                             Err(e) => match e {
                                 async_channel::TrySendError::Full(_) => {}
                                 async_channel::TrySendError::Closed(_) => {
-                                    return Err("channel closed!".to_string());
+                                    return Poll::Ready(Err("channel closed!".to_string()));
                                 }
                             },
                         }
                     }
                     SelectComm::Recv(c, _, _) => match c.as_channel().chan.try_recv() {
-                        Ok(v) => return Ok((i, Some(v))),
+                        Ok(v) => return Poll::Ready(Ok((i, Some(v)))),
                         Err(e) => match e {
                             async_channel::TryRecvError::Empty => {}
-                            async_channel::TryRecvError::Closed => return Ok((i, None)),
+                            async_channel::TryRecvError::Closed => {
+                                return Poll::Ready(Ok((i, None)))
+                            }
                         },
                     },
                 }
             }
 
             if let Some(_) = self.default_offset {
-                return Ok((self.comms.len(), None));
+                return Poll::Ready(Ok((self.comms.len(), None)));
+            }
+            for entry in self.comms.iter() {
+                match entry {
+                    SelectComm::Send(GosValue::Nil(_), _, _)
+                    | SelectComm::Recv(GosValue::Nil(_), _, _) => {}
+                    SelectComm::Send(c, _, _) | SelectComm::Recv(c, _, _) => {
+                        c.as_channel().chan.wake_on(cx.waker())
+                    }
+                }
             }
-            future::yield_now().await;
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::future::Future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Wake};
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
         }
     }
+
+    #[test]
+    fn test_parked() {
+        let count = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(count.clone());
+        let mut cx = Context::from_waker(&waker);
+        let chan = Channel::new(0);
+        let mut recv = Box::pin(chan.recv());
+        assert!(recv.as_mut().poll(&mut cx).is_pending());
+        assert!(recv.as_mut().poll(&mut cx).is_pending());
+        // the receiver is woken once, when a value is sent
+        assert_eq!(count.0.load(Ordering::SeqCst), 0);
+        chan.try_send(GosValue::Int(1)).unwrap();
+        assert_eq!(count.0.load(Ordering::SeqCst), 1);
+        match recv.as_mut().poll(&mut cx) {
+            Poll::Ready(Some(GosValue::Int(1))) => {}
+            _ => panic!("expected the value sent"),
+        }
+
+        let mut recv = Box::pin(chan.recv());
+        assert!(recv.as_mut().poll(&mut cx).is_pending());
+        chan.close();
+        assert_eq!(count.0.load(Ordering::SeqCst), 2);
+        assert!(matches!(recv.as_mut().poll(&mut cx), Poll::Ready(None)));
+    }
 }
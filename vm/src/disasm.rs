@@ -0,0 +1,517 @@
+/// The disassembler prints the functions of a ByteCode in a human readable
+/// form: the signature, the counts of params/results/locals, the constants
+/// and the up-values, followed by the decoded instructions, with jump
+/// targets turned into labels and the source lines interleaved when the
+/// FileSet is available.
+use super::instruction::{Instruction, OpIndex, Opcode, T2Index, ValueType};
+use super::metadata::*;
+use super::value::*;
+use super::vm::ByteCode;
+use goscript_parser::{FileSet, Position};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// deeper metadata is printed as "..."
+const MAX_META_DEPTH: usize = 3;
+
+/// disassemble writes the disassembly of every function in bc to w.
+/// With fs, every group of instructions is preceded by the file name and line
+/// it's generated from, and the text of the line if source returns the content
+/// of the file.
+pub fn disassemble(
+    bc: &ByteCode,
+    fs: Option<&FileSet>,
+    source: Option<&dyn Fn(&str) -> Option<String>>,
+    w: &mut dyn Write,
+) -> io::Result<()> {
+    let d = Disassembler {
+        bc: bc,
+        fs: fs,
+        source: source,
+        names: func_names(bc),
+        lines: RefCell::new(HashMap::new()),
+    };
+    for (i, (key, func)) in bc.objects.functions.iter().enumerate() {
+        if i > 0 {
+            writeln!(w)?;
+        }
+        d.write_func(key, func, w)?;
+    }
+    Ok(())
+}
+
+/// func_names names all the functions in bc, the way Go names them:
+/// "pkg.Func" for package level functions, "pkg.init" for the package
+/// constructors, "pkg.Func.func1" for function literals, and "entry" for
+/// the generated entry function. The receiver type of a method is not
+/// recorded in the ByteCode, so methods are named "pkg.Method (method)".
+pub fn func_names(bc: &ByteCode) -> HashMap<FunctionKey, String> {
+    let objs = &bc.objects;
+    let mut names = HashMap::new();
+    names.insert(bc.entry, "entry".to_string());
+    for (_, pkg) in objs.packages.iter() {
+        let (members, indices, _, _) = pkg.parts();
+        for (name, &i) in indices.iter() {
+            if let GosValue::Closure(c) = &members[i as usize] {
+                if let Some(f) = c.0.borrow().func {
+                    let n = if i == 0 { "init" } else { name.as_str() };
+                    names.insert(f, format!("{}.{}", pkg.name(), n));
+                }
+            }
+        }
+    }
+    for (_, meta) in objs.metas.iter() {
+        if let MetadataType::Named(methods, _) = meta {
+            for (name, &i) in methods.mapping.iter() {
+                if let Some(f) = methods.members[i as usize].borrow().func {
+                    if let Some(pkg) = objs.packages.get(objs.functions[f].package) {
+                        names.insert(f, format!("{}.{} (method)", pkg.name(), name));
+                    }
+                }
+            }
+        }
+    }
+    // function literals are in the constants of the enclosing function,
+    // they are named after it, so keep going until nothing new is found
+    loop {
+        let mut found = vec![];
+        for (key, func) in objs.functions.iter() {
+            if let Some(parent) = names.get(&key) {
+                let mut count = 0;
+                for c in func.consts.iter() {
+                    if let GosValue::Function(f) = c {
+                        count += 1;
+                        if !names.contains_key(f) {
+                            found.push((*f, format!("{}.func{}", parent, count)));
+                        }
+                    }
+                }
+            }
+        }
+        if found.is_empty() {
+            break;
+        }
+        names.extend(found);
+    }
+    for (i, (key, _)) in objs.functions.iter().enumerate() {
+        names.entry(key).or_insert(format!("func#{}", i));
+    }
+    names
+}
+
+struct Disassembler<'a> {
+    bc: &'a ByteCode,
+    fs: Option<&'a FileSet>,
+    source: Option<&'a dyn Fn(&str) -> Option<String>>,
+    names: HashMap<FunctionKey, String>,
+    // the lines of the source files, None if source can't provide it
+    lines: RefCell<HashMap<Rc<String>, Option<Vec<String>>>>,
+}
+
+impl<'a> Disassembler<'a> {
+    fn write_func(
+        &self,
+        key: FunctionKey,
+        func: &FunctionVal,
+        w: &mut dyn Write,
+    ) -> io::Result<()> {
+        let objs = &self.bc.objects;
+        writeln!(
+            w,
+            "func {} {}",
+            self.names[&key],
+            self.meta_text(&func.meta, 0)
+        )?;
+        // the entry function doesn't belong to any package
+        let pkg = objs.packages.get(func.package).map_or("-", |p| p.name());
+        writeln!(w, "    package: {}, flag: {:?}", pkg, func.flag)?;
+        writeln!(
+            w,
+            "    params: {}, results: {}, locals: {}",
+            func.param_count(),
+            func.ret_count(),
+            func.local_count()
+        )?;
+        if !func.consts.is_empty() {
+            writeln!(w, "    consts:")?;
+            for (i, c) in func.consts.iter().enumerate() {
+                writeln!(w, "        #{:<4} {}", i, self.value_text(c))?;
+            }
+        }
+        if !func.up_ptrs.is_empty() {
+            writeln!(w, "    upvalues:")?;
+            for (i, uv) in func.up_ptrs.iter().enumerate() {
+                let kind = if uv.is_up_value { "upvalue" } else { "local" };
+                writeln!(
+                    w,
+                    "        ^{:<4} {} {} of {}, {:?}",
+                    i,
+                    kind,
+                    uv.index,
+                    self.func_name(uv.func),
+                    uv.typ
+                )?;
+            }
+        }
+        writeln!(w, "    code:")?;
+        self.write_code(func, w)
+    }
+
+    fn write_code(&self, func: &FunctionVal, w: &mut dyn Write) -> io::Result<()> {
+        let code = func.code();
+        let labels = jump_labels(code);
+        let mut last_line: Option<(Rc<String>, usize)> = None;
+        let mut i = 0;
+        while i < code.len() {
+            if let Some(p) = self.position(func.pos()[i]) {
+                let cur = (p.filename.clone(), p.line);
+                if last_line.as_ref() != Some(&cur) {
+                    match self.line_text(&p) {
+                        Some(text) => writeln!(w, "  {}:{}    {}", p.filename, p.line, text)?,
+                        None => writeln!(w, "  {}:{}", p.filename, p.line)?,
+                    }
+                    last_line = Some(cur);
+                }
+            }
+            if let Some(l) = labels.get(&i) {
+                writeln!(w, "  L{}:", l)?;
+            }
+            let inst = &code[i];
+            let (mnemonic, operands) = self.inst_text(func, i, &labels);
+            writeln!(w, "    {:<6}{:<24}{}", i, mnemonic, operands)?;
+            // the package key that follows the instruction
+            if takes_pkg_word(inst) && i + 1 < code.len() {
+                let pkg = u64_to_key(code[i + 1].get_u64());
+                let name = match self.bc.objects.packages.get(pkg) {
+                    Some(p) => p.name(),
+                    None => "<invalid package>",
+                };
+                writeln!(w, "    {:<6}{:<24}{}", i + 1, ".pkg", name)?;
+                i += 1;
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    fn inst_text(
+        &self,
+        func: &FunctionVal,
+        i: usize,
+        labels: &HashMap<usize, usize>,
+    ) -> (String, String) {
+        let inst = &func.code()[i];
+        let op = match opcode(inst) {
+            Some(op) => op,
+            None => return (".word".to_string(), format!("{:#018x}", inst.get_u64())),
+        };
+        let mut operands: Vec<String> = vec![];
+        let bits = inst.get_u64();
+        // t2 is used as an index by some instructions, so they are read raw
+        let types = [(bits >> 48) as u8, (bits >> 40) as u8, (bits >> 32) as u8];
+        let type_count = match op.t2_index() {
+            Some(_) => 2,
+            None => 3,
+        };
+        let type_names: Vec<String> = types[..type_count]
+            .iter()
+            .filter(|&&t| t != 0)
+            .map(|&t| match ValueType::from_u8(t) {
+                Some(vt) => format!("{:?}", vt),
+                None => format!("?{}", t),
+            })
+            .collect();
+        if !type_names.is_empty() {
+            operands.push(type_names.join(","));
+        }
+        match op.t2_index() {
+            Some(T2Index::CommaOk) if inst.t2_as_index() > 0 => {
+                operands.push("comma_ok".to_string())
+            }
+            Some(T2Index::Index) => operands.push(format!("[{}]", inst.t2_as_index())),
+            _ => {}
+        }
+        let imm = inst.imm();
+        match op {
+            Opcode::PUSH_CONST => match func.consts.get(imm as usize) {
+                Some(c) => operands.push(format!("#{} ({})", imm, self.value_text(c))),
+                None => operands.push(format!("#{}", imm)),
+            },
+            Opcode::PUSH_IMM => operands.push(format!("{}", imm)),
+            Opcode::LOAD_LOCAL | Opcode::REF_LOCAL => operands.push(format!("local {}", imm)),
+            Opcode::LOAD_UPVALUE | Opcode::REF_UPVALUE => operands.push(format!("^{}", imm)),
+            Opcode::LOAD_PKG_FIELD | Opcode::REF_PKG_MEMBER => {
+                operands.push(format!("member {}", self.member_text(func, i, imm)))
+            }
+            Opcode::JUMP
+            | Opcode::JUMP_IF
+            | Opcode::JUMP_IF_NOT
+            | Opcode::SWITCH
+            | Opcode::RANGE => match jump_target(i, imm).and_then(|t| labels.get(&t)) {
+                Some(l) => operands.push(format!("-> L{}", l)),
+                None => operands.push(format!("-> {:+}", imm)),
+            },
+            Opcode::STORE_LOCAL
+            | Opcode::STORE_UPVALUE
+            | Opcode::STORE_INDEX
+            | Opcode::STORE_INDEX_IMM
+            | Opcode::STORE_FIELD
+            | Opcode::STORE_STRUCT_FIELD
+            | Opcode::STORE_PKG_FIELD
+            | Opcode::STORE_DEREF => {
+                let (i0, i1) = inst.imm824();
+                // a non-negative i0 is the operator of an assignment like +=
+                if i0 >= 0 {
+                    match Opcode::from_u8(i0 as u8) {
+                        Some(ex) => operands.push(format!("op {}", ex.text())),
+                        None => operands.push(format!("op ?{}", i0)),
+                    }
+                } else {
+                    operands.push(format!("rhs {}", i0));
+                }
+                match op {
+                    Opcode::STORE_LOCAL => operands.push(format!("local {}", i1)),
+                    Opcode::STORE_UPVALUE => operands.push(format!("^{}", i1)),
+                    Opcode::STORE_PKG_FIELD => {
+                        operands.push(format!("member {}", self.member_text(func, i, i1)))
+                    }
+                    _ => operands.push(format!("{}", i1)),
+                }
+            }
+            Opcode::CAST => {
+                let (i0, i1) = inst.imm824();
+                operands.push(format!("{} {}", i0, i1));
+            }
+            Opcode::SELECT => {
+                let first = i == 0 || opcode(&func.code()[i - 1]) != Some(Opcode::SELECT);
+                if first {
+                    operands.push(format!("blocks {}", imm));
+                } else {
+                    operands.push(format!("block {:+}", imm));
+                }
+            }
+            _ => {
+                if imm != 0 {
+                    operands.push(format!("{}", imm));
+                }
+            }
+        }
+        (op.text().to_string(), operands.join("  "))
+    }
+
+    fn member_text(&self, func: &FunctionVal, i: usize, index: OpIndex) -> String {
+        let code = func.code();
+        if i + 1 < code.len() {
+            let pkg = u64_to_key(code[i + 1].get_u64());
+            if let Some(p) = self.bc.objects.packages.get(pkg) {
+                let (_, indices, _, _) = p.parts();
+                if let Some((name, _)) = indices.iter().find(|(_, &v)| v == index) {
+                    // the 0th member is the constructor, it's unnamed
+                    let name = if index == 0 { "init" } else { name.as_str() };
+                    return format!("{} ({}.{})", index, p.name(), name);
+                }
+            }
+        }
+        format!("{}", index)
+    }
+
+    fn value_text(&self, val: &GosValue) -> String {
+        match val {
+            GosValue::Str(s) => format!("string {:?}", s.as_str()),
+            GosValue::Function(f) => format!("function {}", self.func_name(*f)),
+            GosValue::Closure(c) => match c.0.borrow().func {
+                Some(f) => format!("closure {}", self.func_name(f)),
+                None => "closure <ffi>".to_string(),
+            },
+            GosValue::Metadata(m) => format!("meta {}", self.meta_text(m, 0)),
+            GosValue::Package(p) => match self.bc.objects.packages.get(*p) {
+                Some(pkg) => format!("package {}", pkg.name()),
+                None => "package <invalid>".to_string(),
+            },
+            _ => format!("{:?} {}", val.get_type(), val),
+        }
+    }
+
+    fn func_name(&self, f: FunctionKey) -> &str {
+        self.names
+            .get(&f)
+            .map(|x| x.as_str())
+            .unwrap_or("<invalid function>")
+    }
+
+    fn meta_text(&self, meta: &GosMetadata, depth: usize) -> String {
+        let (key, category, ptr) = match meta {
+            GosMetadata::Untyped => return "untyped".to_string(),
+            GosMetadata::NonPtr(k, c) => (k, c, 0),
+            GosMetadata::Ptr1(k, c) => (k, c, 1),
+            GosMetadata::Ptr2(k, c) => (k, c, 2),
+            GosMetadata::Ptr3(k, c) => (k, c, 3),
+            GosMetadata::Ptr4(k, c) => (k, c, 4),
+            GosMetadata::Ptr5(k, c) => (k, c, 5),
+            GosMetadata::Ptr6(k, c) => (k, c, 6),
+            GosMetadata::Ptr7(k, c) => (k, c, 7),
+        };
+        if depth > MAX_META_DEPTH {
+            return "...".to_string();
+        }
+        let metas = &self.bc.objects.metas;
+        let mt = match metas.get(*key) {
+            Some(mt) => mt,
+            None => return "<invalid metadata>".to_string(),
+        };
+        let list = |ms: &[GosMetadata]| {
+            ms.iter()
+                .map(|m| self.meta_text(m, depth + 1))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        let names = |mapping: &HashMap<String, OpIndex>| {
+            let mut ns: Vec<(&OpIndex, &String)> = mapping.iter().map(|(k, v)| (v, k)).collect();
+            ns.sort();
+            ns.into_iter()
+                .map(|(_, n)| n.clone())
+                .collect::<Vec<String>>()
+        };
+        let text = match mt {
+            MetadataType::Bool => "bool".to_string(),
+            MetadataType::Int => "int".to_string(),
+            MetadataType::Int8 => "int8".to_string(),
+            MetadataType::Int16 => "int16".to_string(),
+            MetadataType::Int32 => "int32".to_string(),
+            MetadataType::Int64 => "int64".to_string(),
+            MetadataType::Uint => "uint".to_string(),
+            MetadataType::Uint8 => "uint8".to_string(),
+            MetadataType::Uint16 => "uint16".to_string(),
+            MetadataType::Uint32 => "uint32".to_string(),
+            MetadataType::Uint64 => "uint64".to_string(),
+            MetadataType::Float32 => "float32".to_string(),
+            MetadataType::Float64 => "float64".to_string(),
+            MetadataType::Complex64 => "complex64".to_string(),
+            MetadataType::Complex128 => "complex128".to_string(),
+            MetadataType::Str(_) => "string".to_string(),
+            MetadataType::SliceOrArray(m, size) => match category {
+                MetaCategory::Array | MetaCategory::ArrayType => {
+                    format!("[{}]{}", size, self.meta_text(m, depth + 1))
+                }
+                _ => format!("[]{}", self.meta_text(m, depth + 1)),
+            },
+            MetadataType::Struct(f, _) => {
+                let fields: Vec<String> = names(&f.mapping)
+                    .into_iter()
+                    .zip(f.fields.iter())
+                    .map(|(n, m)| format!("{} {}", n, self.meta_text(m, depth + 1)))
+                    .collect();
+                format!("struct{{{}}}", fields.join("; "))
+            }
+            MetadataType::Signature(sig) => {
+                let recv = match &sig.recv {
+                    Some(r) => format!(" ({}) ", self.meta_text(r, depth + 1)),
+                    None => "".to_string(),
+                };
+                let results = match sig.results.len() {
+                    0 => "".to_string(),
+                    1 => format!(" {}", list(&sig.results)),
+                    _ => format!(" ({})", list(&sig.results)),
+                };
+                format!("func{}({}){}", recv, list(&sig.params), results)
+            }
+            MetadataType::Map(k, v) => format!(
+                "map[{}]{}",
+                self.meta_text(k, depth + 1),
+                self.meta_text(v, depth + 1)
+            ),
+            MetadataType::Interface(f) => format!("interface{{{}}}", names(&f.mapping).join("; ")),
+            MetadataType::Channel(t, m) => {
+                let prefix = match t {
+                    ChannelType::Send => "chan<- ",
+                    ChannelType::Recv => "<-chan ",
+                    ChannelType::SendRecv => "chan ",
+                };
+                format!("{}{}", prefix, self.meta_text(m, depth + 1))
+            }
+            MetadataType::Named(_, m) => format!("named({})", self.meta_text(m, depth + 1)),
+        };
+        let prefix = match category {
+            MetaCategory::Type | MetaCategory::ArrayType => "type ",
+            _ => "",
+        };
+        format!("{}{}{}", prefix, "*".repeat(ptr), text)
+    }
+
+    fn position(&self, pos: Option<usize>) -> Option<Position> {
+        let p = self.fs?.position(pos?);
+        if p.line > 0 {
+            Some(p)
+        } else {
+            None
+        }
+    }
+
+    fn line_text(&self, p: &Position) -> Option<String> {
+        let source = self.source?;
+        let mut lines = self.lines.borrow_mut();
+        let file_lines = lines
+            .entry(p.filename.clone())
+            .or_insert_with(|| source(&p.filename).map(|s| s.lines().map(String::from).collect()));
+        file_lines
+            .as_ref()
+            .and_then(|l| l.get(p.line - 1))
+            .map(|l| l.trim().to_string())
+    }
+}
+
+fn opcode(inst: &Instruction) -> Option<Opcode> {
+    Opcode::from_u8((inst.get_u64() >> 56) as u8)
+}
+
+// these instructions are followed by a package key instead of an instruction
+fn takes_pkg_word(inst: &Instruction) -> bool {
+    opcode(inst).map_or(false, |op| op.takes_pkg_key())
+}
+
+// jumps are relative to the next instruction
+fn jump_target(i: usize, offset: OpIndex) -> Option<usize> {
+    let t = i as i64 + 1 + offset as i64;
+    if t >= 0 {
+        Some(t as usize)
+    } else {
+        None
+    }
+}
+
+// maps the index of every jump target to its label number, numbered in the
+// order of the code
+fn jump_labels(code: &Vec<Instruction>) -> HashMap<usize, usize> {
+    let mut targets = vec![];
+    let mut i = 0;
+    while i < code.len() {
+        let inst = &code[i];
+        match opcode(inst) {
+            Some(Opcode::JUMP)
+            | Some(Opcode::JUMP_IF)
+            | Some(Opcode::JUMP_IF_NOT)
+            | Some(Opcode::SWITCH)
+            | Some(Opcode::RANGE) => {
+                if let Some(t) = jump_target(i, inst.imm()) {
+                    if t <= code.len() {
+                        targets.push(t);
+                    }
+                }
+            }
+            _ => {}
+        }
+        if takes_pkg_word(inst) {
+            i += 1;
+        }
+        i += 1;
+    }
+    targets.sort();
+    targets.dedup();
+    targets
+        .into_iter()
+        .enumerate()
+        .map(|(l, t)| (t, l))
+        .collect()
+}
@@ -1,14 +1,348 @@
+//! FFI lets Go code call into Rust.
+//!
+//! On the Go side, an FFI object is created with the builtin `ffi`, which
+//! takes an interface type and the name the object is registered with, and
+//! its methods are called like the methods of any interface:
+//!
+//! ```go
+//! type ffiMath interface {
+//!     add(a, b int) int
+//!     split(s string) (string, bool)
+//! }
+//!
+//! var m = ffi(ffiMath, "math")
+//! ```
+//!
+//! On the Rust side, an FFI object either implements Ffi and is registered
+//! with FfiFactory::register, or is made of plain Rust functions registered
+//! with FfiFactory::register_fn, whose arguments and results are converted
+//! with FromGos and ToGos:
+//!
+//! ```ignore
+//! factory.register_fn("math", "add", |a: i64, b: i64| a + b);
+//! factory.register_fn("math", "split", |s: String| (s.clone(), s.is_empty()));
+//! ```
+//!
+//! An FFI call that fails becomes a Go panic at the call site, which can be
+//! recovered by the Go code. A Rust function returning a Result instead
+//! returns an error value, if the last result of the Go method is of type
+//! error, with the text of the Rust error.
+//!
+//! Go func values passed to an FFI call can be called back with FfiCtx::call
+//! before the call returns, a function registered with register_fn gets the
+//! FfiCtx if it's the first parameter:
+//!
+//! ```ignore
+//! factory.register_fn("host", "each", |ctx: &FfiCtx, n: i64, f: GosValue| {
+//!     for i in 0..n {
+//!         ctx.call(&f, vec![GosValue::Int(i as isize)])?;
+//!     }
+//!     Ok::<(), String>(())
+//! });
+//! ```
+//!
+//! A panic not recovered by the callback is returned to Rust as an Err with
+//! the panic message. To call a func value after the FFI call has returned,
+//! keep it and call it with GosVM::call_closure.
+//!
+//! A Go function declared without a body is implemented by the Rust function
+//! registered with FfiFactory::register_native under "pkgpath.FuncName", its
+//! arguments and results are converted the same way:
+//!
+//! ```ignore
+//! // func Sqrt(x float64) float64
+//! factory.register_native("math.Sqrt", f64::sqrt);
+//! ```
+//!
+//! The VM runs nothing if any of them is not registered, see
+//! ByteCode::missing_natives. Their Go signatures are checked against the
+//! Rust functions with FromGos::converts_from and ToGos::converts_to, see
+//! ByteCode::mismatched_natives.
+//!
+//! Besides calling closures back, FfiCtx gives the FFI code the runtime of
+//! the VM, like its timers, its output and the variables Go pointers point
+//! to, the std packages implemented in Rust are built on them:
+//!
+//! ```ignore
+//! // func AddInt64(addr *int64, delta int64) int64
+//! factory.register_native("sync/atomic.AddInt64", |ctx: &FfiCtx, addr: GosValue, d: i64| {
+//!     let n = i64::from_gos(ctx.load(&addr)?)? + d;
+//!     ctx.store(&addr, GosValue::Int64(n)).map(|_| n)
+//! });
+//! ```
+//!
+//! A Rust object implementing Ffi, as an `Rc<RefCell<T>>`, converts to a Go
+//! interface value, which Go code can store, compare and use in type switches
+//! like any other, and converts back to the same object when it's passed to
+//! Rust. Its dynamic type in Go is the interface type it's converted to.
+//!
+//! A Rust function returning a future is registered with register_async_fn
+//! or register_async_native, the goroutine calling it waits for the future
+//! while the other goroutines keep running:
+//!
+//! ```ignore
+//! factory.register_async_fn("net", "get", |url: String| async move {
+//!     fetch(&url).await.map_err(|e| e.to_string())
+//! });
+//! ```
+use super::channel::Channel;
+use super::gc::GcoVec;
+use super::instruction::ValueType;
+use super::metadata::{Fields, GosMetadata, MetaCategory, MetadataType};
+use super::objects::{ChannelObj, IfaceUnderlying, MetadataObjs, UnderlyingFfi};
 use super::value::GosValue;
-use std::cell::RefCell;
+use futures_lite::future;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::future::Future;
+use std::hash::Hash;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::pin::Pin;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 pub type FfiResult<T> = std::result::Result<T, String>;
 
+/// FfiFuture is what an async FFI call returns, the results it resolves to
+/// are converted with the FfiCtx of the call.
+pub type FfiFuture<'a> = Pin<Box<dyn Future<Output = FfiResult<Vec<GosValue>>> + 'a>>;
+
 pub type Ctor = dyn Fn(Vec<GosValue>) -> FfiResult<Rc<RefCell<dyn Ffi>>>;
 
+/// Runtime is what the VM does for the FFI code, through FfiCtx: it calls
+/// Go closures back, ends the program, lets goroutines sleep, runs timers,
+/// gives the output of the program, reads and writes through Go pointers and
+/// keeps goroutines from being preempted. It's implemented by the VM.
+pub trait Runtime {
+    /// call_closure runs cls on a new fiber until it returns. The FFI call
+    /// calling back is not async, so the thread is blocked while nothing but
+    /// a timer or an async FFI call can get cls going. That's not done to
+    /// the executor of the host, see GosVM::run_async, the call fails then.
+    fn call_closure(&self, cls: &GosValue, args: Vec<GosValue>) -> FfiResult<Vec<GosValue>>;
+
+    fn exit(&self, code: i32);
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()>>>;
+
+    fn start_timer(&self, deadline: Instant, period: Option<Duration>, f: GosValue) -> u64;
+
+    fn stop_timer(&self, id: u64) -> bool;
+
+    fn stdout(&self) -> Rc<RefCell<dyn Write>>;
+
+    fn load(&self, ptr: &GosValue) -> FfiResult<GosValue>;
+
+    fn store(&self, ptr: &GosValue, val: GosValue) -> FfiResult<()>;
+
+    fn set_atomic(&self, on: bool);
+}
+
+/// FfiCtx is what an FFI call is given besides the arguments, with it the
+/// results can be built, including the ones that need type info, like slices
+/// and maps.
+#[derive(Clone, Copy)]
+pub struct FfiCtx<'a> {
+    // the name of the method called
+    pub func_name: &'a str,
+    pub metas: &'a MetadataObjs,
+    pub gcv: &'a GcoVec,
+    // the types of the results of the method called
+    pub results: &'a [GosMetadata],
+    pub runtime: &'a dyn Runtime,
+}
+
+impl<'a> FfiCtx<'a> {
+    /// call calls the Go closure cls with args and returns its results. It
+    /// runs on a new fiber, other fibers may run before it returns. Under
+    /// GosVM::run_async, it fails if cls waits for a timer or an async FFI
+    /// call, see Runtime::call_closure.
+    pub fn call(&self, cls: &GosValue, args: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+        self.runtime.call_closure(cls, args)
+    }
+
+    /// exit ends the program with the exit code code when the call returns,
+    /// like os.Exit, no deferred calls are run.
+    pub fn exit(&self, code: i32) {
+        self.runtime.exit(code)
+    }
+
+    /// sleep_until returns a future ready at deadline, for async FFI calls.
+    /// The goroutine waiting for it is woken by the scheduler of the VM, and
+    /// is not taken as deadlocked meanwhile.
+    pub fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()>>> {
+        self.runtime.sleep_until(deadline)
+    }
+
+    /// start_timer starts a timer running the Go closure f, which takes no
+    /// arguments, on a new goroutine at deadline, and every period after that
+    /// if there is one. It returns the id of the timer, for stop_timer. The
+    /// goroutines are not taken as deadlocked while a timer is pending.
+    pub fn start_timer(&self, deadline: Instant, period: Option<Duration>, f: GosValue) -> u64 {
+        self.runtime.start_timer(deadline, period, f)
+    }
+
+    /// stop_timer stops the timer with the id, it returns false if the timer
+    /// has already been stopped, or run and has no period.
+    pub fn stop_timer(&self, id: u64) -> bool {
+        self.runtime.stop_timer(id)
+    }
+
+    /// stdout returns where the output of the script goes, like the one of
+    /// fmt.Println.
+    pub fn stdout(&self) -> Rc<RefCell<dyn Write>> {
+        self.runtime.stdout()
+    }
+
+    /// load returns the value the Go pointer ptr points to, like *ptr.
+    pub fn load(&self, ptr: &GosValue) -> FfiResult<GosValue> {
+        self.runtime.load(ptr)
+    }
+
+    /// store sets the value the Go pointer ptr points to, like *ptr = val,
+    /// val is of the type of the variable.
+    pub fn store(&self, ptr: &GosValue, val: GosValue) -> FfiResult<()> {
+        self.runtime.store(ptr, val)
+    }
+
+    /// begin_atomic keeps the current goroutine running until end_atomic is
+    /// called as many times, so that what it does meanwhile is atomic to the
+    /// other goroutines, it must not block meanwhile. It ends with the
+    /// goroutine anyway.
+    pub fn begin_atomic(&self) {
+        self.runtime.set_atomic(true)
+    }
+
+    /// end_atomic ends what begin_atomic started.
+    pub fn end_atomic(&self) {
+        self.runtime.set_atomic(false)
+    }
+
+    /// to_gos converts v to a Go value of the type meta.
+    pub fn to_gos<T: ToGos>(&self, v: T, meta: GosMetadata) -> FfiResult<GosValue> {
+        v.to_gos(self, meta)
+    }
+
+    /// from_gos converts the Go value v to T, like the arguments of the call
+    /// are, see FromGos::from_gos_typed.
+    pub fn from_gos<T: FromGos>(&self, v: GosValue) -> FfiResult<T> {
+        T::from_gos_typed(v, self.metas)
+    }
+
+    /// is_error tells if meta is an interface with nothing but the method
+    /// Error() string, like the Go type error.
+    pub fn is_error(&self, meta: GosMetadata) -> bool {
+        is_error(meta, self.metas)
+    }
+
+    /// new_error creates an error value of the type meta, which must satisfy
+    /// is_error, its method Error returns msg, like errors.New(msg) in Go.
+    pub fn new_error(&self, meta: GosMetadata, msg: String) -> FfiResult<GosValue> {
+        if !self.is_error(meta) {
+            return Err("cannot convert an error to a Go type that is not error".to_string());
+        }
+        let imeta = meta.get_underlying(self.metas);
+        let info = self.metas[imeta.as_non_ptr()]
+            .as_interface()
+            .iface_ffi_info();
+        let obj = Rc::new(RefCell::new(ErrorString { s: msg }));
+        Ok(GosValue::new_iface(
+            imeta,
+            IfaceUnderlying::Ffi(UnderlyingFfi::new(obj, info, meta)),
+        ))
+    }
+
+    /// struct_fields returns the types of the fields named names of the Go
+    /// struct type meta, which a value of rust_type converts to.
+    pub fn struct_fields(
+        &self,
+        meta: GosMetadata,
+        names: &[&str],
+        rust_type: &str,
+    ) -> FfiResult<Vec<GosMetadata>> {
+        let fields = match struct_meta(self.metas, meta) {
+            Some((fields, _)) => fields,
+            None => {
+                let vt = meta.get_value_type(self.metas);
+                return Err(format!("cannot convert {} to Go {:?}", rust_type, vt));
+            }
+        };
+        names
+            .iter()
+            .map(|n| match fields.mapping.get(*n) {
+                Some(i) => Ok(fields.fields[*i as usize]),
+                None => Err(format!(
+                    "cannot convert {} to Go struct without field {}",
+                    rust_type, n
+                )),
+            })
+            .collect()
+    }
+
+    /// new_struct creates a value of the Go struct type meta, the fields named
+    /// names are set to vals and the others are zero values.
+    pub fn new_struct(
+        &self,
+        meta: GosMetadata,
+        names: &[&str],
+        vals: Vec<GosValue>,
+    ) -> FfiResult<GosValue> {
+        self.convert(meta, "struct", |m, _| {
+            let (fields, zero) = struct_meta(self.metas, m)?;
+            let v = zero.copy_semantic(self.gcv);
+            {
+                let mut obj = v.as_struct().0.borrow_mut();
+                for (n, val) in names.iter().zip(vals.into_iter()) {
+                    obj.fields[fields.mapping[*n] as usize] = val;
+                }
+            }
+            Some(Ok(v))
+        })
+    }
+
+    // calls f with the underlying type of meta and its value type, and turns
+    // the result into a value of meta, which is a named type or the same as
+    // the underlying type
+    fn convert<F>(&self, meta: GosMetadata, rust_type: &str, f: F) -> FfiResult<GosValue>
+    where
+        F: FnOnce(GosMetadata, ValueType) -> Option<FfiResult<GosValue>>,
+    {
+        let named = match meta {
+            GosMetadata::NonPtr(k, MetaCategory::Default) => match &self.metas[k] {
+                MetadataType::Named(_, u) => Some(*u),
+                _ => None,
+            },
+            _ => None,
+        };
+        let umeta = named.unwrap_or(meta);
+        let vt = umeta.get_value_type(self.metas);
+        match f(umeta, vt) {
+            Some(v) => Ok(match named {
+                Some(_) => GosValue::Named(Box::new((v?, meta))),
+                None => v?,
+            }),
+            None => Err(format!("cannot convert {} to Go {:?}", rust_type, vt)),
+        }
+    }
+}
+
 pub trait Ffi {
-    fn call(&self, func_name: &str, params: Vec<GosValue>) -> Vec<GosValue>;
+    /// call calls the method ctx.func_name, an Err becomes a Go panic.
+    fn call(&self, ctx: &FfiCtx, params: Vec<GosValue>) -> FfiResult<Vec<GosValue>>;
+
+    /// is_async tells if the method func_name is called with call_async
+    /// instead of call.
+    fn is_async(&self, _func_name: &str) -> bool {
+        false
+    }
+
+    /// call_async is the async version of call, the goroutine calling the
+    /// method waits for the future while the others keep running.
+    fn call_async<'a>(&'a self, ctx: &'a FfiCtx<'a>, params: Vec<GosValue>) -> FfiFuture<'a> {
+        Box::pin(future::ready(self.call(ctx, params)))
+    }
 }
 
 impl std::fmt::Debug for dyn Ffi {
@@ -17,14 +351,1150 @@ impl std::fmt::Debug for dyn Ffi {
     }
 }
 
+/// FromGos converts a Go value passed to Rust. Interfaces and named types are
+/// seen through, that is, a Go interface{} holding an int converts to i64.
+pub trait FromGos: Sized {
+    fn from_gos(v: GosValue) -> FfiResult<Self>;
+
+    /// from_gos_typed converts v with the types of the VM at hand, it's what
+    /// the VM uses. The types converting from Go structs, whose fields are
+    /// matched by name, need them, the others just convert with from_gos.
+    fn from_gos_typed(v: GosValue, metas: &MetadataObjs) -> FfiResult<Self> {
+        let _ = metas;
+        Self::from_gos(v)
+    }
+
+    /// converts_from tells if the values of the Go type meta convert to Self,
+    /// it's used to check the signatures of the natives before anything is
+    /// run. It's true by default, for the types converting from any value.
+    fn converts_from(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        let _ = (meta, metas);
+        true
+    }
+}
+
+/// ToGos converts a Rust value to a Go value of the type given by the
+/// signature of the Go side.
+pub trait ToGos {
+    fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue>;
+
+    /// converts_to tells if Self converts to the Go type meta, see
+    /// FromGos::converts_from.
+    fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        let _ = (meta, metas);
+        true
+    }
+}
+
+/// ToGosResults converts what a Rust function returns to the results of a
+/// Go function: () for none, a tuple for more than one.
+pub trait ToGosResults {
+    fn to_gos_results(self, ctx: &FfiCtx) -> FfiResult<Vec<GosValue>>;
+
+    /// converts_to_results tells if Self converts to results of the Go types
+    /// results, see FromGos::converts_from.
+    fn converts_to_results(results: &[GosMetadata], metas: &MetadataObjs) -> bool;
+}
+
+// the error values created by FfiCtx::new_error
+struct ErrorString {
+    s: String,
+}
+
+impl Ffi for ErrorString {
+    fn call(&self, _: &FfiCtx, _: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+        Ok(vec![GosValue::new_str(self.s.clone())])
+    }
+}
+
+fn is_error(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+    let meta = meta.get_underlying(metas);
+    let fields = match meta {
+        GosMetadata::NonPtr(k, MetaCategory::Default) => match &metas[k] {
+            MetadataType::Interface(fields) => fields,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    match fields.iface_ffi_info().as_slice() {
+        [(name, m)] if name == "Error" => {
+            let sig = metas[m.as_non_ptr()].as_signature();
+            sig.params.is_empty()
+                && sig.results.len() == 1
+                && sig.results[0].get_value_type(metas) == ValueType::Str
+        }
+        _ => false,
+    }
+}
+
+/// is_interface tells if meta is an interface type, or a named one, whose
+/// values FromGos sees through.
+pub fn is_interface(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+    go_type(meta, metas).1 == ValueType::Interface
+}
+
+// the underlying type of meta and its value type
+fn go_type(meta: GosMetadata, metas: &MetadataObjs) -> (GosMetadata, ValueType) {
+    let u = meta.get_underlying(metas);
+    (u, u.get_value_type(metas))
+}
+
+// returns the value held by an interface or a named type, recursively
+fn see_through(v: GosValue) -> GosValue {
+    match v {
+        GosValue::Named(n) => see_through(n.0),
+        GosValue::Interface(i) => {
+            let val = i.borrow().underlying_value().cloned();
+            match val {
+                Some(val) => see_through(val),
+                None => GosValue::new_nil(),
+            }
+        }
+        _ => v,
+    }
+}
+
+fn mismatch<T>(v: &GosValue) -> String {
+    format!(
+        "cannot convert Go {:?} to {}",
+        v.get_type(),
+        std::any::type_name::<T>()
+    )
+}
+
+fn gos_to_int(v: &GosValue) -> Option<i128> {
+    match v {
+        GosValue::Int(i) => Some(*i as i128),
+        GosValue::Int8(i) => Some(*i as i128),
+        GosValue::Int16(i) => Some(*i as i128),
+        GosValue::Int32(i) => Some(*i as i128),
+        GosValue::Int64(i) => Some(*i as i128),
+        GosValue::Uint(i) => Some(*i as i128),
+        GosValue::Uint8(i) => Some(*i as i128),
+        GosValue::Uint16(i) => Some(*i as i128),
+        GosValue::Uint32(i) => Some(*i as i128),
+        GosValue::Uint64(i) => Some(*i as i128),
+        _ => None,
+    }
+}
+
+fn int_to_gos(i: i128, t: ValueType) -> Option<GosValue> {
+    Some(match t {
+        ValueType::Int => GosValue::Int(TryFrom::try_from(i).ok()?),
+        ValueType::Int8 => GosValue::Int8(TryFrom::try_from(i).ok()?),
+        ValueType::Int16 => GosValue::Int16(TryFrom::try_from(i).ok()?),
+        ValueType::Int32 => GosValue::Int32(TryFrom::try_from(i).ok()?),
+        ValueType::Int64 => GosValue::Int64(TryFrom::try_from(i).ok()?),
+        ValueType::Uint => GosValue::Uint(TryFrom::try_from(i).ok()?),
+        ValueType::Uint8 => GosValue::Uint8(TryFrom::try_from(i).ok()?),
+        ValueType::Uint16 => GosValue::Uint16(TryFrom::try_from(i).ok()?),
+        ValueType::Uint32 => GosValue::Uint32(TryFrom::try_from(i).ok()?),
+        ValueType::Uint64 => GosValue::Uint64(TryFrom::try_from(i).ok()?),
+        _ => return None,
+    })
+}
+
+macro_rules! impl_int_conversion {
+    ($($t:ty),*) => {
+        $(
+            impl FromGos for $t {
+                fn from_gos(v: GosValue) -> FfiResult<Self> {
+                    let v = see_through(v);
+                    gos_to_int(&v)
+                        .and_then(|i| <$t>::try_from(i).ok())
+                        .ok_or_else(|| mismatch::<$t>(&v))
+                }
+
+                fn converts_from(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+                    is_interface(meta, metas) || Self::converts_to(meta, metas)
+                }
+            }
+
+            impl ToGos for $t {
+                fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue> {
+                    ctx.convert(meta, stringify!($t), |_, t| int_to_gos(self as i128, t).map(Ok))
+                }
+
+                fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+                    int_to_gos(0, go_type(meta, metas).1).is_some()
+                }
+            }
+        )*
+    };
+}
+
+impl_int_conversion!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! impl_float_conversion {
+    ($($t:ty),*) => {
+        $(
+            impl FromGos for $t {
+                fn from_gos(v: GosValue) -> FfiResult<Self> {
+                    match see_through(v) {
+                        GosValue::Float32(f) => Ok(f.into_inner() as $t),
+                        GosValue::Float64(f) => Ok(f.into_inner() as $t),
+                        v => Err(mismatch::<$t>(&v)),
+                    }
+                }
+
+                fn converts_from(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+                    is_interface(meta, metas) || Self::converts_to(meta, metas)
+                }
+            }
+
+            impl ToGos for $t {
+                fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue> {
+                    ctx.convert(meta, stringify!($t), |_, t| match t {
+                        ValueType::Float32 => Some(Ok(GosValue::Float32((self as f32).into()))),
+                        ValueType::Float64 => Some(Ok(GosValue::Float64((self as f64).into()))),
+                        _ => None,
+                    })
+                }
+
+                fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+                    match go_type(meta, metas).1 {
+                        ValueType::Float32 | ValueType::Float64 => true,
+                        _ => false,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_float_conversion!(f32, f64);
+
+impl FromGos for bool {
+    fn from_gos(v: GosValue) -> FfiResult<Self> {
+        match see_through(v) {
+            GosValue::Bool(b) => Ok(b),
+            v => Err(mismatch::<bool>(&v)),
+        }
+    }
+
+    fn converts_from(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        is_interface(meta, metas) || Self::converts_to(meta, metas)
+    }
+}
+
+impl ToGos for bool {
+    fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue> {
+        ctx.convert(meta, "bool", |_, t| match t {
+            ValueType::Bool => Some(Ok(GosValue::Bool(self))),
+            _ => None,
+        })
+    }
+
+    fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        go_type(meta, metas).1 == ValueType::Bool
+    }
+}
+
+impl FromGos for String {
+    fn from_gos(v: GosValue) -> FfiResult<Self> {
+        match see_through(v) {
+            GosValue::Str(s) => Ok(s.as_str().to_string()),
+            v => Err(mismatch::<String>(&v)),
+        }
+    }
+
+    fn converts_from(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        is_interface(meta, metas) || Self::converts_to(meta, metas)
+    }
+}
+
+impl ToGos for String {
+    fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue> {
+        ctx.convert(meta, "String", |_, t| match t {
+            ValueType::Str => Some(Ok(GosValue::new_str(self))),
+            _ => None,
+        })
+    }
+
+    fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        go_type(meta, metas).1 == ValueType::Str
+    }
+}
+
+impl ToGos for &str {
+    fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue> {
+        self.to_string().to_gos(ctx, meta)
+    }
+
+    fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        String::converts_to(meta, metas)
+    }
+}
+
+/// GosValue is passed as it is, for the cases the conversions don't cover.
+impl FromGos for GosValue {
+    fn from_gos(v: GosValue) -> FfiResult<Self> {
+        Ok(v)
+    }
+}
+
+impl ToGos for GosValue {
+    fn to_gos(self, _: &FfiCtx, _: GosMetadata) -> FfiResult<GosValue> {
+        Ok(self)
+    }
+}
+
+/// A host object converts to a Go value of an interface type, which is also
+/// its dynamic type in Go, and back to the same object.
+impl<T: Ffi + 'static> FromGos for Rc<RefCell<T>> {
+    fn from_gos(v: GosValue) -> FfiResult<Self> {
+        let iface = match &v {
+            GosValue::Named(n) => &n.0,
+            _ => &v,
+        };
+        let obj = match iface {
+            GosValue::Interface(i) => match i.borrow().underlying() {
+                IfaceUnderlying::Ffi(ffi) => ffi.downcast::<T>(),
+                _ => None,
+            },
+            _ => None,
+        };
+        obj.ok_or_else(|| mismatch::<Self>(&see_through(v)))
+    }
+
+    fn converts_from(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        is_interface(meta, metas)
+    }
+}
+
+impl<T: Ffi + 'static> ToGos for Rc<RefCell<T>> {
+    fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue> {
+        ctx.convert(meta, std::any::type_name::<T>(), |m, t| match t {
+            ValueType::Interface => {
+                let info = ctx.metas[m.as_non_ptr()].as_interface().iface_ffi_info();
+                let ffi = UnderlyingFfi::new_host(self, info, meta);
+                Some(Ok(GosValue::new_iface(m, IfaceUnderlying::Ffi(ffi))))
+            }
+            _ => None,
+        })
+    }
+
+    fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        is_interface(meta, metas)
+    }
+}
+
+/// Option is None for nil.
+impl<T: FromGos> FromGos for Option<T> {
+    fn from_gos(v: GosValue) -> FfiResult<Self> {
+        if v.equals_nil() {
+            Ok(None)
+        } else {
+            T::from_gos(v).map(Some)
+        }
+    }
+
+    fn from_gos_typed(v: GosValue, metas: &MetadataObjs) -> FfiResult<Self> {
+        if v.equals_nil() {
+            Ok(None)
+        } else {
+            T::from_gos_typed(v, metas).map(Some)
+        }
+    }
+
+    fn converts_from(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        T::converts_from(meta, metas)
+    }
+}
+
+impl<T: ToGos> ToGos for Option<T> {
+    fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue> {
+        match self {
+            Some(v) => v.to_gos(ctx, meta),
+            None => Ok(meta.zero_val(ctx.metas, ctx.gcv)),
+        }
+    }
+
+    fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        T::converts_to(meta, metas)
+    }
+}
+
+/// Vec converts from Go slices and arrays, and to either of them.
+impl<T: FromGos> FromGos for Vec<T> {
+    fn from_gos(v: GosValue) -> FfiResult<Self> {
+        vec_elems::<T>(v)?.into_iter().map(T::from_gos).collect()
+    }
+
+    fn from_gos_typed(v: GosValue, metas: &MetadataObjs) -> FfiResult<Self> {
+        vec_elems::<T>(v)?
+            .into_iter()
+            .map(|x| T::from_gos_typed(x, metas))
+            .collect()
+    }
+
+    fn converts_from(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        is_interface(meta, metas)
+            || elem_type(meta, metas).map_or(false, |e| T::converts_from(e, metas))
+    }
+}
+
+// the type of the elements of the Go slice or array type meta
+fn elem_type(meta: GosMetadata, metas: &MetadataObjs) -> Option<GosMetadata> {
+    match go_type(meta, metas).0 {
+        GosMetadata::NonPtr(k, MetaCategory::Default)
+        | GosMetadata::NonPtr(k, MetaCategory::Array) => match &metas[k] {
+            MetadataType::SliceOrArray(elem, _) => Some(*elem),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// the elements of a Go slice or array converted to a Vec
+fn vec_elems<T>(v: GosValue) -> FfiResult<Vec<GosValue>> {
+    Ok(match see_through(v) {
+        GosValue::Slice(s) if s.0.is_nil() => vec![],
+        GosValue::Slice(s) => s.0.get_vec(),
+        GosValue::Array(a) => {
+            a.0.borrow_data()
+                .iter()
+                .map(|x| x.borrow().clone())
+                .collect()
+        }
+        v => return Err(mismatch::<Vec<T>>(&v)),
+    })
+}
+
+impl<T: ToGos> ToGos for Vec<T> {
+    fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue> {
+        ctx.convert(meta, "Vec", |m, _| match m {
+            GosMetadata::NonPtr(k, c) => match &ctx.metas[k] {
+                MetadataType::SliceOrArray(elem, size) => Some((|| {
+                    if c == MetaCategory::Array && self.len() != *size {
+                        return Err(format!(
+                            "cannot convert Vec of length {} to Go array of length {}",
+                            self.len(),
+                            size
+                        ));
+                    }
+                    let vals = self
+                        .into_iter()
+                        .map(|x| x.to_gos(ctx, *elem))
+                        .collect::<FfiResult<Vec<GosValue>>>()?;
+                    Ok(match c {
+                        MetaCategory::Array => GosValue::array_with_val(vals, m, ctx.gcv),
+                        _ => GosValue::slice_with_val(vals, m, ctx.gcv),
+                    })
+                })()),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        elem_type(meta, metas).map_or(false, |e| T::converts_to(e, metas))
+    }
+}
+
+// the types of the keys and the values of the Go map type meta
+fn map_types(meta: GosMetadata, metas: &MetadataObjs) -> Option<(GosMetadata, GosMetadata)> {
+    match go_type(meta, metas) {
+        (GosMetadata::NonPtr(k, _), ValueType::Map) => match &metas[k] {
+            MetadataType::Map(km, vm) => Some((*km, *vm)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl<K, V> FromGos for HashMap<K, V>
+where
+    K: FromGos + Eq + Hash,
+    V: FromGos,
+{
+    fn from_gos(v: GosValue) -> FfiResult<Self> {
+        match see_through(v) {
+            GosValue::Map(m) if m.0.is_nil() => Ok(HashMap::new()),
+            GosValue::Map(m) => {
+                m.0.borrow_data()
+                    .iter()
+                    .map(|(k, v)| Ok((K::from_gos(k.clone())?, V::from_gos(v.borrow().clone())?)))
+                    .collect()
+            }
+            v => Err(mismatch::<HashMap<K, V>>(&v)),
+        }
+    }
+
+    fn from_gos_typed(v: GosValue, metas: &MetadataObjs) -> FfiResult<Self> {
+        match see_through(v) {
+            GosValue::Map(m) if m.0.is_nil() => Ok(HashMap::new()),
+            GosValue::Map(m) => {
+                m.0.borrow_data()
+                    .iter()
+                    .map(|(k, v)| {
+                        let k = K::from_gos_typed(k.clone(), metas)?;
+                        Ok((k, V::from_gos_typed(v.borrow().clone(), metas)?))
+                    })
+                    .collect()
+            }
+            v => Err(mismatch::<HashMap<K, V>>(&v)),
+        }
+    }
+
+    fn converts_from(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        is_interface(meta, metas)
+            || map_types(meta, metas).map_or(false, |(k, v)| {
+                K::converts_from(k, metas) && V::converts_from(v, metas)
+            })
+    }
+}
+
+impl<K: ToGos, V: ToGos> ToGos for HashMap<K, V> {
+    fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue> {
+        ctx.convert(meta, "HashMap", |m, t| match (t, m) {
+            (ValueType::Map, GosMetadata::NonPtr(k, _)) => match &ctx.metas[k] {
+                MetadataType::Map(km, vm) => Some((|| {
+                    let map = GosValue::new_map(m, vm.default_val(ctx.metas, ctx.gcv), ctx.gcv);
+                    for (key, val) in self.into_iter() {
+                        let key = key.to_gos(ctx, *km)?;
+                        let val = val.to_gos(ctx, *vm)?;
+                        map.as_map().0.insert(key, val);
+                    }
+                    Ok(map)
+                })()),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        map_types(meta, metas).map_or(false, |(k, v)| {
+            K::converts_to(k, metas) && V::converts_to(v, metas)
+        })
+    }
+}
+
+/// Chan is a Go channel the host sends values to and receives values from,
+/// with GosVM::send and GosVM::recv and their variants. It's converted from a
+/// Go channel passed to Rust, or created with Chan::new, which gets the Go
+/// type it's first converted to.
+#[derive(Clone, Debug)]
+pub struct Chan {
+    chan: Channel,
+    // the Go value, shared by the clones, it's None until a channel created
+    // by the host is converted to Go
+    obj: Rc<RefCell<Option<Rc<ChannelObj>>>>,
+}
+
+impl Chan {
+    /// new creates a channel with the buffer size cap, 0 for unbuffered.
+    pub fn new(cap: usize) -> Chan {
+        Chan {
+            chan: Channel::new(cap),
+            obj: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.chan
+    }
+
+    /// elem_meta returns the type of the elements, None if the channel is
+    /// not converted to Go yet.
+    pub fn elem_meta(&self, metas: &MetadataObjs) -> Option<GosMetadata> {
+        let obj = self.obj.borrow();
+        let meta = obj.as_ref()?.meta.get_underlying(metas);
+        Some(*metas[meta.as_non_ptr()].as_channel().1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chan.len()
+    }
+
+    pub fn cap(&self) -> usize {
+        self.chan.cap()
+    }
+
+    /// close closes the channel, like the builtin close.
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.is_closed()
+    }
+}
+
+impl FromGos for Chan {
+    fn from_gos(v: GosValue) -> FfiResult<Self> {
+        match see_through(v) {
+            GosValue::Channel(c) => {
+                c.host.set(true);
+                Ok(Chan {
+                    chan: c.chan.clone(),
+                    obj: Rc::new(RefCell::new(Some(c))),
+                })
+            }
+            v => Err(mismatch::<Self>(&v)),
+        }
+    }
+
+    fn converts_from(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        is_interface(meta, metas) || Self::converts_to(meta, metas)
+    }
+}
+
+impl ToGos for Chan {
+    fn to_gos(self, ctx: &FfiCtx, meta: GosMetadata) -> FfiResult<GosValue> {
+        ctx.convert(meta, "Chan", |m, t| match t {
+            ValueType::Channel => {
+                let mut obj = self.obj.borrow_mut();
+                let obj = obj.get_or_insert_with(|| {
+                    Rc::new(ChannelObj {
+                        meta: m,
+                        chan: self.chan.clone(),
+                        host: Cell::new(true),
+                    })
+                });
+                Some(Ok(GosValue::Channel(obj.clone())))
+            }
+            _ => None,
+        })
+    }
+
+    fn converts_to(meta: GosMetadata, metas: &MetadataObjs) -> bool {
+        go_type(meta, metas).1 == ValueType::Channel
+    }
+}
+
+/// GoType gives the name of the Go type a Rust type converts to, it's used to
+/// write the Go declarations of the types deriving GoStruct.
+pub trait GoType {
+    fn go_type() -> String;
+}
+
+/// GoStruct is implemented with `#[derive(GoStruct)]` for a Rust struct with
+/// named fields, which converts to and from a Go struct type of the same
+/// name. A field can be given another name in Go with `#[gos(rename = "..")]`.
+///
+/// The Go type has to be declared in the script, go_decl returns the
+/// declaration. The fields are matched by name, the Go type may have others,
+/// which are zero values when converted from Rust. The conversion from Go
+/// needs the types of the VM, see FromGos::from_gos_typed.
+pub trait GoStruct: FromGos + ToGos + GoType {
+    fn go_decl() -> String;
+}
+
+/// struct_field_types returns the types of the fields named names of the Go
+/// struct type meta, None if it's not a struct type or lacks one of them.
+/// It's used by `#[derive(GoStruct)]`.
+pub fn struct_field_types(
+    meta: GosMetadata,
+    metas: &MetadataObjs,
+    names: &[&str],
+) -> Option<Vec<GosMetadata>> {
+    let (fields, _) = struct_meta(metas, meta)?;
+    names
+        .iter()
+        .map(|n| fields.mapping.get(*n).map(|i| fields.fields[*i as usize]))
+        .collect()
+}
+
+/// struct_from_gos returns the fields named names of a Go struct converted
+/// to the Rust type T, in the order of names, the types of the VM are metas.
+/// It's used by `#[derive(GoStruct)]`.
+pub fn struct_from_gos<T>(
+    v: GosValue,
+    metas: &MetadataObjs,
+    names: &[&str],
+) -> FfiResult<Vec<GosValue>> {
+    let s = match see_through(v) {
+        GosValue::Struct(s) => s,
+        v => return Err(mismatch::<T>(&v)),
+    };
+    let obj = s.0.borrow();
+    let fields = match struct_meta(metas, obj.meta) {
+        Some((fields, _)) => fields,
+        None => return Err(mismatch::<T>(&GosValue::Struct(s.clone()))),
+    };
+    names
+        .iter()
+        .map(|n| match fields.mapping.get(*n) {
+            Some(i) => Ok(obj.fields[*i as usize].clone()),
+            None => Err(format!(
+                "cannot convert Go struct without field {} to {}",
+                n,
+                std::any::type_name::<T>()
+            )),
+        })
+        .collect()
+}
+
+// returns the fields and the zero value of a struct type, or of the
+// underlying type of a named struct type
+fn struct_meta(metas: &MetadataObjs, meta: GosMetadata) -> Option<(&Fields, &GosValue)> {
+    match meta {
+        GosMetadata::NonPtr(k, MetaCategory::Default) => match &metas[k] {
+            MetadataType::Named(_, u) => struct_meta(metas, *u),
+            MetadataType::Struct(fields, zero) => Some((fields, zero)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+macro_rules! impl_go_type {
+    ($($t:ty => $name:expr),*) => {
+        $(
+            impl GoType for $t {
+                fn go_type() -> String {
+                    $name.to_string()
+                }
+            }
+        )*
+    };
+}
+
+impl_go_type!(
+    i8 => "int8", i16 => "int16", i32 => "int32", i64 => "int64", isize => "int",
+    u8 => "uint8", u16 => "uint16", u32 => "uint32", u64 => "uint64", usize => "uint",
+    f32 => "float32", f64 => "float64", bool => "bool", String => "string",
+    GosValue => "interface{}"
+);
+
+impl<T: GoType> GoType for Option<T> {
+    fn go_type() -> String {
+        T::go_type()
+    }
+}
+
+impl<T: GoType> GoType for Vec<T> {
+    fn go_type() -> String {
+        format!("[]{}", T::go_type())
+    }
+}
+
+impl<K: GoType, V: GoType> GoType for HashMap<K, V> {
+    fn go_type() -> String {
+        format!("map[{}]{}", K::go_type(), V::go_type())
+    }
+}
+
+impl ToGosResults for () {
+    fn to_gos_results(self, _: &FfiCtx) -> FfiResult<Vec<GosValue>> {
+        Ok(vec![])
+    }
+
+    fn converts_to_results(results: &[GosMetadata], _: &MetadataObjs) -> bool {
+        results.is_empty()
+    }
+}
+
+impl<T: ToGos> ToGosResults for T {
+    fn to_gos_results(self, ctx: &FfiCtx) -> FfiResult<Vec<GosValue>> {
+        match ctx.results {
+            [meta] => Ok(vec![self.to_gos(ctx, *meta)?]),
+            _ => Err(format!("1 result returned, {} expected", ctx.results.len())),
+        }
+    }
+
+    fn converts_to_results(results: &[GosMetadata], metas: &MetadataObjs) -> bool {
+        match results {
+            [meta] => T::converts_to(*meta, metas),
+            _ => false,
+        }
+    }
+}
+
+/// A Result is an error value if the last result of the Go function is of
+/// type error, which is nil for Ok. Otherwise an Err becomes a Go panic.
+impl<T: ToGosResults, E: Display> ToGosResults for Result<T, E> {
+    fn to_gos_results(self, ctx: &FfiCtx) -> FfiResult<Vec<GosValue>> {
+        let err_meta = match ctx.results.last() {
+            Some(m) if ctx.is_error(*m) => *m,
+            _ => return self.map_err(|e| e.to_string())?.to_gos_results(ctx),
+        };
+        let others = &ctx.results[..ctx.results.len() - 1];
+        match self {
+            Ok(v) => {
+                let mut vals = v.to_gos_results(&FfiCtx {
+                    results: others,
+                    ..*ctx
+                })?;
+                vals.push(err_meta.zero_val(ctx.metas, ctx.gcv));
+                Ok(vals)
+            }
+            Err(e) => {
+                let mut vals: Vec<GosValue> = others
+                    .iter()
+                    .map(|m| m.zero_val(ctx.metas, ctx.gcv))
+                    .collect();
+                vals.push(ctx.new_error(err_meta, e.to_string())?);
+                Ok(vals)
+            }
+        }
+    }
+
+    fn converts_to_results(results: &[GosMetadata], metas: &MetadataObjs) -> bool {
+        match results.split_last() {
+            Some((m, others)) if is_error(*m, metas) => T::converts_to_results(others, metas),
+            _ => T::converts_to_results(results, metas),
+        }
+    }
+}
+
+macro_rules! count {
+    () => (0usize);
+    ($head:ident $($tail:ident)*) => (1usize + count!($($tail)*));
+}
+
+macro_rules! impl_tuple_results {
+    ($(($($t:ident $i:tt),+))*) => {
+        $(
+            impl<$($t: ToGos),+> ToGosResults for ($($t,)+) {
+                fn to_gos_results(self, ctx: &FfiCtx) -> FfiResult<Vec<GosValue>> {
+                    let n = count!($($t)+);
+                    if ctx.results.len() != n {
+                        return Err(format!(
+                            "{} results returned, {} expected",
+                            n,
+                            ctx.results.len()
+                        ));
+                    }
+                    Ok(vec![$(self.$i.to_gos(ctx, ctx.results[$i])?),+])
+                }
+
+                fn converts_to_results(results: &[GosMetadata], metas: &MetadataObjs) -> bool {
+                    results.len() == count!($($t)+) && $($t::converts_to(results[$i], metas))&&+
+                }
+            }
+        )*
+    };
+}
+
+impl_tuple_results! {
+    (A 0, B 1)
+    (A 0, B 1, C 2)
+    (A 0, B 1, C 2, D 3)
+    (A 0, B 1, C 2, D 3, E 4)
+    (A 0, B 1, C 2, D 3, E 4, F 5)
+}
+
+/// FfiFn is implemented by the Rust functions that can be registered with
+/// FfiFactory::register_fn, which are the ones taking up to 6 arguments of
+/// FromGos types, optionally after a &FfiCtx, and returning ToGosResults.
+pub trait FfiFn<Args> {
+    fn call_gos(&self, ctx: &FfiCtx, params: Vec<GosValue>) -> FfiResult<Vec<GosValue>>;
+
+    /// check_sig checks the parameters and the results of the Go signature
+    /// the function implements against its arguments and results.
+    fn check_sig(
+        params: &[GosMetadata],
+        results: &[GosMetadata],
+        metas: &MetadataObjs,
+    ) -> FfiResult<()>;
+}
+
+/// WithCtx marks the FfiFn and AsyncFfiFn implementations for functions
+/// taking a &FfiCtx.
+pub struct WithCtx<Args>(PhantomData<Args>);
+
+// checks the parameters and the results of a Go signature against the
+// arguments and the results of a Rust function
+macro_rules! check_sig {
+    ($params:ident, $results:ident, $metas:ident, $r:ident, $($a:ident),*) => {{
+        let n = count!($($a)*);
+        if $params.len() != n {
+            return Err(format!("{} parameters declared, {} expected", $params.len(), n));
+        }
+        let mut iter = $params.iter().enumerate();
+        $(
+            let (i, m) = iter.next().unwrap();
+            if !$a::converts_from(*m, $metas) {
+                return Err(format!(
+                    "parameter {} doesn't convert to {}",
+                    i,
+                    std::any::type_name::<$a>()
+                ));
+            }
+        )*
+        if !$r::converts_to_results($results, $metas) {
+            return Err(format!(
+                "results don't convert from {}",
+                std::any::type_name::<$r>()
+            ));
+        }
+        Ok(())
+    }};
+}
+
+// converts the arguments of a Go call to the arguments of a Rust function
+macro_rules! from_gos_args {
+    ($ctx:ident, $params:ident, $($a:ident),*) => {
+        let n = count!($($a)*);
+        if $params.len() != n {
+            return Err(format!(
+                "{} arguments given, {} expected",
+                $params.len(),
+                n
+            ));
+        }
+        let mut iter = $params.into_iter().enumerate();
+        $(
+            let (i, v) = iter.next().unwrap();
+            let $a = $a::from_gos_typed(v, $ctx.metas).map_err(|e| format!(
+                "{}: argument {}: {}",
+                $ctx.func_name,
+                i,
+                e
+            ))?;
+        )*
+    };
+}
+
+macro_rules! impl_ffi_fn {
+    ($(($($a:ident),*))*) => {
+        $(
+            impl<Func, R, $($a),*> FfiFn<($($a,)*)> for Func
+            where
+                Func: Fn($($a),*) -> R,
+                R: ToGosResults,
+                $($a: FromGos),*
+            {
+                #[allow(non_snake_case, unused_mut, unused_variables)]
+                fn call_gos(&self, ctx: &FfiCtx, params: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+                    from_gos_args!(ctx, params, $($a),*);
+                    (self)($($a),*).to_gos_results(ctx)
+                }
+
+                #[allow(non_snake_case, unused_mut, unused_variables)]
+                fn check_sig(
+                    params: &[GosMetadata],
+                    results: &[GosMetadata],
+                    metas: &MetadataObjs,
+                ) -> FfiResult<()> {
+                    check_sig!(params, results, metas, R, $($a),*)
+                }
+            }
+
+            impl<Func, R, $($a),*> FfiFn<WithCtx<($($a,)*)>> for Func
+            where
+                Func: Fn(&FfiCtx, $($a),*) -> R,
+                R: ToGosResults,
+                $($a: FromGos),*
+            {
+                #[allow(non_snake_case, unused_mut, unused_variables)]
+                fn call_gos(&self, ctx: &FfiCtx, params: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+                    from_gos_args!(ctx, params, $($a),*);
+                    (self)(ctx, $($a),*).to_gos_results(ctx)
+                }
+
+                #[allow(non_snake_case, unused_mut, unused_variables)]
+                fn check_sig(
+                    params: &[GosMetadata],
+                    results: &[GosMetadata],
+                    metas: &MetadataObjs,
+                ) -> FfiResult<()> {
+                    check_sig!(params, results, metas, R, $($a),*)
+                }
+            }
+        )*
+    };
+}
+
+impl_ffi_fn! {
+    ()
+    (A)
+    (A, B)
+    (A, B, C)
+    (A, B, C, D)
+    (A, B, C, D, E)
+    (A, B, C, D, E, F)
+}
+
+/// AsyncFfiFn is implemented by the Rust functions that can be registered
+/// with FfiFactory::register_async_fn, which are the ones taking up to 6
+/// arguments of FromGos types, optionally after a &FfiCtx, and returning a
+/// future of ToGosResults. The future can't borrow the &FfiCtx.
+pub trait AsyncFfiFn<Args> {
+    fn call_gos_async<'a>(&self, ctx: &'a FfiCtx<'a>, params: Vec<GosValue>) -> FfiFuture<'a>;
+
+    /// check_sig is the one of FfiFn.
+    fn check_sig(
+        params: &[GosMetadata],
+        results: &[GosMetadata],
+        metas: &MetadataObjs,
+    ) -> FfiResult<()>;
+}
+
+macro_rules! impl_async_ffi_fn {
+    ($(($($a:ident),*))*) => {
+        $(
+            impl<Func, Fut, R, $($a),*> AsyncFfiFn<($($a,)*)> for Func
+            where
+                Func: Fn($($a),*) -> Fut,
+                Fut: Future<Output = R> + 'static,
+                R: ToGosResults,
+                $($a: FromGos),*
+            {
+                #[allow(non_snake_case, unused_mut, unused_variables)]
+                fn call_gos_async<'a>(
+                    &self,
+                    ctx: &'a FfiCtx<'a>,
+                    params: Vec<GosValue>,
+                ) -> FfiFuture<'a> {
+                    // the arguments are converted before the call, the
+                    // results after the future is ready
+                    let fut = (|| -> FfiResult<Fut> {
+                        from_gos_args!(ctx, params, $($a),*);
+                        Ok((self)($($a),*))
+                    })();
+                    Box::pin(async move { fut?.await.to_gos_results(ctx) })
+                }
+
+                #[allow(non_snake_case, unused_mut, unused_variables)]
+                fn check_sig(
+                    params: &[GosMetadata],
+                    results: &[GosMetadata],
+                    metas: &MetadataObjs,
+                ) -> FfiResult<()> {
+                    check_sig!(params, results, metas, R, $($a),*)
+                }
+            }
+
+            impl<Func, Fut, R, $($a),*> AsyncFfiFn<WithCtx<($($a,)*)>> for Func
+            where
+                Func: Fn(&FfiCtx, $($a),*) -> Fut,
+                Fut: Future<Output = R> + 'static,
+                R: ToGosResults,
+                $($a: FromGos),*
+            {
+                #[allow(non_snake_case, unused_mut, unused_variables)]
+                fn call_gos_async<'a>(
+                    &self,
+                    ctx: &'a FfiCtx<'a>,
+                    params: Vec<GosValue>,
+                ) -> FfiFuture<'a> {
+                    let fut = (|| -> FfiResult<Fut> {
+                        from_gos_args!(ctx, params, $($a),*);
+                        Ok((self)(ctx, $($a),*))
+                    })();
+                    Box::pin(async move { fut?.await.to_gos_results(ctx) })
+                }
+
+                #[allow(non_snake_case, unused_mut, unused_variables)]
+                fn check_sig(
+                    params: &[GosMetadata],
+                    results: &[GosMetadata],
+                    metas: &MetadataObjs,
+                ) -> FfiResult<()> {
+                    check_sig!(params, results, metas, R, $($a),*)
+                }
+            }
+        )*
+    };
+}
+
+impl_async_ffi_fn! {
+    ()
+    (A)
+    (A, B)
+    (A, B, C)
+    (A, B, C, D)
+    (A, B, C, D, E)
+    (A, B, C, D, E, F)
+}
+
+type BoxedFfiFn = Box<dyn Fn(&FfiCtx, Vec<GosValue>) -> FfiResult<Vec<GosValue>>>;
+
+type BoxedAsyncFfiFn = Box<dyn for<'a> Fn(&'a FfiCtx<'a>, Vec<GosValue>) -> FfiFuture<'a>>;
+
+type CheckSig = fn(&[GosMetadata], &[GosMetadata], &MetadataObjs) -> FfiResult<()>;
+
+// a Rust function registered with FfiFactory
+enum FfiFunc {
+    Sync(BoxedFfiFn),
+    Async(BoxedAsyncFfiFn),
+}
+
+impl FfiFunc {
+    fn new<Args, F: FfiFn<Args> + 'static>(f: F) -> FfiFunc {
+        FfiFunc::Sync(Box::new(move |ctx, params| f.call_gos(ctx, params)))
+    }
+
+    fn new_async<Args, F: AsyncFfiFn<Args> + 'static>(f: F) -> FfiFunc {
+        FfiFunc::Async(Box::new(move |ctx, params| f.call_gos_async(ctx, params)))
+    }
+
+    fn is_async(&self) -> bool {
+        match self {
+            FfiFunc::Sync(_) => false,
+            FfiFunc::Async(_) => true,
+        }
+    }
+
+    fn call(&self, ctx: &FfiCtx, params: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+        match self {
+            FfiFunc::Sync(f) => f(ctx, params),
+            FfiFunc::Async(_) => Err(format!("{} must be called with call_async", ctx.func_name)),
+        }
+    }
+
+    fn call_async<'a>(&self, ctx: &'a FfiCtx<'a>, params: Vec<GosValue>) -> FfiFuture<'a> {
+        match self {
+            FfiFunc::Sync(f) => Box::pin(future::ready(f(ctx, params))),
+            FfiFunc::Async(f) => f(ctx, params),
+        }
+    }
+}
+
+/// FfiFuncs is an FFI object made of Rust functions, see
+/// FfiFactory::register_fn.
+pub struct FfiFuncs {
+    funcs: HashMap<String, FfiFunc>,
+}
+
+impl FfiFuncs {
+    fn new() -> FfiFuncs {
+        FfiFuncs {
+            funcs: HashMap::new(),
+        }
+    }
+}
+
+impl Ffi for FfiFuncs {
+    fn call(&self, ctx: &FfiCtx, params: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+        match self.funcs.get(ctx.func_name) {
+            Some(f) => f.call(ctx, params),
+            None => Err(format!("FFI function {} not found", ctx.func_name)),
+        }
+    }
+
+    fn is_async(&self, func_name: &str) -> bool {
+        self.funcs.get(func_name).map_or(false, |f| f.is_async())
+    }
+
+    fn call_async<'a>(&'a self, ctx: &'a FfiCtx<'a>, params: Vec<GosValue>) -> FfiFuture<'a> {
+        match self.funcs.get(ctx.func_name) {
+            Some(f) => f.call_async(ctx, params),
+            None => {
+                let msg = format!("FFI function {} not found", ctx.func_name);
+                Box::pin(future::ready(Err(msg)))
+            }
+        }
+    }
+}
+
 pub struct FfiFactory {
     registry: HashMap<&'static str, Box<Ctor>>,
+    funcs: HashMap<&'static str, Rc<RefCell<FfiFuncs>>>,
+    natives: HashMap<String, (FfiFunc, CheckSig)>,
 }
 
 impl FfiFactory {
     pub fn new() -> FfiFactory {
         FfiFactory {
             registry: HashMap::new(),
+            funcs: HashMap::new(),
+            natives: HashMap::new(),
         }
     }
 
@@ -32,6 +1502,118 @@ impl FfiFactory {
         self.registry.insert(name, ctor);
     }
 
+    /// register_fn adds f as the method func_name of the FFI object named
+    /// name, which is created by the first call. The arguments of f are
+    /// converted from the ones of the Go method, and its results to the
+    /// results of the Go method.
+    pub fn register_fn<Args, F>(&mut self, name: &'static str, func_name: &str, f: F)
+    where
+        F: FfiFn<Args> + 'static,
+    {
+        self.add_fn(name, func_name, FfiFunc::new(f));
+    }
+
+    /// register_async_fn is like register_fn, but f returns a future, which
+    /// the goroutine calling the method waits for.
+    pub fn register_async_fn<Args, F>(&mut self, name: &'static str, func_name: &str, f: F)
+    where
+        F: AsyncFfiFn<Args> + 'static,
+    {
+        self.add_fn(name, func_name, FfiFunc::new_async(f));
+    }
+
+    /// register_native adds f as the implementation of the Go function
+    /// declared without a body, name is "pkgpath.FuncName", or
+    /// "pkgpath.TypeName.MethodName" for a method, whose receiver is then
+    /// the first argument of f.
+    pub fn register_native<Args, F>(&mut self, name: &str, f: F)
+    where
+        F: FfiFn<Args> + 'static,
+    {
+        let check: CheckSig = <F as FfiFn<Args>>::check_sig;
+        self.natives
+            .insert(name.to_string(), (FfiFunc::new(f), check));
+    }
+
+    /// register_async_native is like register_native, but f returns a
+    /// future, which the goroutine calling the function waits for.
+    pub fn register_async_native<Args, F>(&mut self, name: &str, f: F)
+    where
+        F: AsyncFfiFn<Args> + 'static,
+    {
+        let check: CheckSig = <F as AsyncFfiFn<Args>>::check_sig;
+        self.natives
+            .insert(name.to_string(), (FfiFunc::new_async(f), check));
+    }
+
+    /// call_native calls the function registered as ctx.func_name with
+    /// register_native.
+    pub fn call_native(&self, ctx: &FfiCtx, params: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+        match self.natives.get(ctx.func_name) {
+            Some((f, _)) => f.call(ctx, params),
+            None => Err(format!("native function {} not found", ctx.func_name)),
+        }
+    }
+
+    /// has_native tells if the function name is registered with
+    /// register_native or register_async_native.
+    pub fn has_native(&self, name: &str) -> bool {
+        self.natives.contains_key(name)
+    }
+
+    /// check_native checks the Go signature of the function name, with the
+    /// parameters params, the receiver first for a method, and the results
+    /// results, against the function registered with register_native or
+    /// register_async_native.
+    pub fn check_native(
+        &self,
+        name: &str,
+        params: &[GosMetadata],
+        results: &[GosMetadata],
+        metas: &MetadataObjs,
+    ) -> FfiResult<()> {
+        match self.natives.get(name) {
+            Some((_, check)) => check(params, results, metas),
+            None => Err(format!("native function {} not found", name)),
+        }
+    }
+
+    /// is_async_native tells if the function name is registered with
+    /// register_async_native, and is to be called with call_native_async.
+    pub fn is_async_native(&self, name: &str) -> bool {
+        self.natives.get(name).map_or(false, |(f, _)| f.is_async())
+    }
+
+    /// call_native_async is the async version of call_native.
+    pub fn call_native_async<'a>(
+        &'a self,
+        ctx: &'a FfiCtx<'a>,
+        params: Vec<GosValue>,
+    ) -> FfiFuture<'a> {
+        match self.natives.get(ctx.func_name) {
+            Some((f, _)) => f.call_async(ctx, params),
+            None => {
+                let msg = format!("native function {} not found", ctx.func_name);
+                Box::pin(future::ready(Err(msg)))
+            }
+        }
+    }
+
+    // adds f to the FFI object named name made of Rust functions
+    fn add_fn(&mut self, name: &'static str, func_name: &str, f: FfiFunc) {
+        let funcs = match self.funcs.get(name) {
+            Some(funcs) => funcs.clone(),
+            None => {
+                let funcs = Rc::new(RefCell::new(FfiFuncs::new()));
+                self.funcs.insert(name, funcs.clone());
+                let obj = funcs.clone();
+                self.register(name, Box::new(move |_| Ok(obj.clone())));
+                funcs
+            }
+        };
+        funcs.borrow_mut().funcs.insert(func_name.to_string(), f);
+    }
+
     pub fn create_by_name(
         &self,
         name: &str,
@@ -100,9 +100,54 @@ pub enum Opcode {
     RECOVER,    // for built-in function recover
     ASSERT,     // for built-in function assert
     FFI,        // for built-in function native
+    PRINT,      // for built-in function print
+    PRINTLN,    // for built-in function println
+    NATIVE,     // for functions declared without a body
+    RANGE_END,  // for leaving range loops
+}
+
+/// T2Index is what an instruction keeps in the space of t2 instead of a type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum T2Index {
+    // the comma-ok form is used if it's not 0
+    CommaOk,
+    // the index of a field or an element
+    Index,
 }
 
 impl Opcode {
+    /// from_u8 returns the opcode of the byte, None if it's out of range
+    pub fn from_u8(v: u8) -> Option<Opcode> {
+        if v <= Opcode::RANGE_END as u8 {
+            Some(unsafe { std::mem::transmute(v) })
+        } else {
+            None
+        }
+    }
+
+    /// t2_index tells if the instruction uses the space of t2 for a small
+    /// integer rather than a type, and what the integer is
+    pub fn t2_index(&self) -> Option<T2Index> {
+        match self {
+            Opcode::LOAD_INDEX | Opcode::LOAD_INDEX_IMM | Opcode::TYPE_ASSERT | Opcode::TYPE => {
+                Some(T2Index::CommaOk)
+            }
+            Opcode::STORE_INDEX_IMM | Opcode::STORE_FIELD | Opcode::STORE_STRUCT_FIELD => {
+                Some(T2Index::Index)
+            }
+            _ => None,
+        }
+    }
+
+    /// takes_pkg_key tells if the instruction is followed by a package key
+    /// instead of another instruction
+    pub fn takes_pkg_key(&self) -> bool {
+        match self {
+            Opcode::LOAD_PKG_FIELD | Opcode::STORE_PKG_FIELD | Opcode::REF_PKG_MEMBER => true,
+            _ => false,
+        }
+    }
+
     #[inline]
     pub fn offset(&self, base: Opcode) -> OpIndex {
         (*self as i16 - base as i16) as OpIndex
@@ -119,7 +164,7 @@ impl Opcode {
             Opcode::POP => ("POP", -1),
             Opcode::LOAD_LOCAL => ("LOAD_LOCAL", 1),
             Opcode::STORE_LOCAL => ("STORE_LOCAL", 0),
-            Opcode::LOAD_UPVALUE => ("LOAD_LOCAL", 1),
+            Opcode::LOAD_UPVALUE => ("LOAD_UPVALUE", 1),
             Opcode::STORE_UPVALUE => ("STORE_UPVALUE", 0),
             Opcode::LOAD_INDEX => ("LOAD_INDEX", -1),
             Opcode::STORE_INDEX => ("STORE_INDEX", 0),
@@ -197,6 +242,10 @@ impl Opcode {
             Opcode::RECOVER => ("RECOVER", 1),
             Opcode::ASSERT => ("ASSERT", 0),
             Opcode::FFI => ("FFI", 0),
+            Opcode::PRINT => ("PRINT", -128),
+            Opcode::PRINTLN => ("PRINTLN", -128),
+            Opcode::NATIVE => ("NATIVE", 0),
+            Opcode::RANGE_END => ("RANGE_END", 0),
         }
     }
 
@@ -264,10 +313,30 @@ pub enum ValueType {
 }
 
 impl ValueType {
+    /// from_u8 returns the value type of the byte, None if it's out of range
+    pub fn from_u8(v: u8) -> Option<ValueType> {
+        if v <= ValueType::FlagE as u8 {
+            Some(unsafe { std::mem::transmute(v) })
+        } else {
+            None
+        }
+    }
+
     #[inline]
     pub fn copyable(&self) -> bool {
         self <= &COPYABLE_END
     }
+
+    /// is_basic tells if it's the type of the values of a basic Go type, the
+    /// values of a named type based on one of them are of the same type, its
+    /// methods are bound statically.
+    #[inline]
+    pub fn is_basic(&self) -> bool {
+        match self {
+            ValueType::Complex128 | ValueType::Str => true,
+            _ => self >= &ValueType::Bool && self <= &ValueType::Complex64,
+        }
+    }
 }
 
 /// Instruction is 64 bit
@@ -344,6 +413,26 @@ impl Instruction {
         self.val = (self.val & 0xffff_ff00_ffff_ffff) | val64;
     }
 
+    /// is_valid tells if the opcode and the types of an instruction made from
+    /// raw bits are all in range, which op() and t0()..t2() rely on
+    pub fn is_valid(&self) -> bool {
+        let bytes = [
+            (self.val >> (8 * 3 + 32)) as u8,
+            (self.val >> (8 * 2 + 32)) as u8,
+            (self.val >> (8 + 32)) as u8,
+            (self.val >> 32) as u8,
+        ];
+        match Opcode::from_u8(bytes[0]) {
+            Some(op) => {
+                let types = if op.t2_index().is_some() { 2 } else { 3 };
+                bytes[1..1 + types]
+                    .iter()
+                    .all(|&t| ValueType::from_u8(t).is_some())
+            }
+            None => false,
+        }
+    }
+
     #[inline]
     pub fn get_u64(&self) -> u64 {
         self.val
@@ -13,9 +13,15 @@ pub mod value;
 
 mod stack;
 
+mod timer;
+
 #[macro_use]
 mod vm_util;
 
 pub mod vm;
 
 pub mod gc;
+
+pub mod serialize;
+
+pub mod disasm;
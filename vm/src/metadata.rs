@@ -301,7 +301,18 @@ impl GosMetadata {
                     MetadataType::Map(_, _) => ValueType::Map,
                     MetadataType::Interface(_) => ValueType::Interface,
                     MetadataType::Channel(_, _) => ValueType::Channel,
-                    MetadataType::Named(_, _) => ValueType::Named,
+                    MetadataType::Named(_, u) => match u {
+                        // a place holder, the underlying type is not known yet
+                        GosMetadata::Untyped => ValueType::Named,
+                        _ => {
+                            let t = u.get_value_type(metas);
+                            if t.is_basic() {
+                                t
+                            } else {
+                                ValueType::Named
+                            }
+                        }
+                    },
                 },
                 MetaCategory::Type | MetaCategory::ArrayType => ValueType::Metadata,
                 MetaCategory::Array => ValueType::Array,
@@ -358,7 +369,11 @@ impl GosMetadata {
                 MetadataType::Channel(_, _) => GosValue::Nil(*self),
                 MetadataType::Named(_, gm) => {
                     let val = gm.default_val(mobjs, gcos);
-                    GosValue::Named(Box::new((val, *gm)))
+                    if self.get_value_type(mobjs) == ValueType::Named {
+                        GosValue::Named(Box::new((val, *gm)))
+                    } else {
+                        val
+                    }
                 }
             },
             _ => GosValue::Nil(*self),
@@ -404,7 +419,11 @@ impl GosMetadata {
                 MetadataType::Channel(_, _) => GosValue::Nil(*self),
                 MetadataType::Named(_, gm) => {
                     let val = gm.default_val(mobjs, gcos);
-                    GosValue::Named(Box::new((val, *gm)))
+                    if self.get_value_type(mobjs) == ValueType::Named {
+                        GosValue::Named(Box::new((val, *gm)))
+                    } else {
+                        val
+                    }
                 }
             },
             _ => unreachable!(),
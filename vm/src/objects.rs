@@ -7,7 +7,8 @@ use super::metadata::*;
 use super::stack::Stack;
 use super::value::{rcount_mark_and_queue, EmptyResult, GosValue, RCQueue, RCount};
 use goscript_parser::objects::{EntityKey, IdentKey};
-use slotmap::{new_key_type, DenseSlotMap};
+use slotmap::{new_key_type, DenseSlotMap, SecondaryMap};
+use std::any::Any;
 use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::cmp::Ordering;
 use std::collections::HashMap;
@@ -35,6 +36,8 @@ new_key_type! { pub struct PackageKey; }
 pub type MetadataObjs = DenseSlotMap<MetadataKey, MetadataType>;
 pub type FunctionObjs = DenseSlotMap<FunctionKey, FunctionVal>;
 pub type PackageObjs = DenseSlotMap<PackageKey, PackageVal>;
+// the package level state of a running VM, see PackageVal::instantiate
+pub type PackageInstances = SecondaryMap<PackageKey, PackageVal>;
 
 pub fn key_to_u64<K>(key: K) -> u64
 where
@@ -124,8 +127,8 @@ impl StringObj {
         let ei = ((self_end + end) % self_end) as usize;
         StringObj {
             data: Rc::clone(&self.data),
-            begin: bi,
-            end: ei,
+            begin: self.begin + bi,
+            end: self.begin + ei,
         }
     }
 
@@ -233,6 +236,11 @@ impl MapObj {
         self.map.is_none()
     }
 
+    #[inline]
+    pub fn default_val(&self) -> GosValue {
+        self.default_val.borrow().clone()
+    }
+
     #[inline]
     pub fn get(&self, key: &GosValue) -> GosValue {
         let mref = self.borrow_data();
@@ -586,7 +594,17 @@ impl<'a> SliceObj {
     pub fn append(&mut self, vals: &mut GosVec) {
         let new_len = self.len() + vals.len();
         self.try_grow_vec(new_len);
-        self.borrow_data_mut().append(vals);
+        {
+            // elements past the end of the slice are overwritten, as in Go
+            let end = self.end();
+            let mut data = self.borrow_data_mut();
+            for (i, v) in vals.drain(..).enumerate() {
+                match data.get_mut(end + i) {
+                    Some(slot) => *slot = v,
+                    None => data.push(v),
+                }
+            }
+        }
         *self.end.get_mut() = self.begin() + new_len;
     }
 
@@ -620,7 +638,10 @@ impl<'a> SliceObj {
 
     #[inline]
     pub fn get_vec(&self) -> Vec<GosValue> {
-        self.borrow_data()
+        if self.is_nil() {
+            return vec![];
+        }
+        self.borrow_data()[self.begin()..self.end()]
             .iter()
             .map(|x| x.borrow().clone())
             .collect()
@@ -633,7 +654,9 @@ impl<'a> SliceObj {
             return;
         }
         while cap < len {
-            if cap < 1024 {
+            if cap == 0 {
+                cap = 1
+            } else if cap < 1024 {
                 cap *= 2
             } else {
                 cap = (cap as f32 * 1.25) as usize
@@ -771,16 +794,49 @@ impl Display for StructObj {
 #[derive(Clone, Debug)]
 pub struct UnderlyingFfi {
     pub ffi_obj: Rc<RefCell<dyn Ffi>>,
+    // the methods of the interface holding the object, in its order
     pub methods: Vec<(String, GosMetadata)>,
+    // the interface type the object is created as, it's the dynamic type of
+    // the interfaces holding the object
+    pub meta: GosMetadata,
+    // the same object as ffi_obj, for the host to get its Rust type back
+    pub any: Option<Rc<dyn Any>>,
 }
 
 impl UnderlyingFfi {
-    pub fn new(obj: Rc<RefCell<dyn Ffi>>, methods: Vec<(String, GosMetadata)>) -> UnderlyingFfi {
+    pub fn new(
+        obj: Rc<RefCell<dyn Ffi>>,
+        methods: Vec<(String, GosMetadata)>,
+        meta: GosMetadata,
+    ) -> UnderlyingFfi {
         UnderlyingFfi {
             ffi_obj: obj,
             methods: methods,
+            meta: meta,
+            any: None,
         }
     }
+
+    /// new_host is like new, but the host can get obj back with downcast.
+    pub fn new_host<T: Ffi + 'static>(
+        obj: Rc<RefCell<T>>,
+        methods: Vec<(String, GosMetadata)>,
+        meta: GosMetadata,
+    ) -> UnderlyingFfi {
+        UnderlyingFfi {
+            ffi_obj: obj.clone(),
+            methods: methods,
+            meta: meta,
+            any: Some(obj),
+        }
+    }
+
+    /// downcast returns the object if it's created by new_host with a T.
+    pub fn downcast<T: 'static>(&self) -> Option<Rc<RefCell<T>>> {
+        self.any
+            .as_ref()
+            .and_then(|a| a.clone().downcast::<RefCell<T>>().ok())
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -897,6 +953,8 @@ impl Display for InterfaceObj {
 pub struct ChannelObj {
     pub meta: GosMetadata,
     pub chan: Channel,
+    // set once the host has got the channel, see ffi::Chan
+    pub host: Cell<bool>,
 }
 
 impl ChannelObj {
@@ -904,6 +962,7 @@ impl ChannelObj {
         ChannelObj {
             meta: meta,
             chan: Channel::new(cap),
+            host: Cell::new(false),
         }
     }
 
@@ -1320,6 +1379,10 @@ pub struct PackageVal {
     member_indices: HashMap<String, OpIndex>,
     // maps func_member_index of the constructor to pkg_member_index
     var_mapping: Option<HashMap<OpIndex, OpIndex>>,
+    // maps pkg_member_index of the package level variables to their types
+    var_metas: HashMap<OpIndex, GosMetadata>,
+    // set when the constructor returns, so that it runs only once
+    inited: Cell<bool>,
 }
 
 impl PackageVal {
@@ -1329,6 +1392,69 @@ impl PackageVal {
             members: Vec::new(),
             member_indices: HashMap::new(),
             var_mapping: Some(HashMap::new()),
+            var_metas: HashMap::new(),
+            inited: Cell::new(false),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// from_parts is the reverse of parts, used when loading serialized code.
+    pub fn from_parts(
+        name: String,
+        members: Vec<GosValue>,
+        member_indices: HashMap<String, OpIndex>,
+        var_mapping: Option<HashMap<OpIndex, OpIndex>>,
+        var_metas: HashMap<OpIndex, GosMetadata>,
+    ) -> PackageVal {
+        PackageVal {
+            name: name,
+            members: members
+                .into_iter()
+                .map(|x| Rc::new(RefCell::new(x)))
+                .collect(),
+            member_indices: member_indices,
+            var_mapping: var_mapping,
+            var_metas: var_metas,
+            inited: Cell::new(false),
+        }
+    }
+
+    /// parts returns the members, the member indices, the var mapping and the
+    /// types of the variables.
+    pub fn parts(
+        &self,
+    ) -> (
+        Vec<GosValue>,
+        &HashMap<String, OpIndex>,
+        Option<&HashMap<OpIndex, OpIndex>>,
+        &HashMap<OpIndex, GosMetadata>,
+    ) {
+        let members = self.members.iter().map(|x| x.borrow().clone()).collect();
+        (
+            members,
+            &self.member_indices,
+            self.var_mapping.as_ref(),
+            &self.var_metas,
+        )
+    }
+
+    /// instantiate makes a copy with its own member slots, so that every VM
+    /// running the same code starts with a fresh and uninitialized package.
+    pub fn instantiate(&self) -> PackageVal {
+        PackageVal {
+            name: self.name.clone(),
+            members: self
+                .members
+                .iter()
+                .map(|m| Rc::new(RefCell::new(m.borrow().clone())))
+                .collect(),
+            member_indices: self.member_indices.clone(),
+            var_mapping: self.var_mapping.clone(),
+            var_metas: self.var_metas.clone(),
+            inited: Cell::new(false),
         }
     }
 
@@ -1339,6 +1465,14 @@ impl PackageVal {
         index as OpIndex
     }
 
+    /// add_var_member adds a package level variable of the type meta, with
+    /// its zero value.
+    pub fn add_var_member(&mut self, name: String, val: GosValue, meta: GosMetadata) -> OpIndex {
+        let index = self.add_member(name, val);
+        self.var_metas.insert(index, meta);
+        index
+    }
+
     pub fn add_var_mapping(&mut self, name: String, fn_index: OpIndex) -> OpIndex {
         let index = *self.get_member_index(&name).unwrap();
         self.var_mapping
@@ -1361,12 +1495,17 @@ impl PackageVal {
         self.member_indices.get(name)
     }
 
+    /// var_meta returns the type of the member i if it's a variable.
+    pub fn var_meta(&self, i: OpIndex) -> Option<GosMetadata> {
+        self.var_metas.get(&i).copied()
+    }
+
     pub fn inited(&self) -> bool {
-        self.var_mapping.is_none()
+        self.inited.get()
     }
 
-    pub fn set_inited(&mut self) {
-        self.var_mapping = None
+    pub fn set_inited(&self) {
+        self.inited.set(true)
     }
 
     #[inline]
@@ -1421,6 +1560,8 @@ pub enum FuncFlag {
 pub struct FunctionVal {
     pub package: PackageKey,
     pub meta: GosMetadata,
+    // the fully-qualified Go name, like "main.(*T).Close", for the tracebacks
+    pub name: String,
     code: Vec<Instruction>,
     pos: Vec<Option<usize>>,
     pub consts: Vec<GosValue>,
@@ -1454,6 +1595,7 @@ impl FunctionVal {
                 FunctionVal {
                     package: package,
                     meta: meta,
+                    name: String::new(),
                     code: Vec::new(),
                     pos: Vec::new(),
                     consts: Vec::new(),
@@ -0,0 +1,956 @@
+/// Serialization of ByteCode into a versioned binary format, and the loader
+/// which reconstructs a runnable ByteCode from it.
+///
+/// The layout is: the magic number, the format version, then the metadata
+/// arena, the function arena, the package arena, the interface table and the
+/// entry function, and finally an optional FileSet snapshot, which is the
+/// debug info the pos() of the functions points into.
+///
+/// Keys are written as they are, the loader inserts the objects in the same
+/// order so that all the keys, including the ones embedded in the instruction
+/// stream, stay valid.
+///
+/// The loader checks the structure of the data, but not the semantics of the
+/// code, so only load what is produced by write_bytecode.
+use super::gc::GcoVec;
+use super::instruction::{Instruction, OpIndex, ValueType};
+use super::metadata::*;
+use super::value::*;
+use super::vm::ByteCode;
+use goscript_parser::FileSet;
+use slotmap::{DenseSlotMap, Key};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::rc::Rc;
+
+const MAGIC: &[u8; 4] = b"GOSB";
+
+/// the version of the format, bump it when the layout or the meaning of the
+/// instructions changes
+pub const FORMAT_VERSION: u32 = 5;
+
+const DEFAULT_CAPACITY: usize = 128;
+
+/// write_bytecode writes bc, and fs if it's provided, to w.
+pub fn write_bytecode(bc: &ByteCode, fs: Option<&FileSet>, w: &mut dyn Write) -> io::Result<()> {
+    let mut writer = Writer { w: w };
+    writer.w.write_all(MAGIC)?;
+    writer.u32(FORMAT_VERSION)?;
+    writer.objects(&bc.objects)?;
+    writer.usize(bc.packages.len())?;
+    for &p in bc.packages.iter() {
+        writer.key(p)?;
+    }
+    writer.usize(bc.ifaces.len())?;
+    for (meta, methods) in bc.ifaces.iter() {
+        writer.meta(meta)?;
+        writer.option(methods.as_ref(), |w, x| w.vec(x, |w, k| w.key(*k)))?;
+    }
+    writer.key(bc.entry)?;
+    writer.option(fs, |w, x| w.file_set(x))
+}
+
+/// read_bytecode reads what's written by write_bytecode.
+pub fn read_bytecode(r: &mut dyn Read) -> io::Result<(ByteCode, Option<FileSet>)> {
+    let mut reader = Reader {
+        r: r,
+        gcv: GcoVec::new(),
+    };
+    let mut magic = [0; 4];
+    reader.r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a goscript bytecode file".to_string()));
+    }
+    let version = reader.u32()?;
+    if version != FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported bytecode format version: {}, expected: {}",
+            version, FORMAT_VERSION
+        )));
+    }
+    let objects = reader.objects()?;
+    let packages = reader.vec(|r| r.key())?;
+    let ifaces = reader.vec(|r| {
+        let meta = r.meta()?;
+        let methods = r.option(|r| r.vec(|r| r.key()))?.map(|x| Rc::new(x));
+        Ok((meta, methods))
+    })?;
+    let entry = reader.key()?;
+    let fs = reader.option(|r| r.file_set())?;
+    let bc = ByteCode {
+        objects: objects,
+        packages: packages,
+        ifaces: ifaces,
+        entry: entry,
+    };
+    Ok((bc, fs))
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// inserts the values in the order of their keys, and makes sure that the
+// arena hands out the same keys as the ones recorded
+fn fill_arena<K: Key + Copy, V>(
+    arena: &mut DenseSlotMap<K, V>,
+    mut items: Vec<(K, V)>,
+    kind: &str,
+) -> io::Result<()> {
+    items.sort_by_key(|(k, _)| key_to_u64(*k) & 0xffff_ffff);
+    for (k, v) in items.into_iter() {
+        if key_to_u64(arena.insert(v)) != key_to_u64(k) {
+            return Err(invalid_data(format!("unexpected {} key", kind)));
+        }
+    }
+    Ok(())
+}
+
+struct Writer<'a> {
+    w: &'a mut dyn Write,
+}
+
+impl<'a> Writer<'a> {
+    fn u8(&mut self, v: u8) -> io::Result<()> {
+        self.w.write_all(&[v])
+    }
+
+    fn bool(&mut self, v: bool) -> io::Result<()> {
+        self.u8(v as u8)
+    }
+
+    fn u16(&mut self, v: u16) -> io::Result<()> {
+        self.w.write_all(&v.to_le_bytes())
+    }
+
+    fn u32(&mut self, v: u32) -> io::Result<()> {
+        self.w.write_all(&v.to_le_bytes())
+    }
+
+    fn u64(&mut self, v: u64) -> io::Result<()> {
+        self.w.write_all(&v.to_le_bytes())
+    }
+
+    fn usize(&mut self, v: usize) -> io::Result<()> {
+        self.u64(v as u64)
+    }
+
+    fn index(&mut self, v: OpIndex) -> io::Result<()> {
+        self.u32(v as u32)
+    }
+
+    fn str(&mut self, v: &str) -> io::Result<()> {
+        self.usize(v.len())?;
+        self.w.write_all(v.as_bytes())
+    }
+
+    fn key<K: Key>(&mut self, k: K) -> io::Result<()> {
+        self.u64(key_to_u64(k))
+    }
+
+    fn value_type(&mut self, t: ValueType) -> io::Result<()> {
+        self.u8(t as u8)
+    }
+
+    fn option<T, F>(&mut self, v: Option<T>, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Self, T) -> io::Result<()>,
+    {
+        match v {
+            Some(x) => {
+                self.bool(true)?;
+                f(self, x)
+            }
+            None => self.bool(false),
+        }
+    }
+
+    fn vec<T, F>(&mut self, v: &[T], f: F) -> io::Result<()>
+    where
+        F: Fn(&mut Self, &T) -> io::Result<()>,
+    {
+        self.usize(v.len())?;
+        for x in v.iter() {
+            f(self, x)?;
+        }
+        Ok(())
+    }
+
+    fn index_map(&mut self, m: &HashMap<String, OpIndex>) -> io::Result<()> {
+        let mut entries: Vec<(&String, &OpIndex)> = m.iter().collect();
+        entries.sort();
+        self.vec(&entries, |w, (k, v)| {
+            w.str(k)?;
+            w.index(**v)
+        })
+    }
+
+    fn meta(&mut self, m: &GosMetadata) -> io::Result<()> {
+        let (tag, km) = match m {
+            GosMetadata::Untyped => (0, None),
+            GosMetadata::NonPtr(k, c) => (1, Some((k, c))),
+            GosMetadata::Ptr1(k, c) => (2, Some((k, c))),
+            GosMetadata::Ptr2(k, c) => (3, Some((k, c))),
+            GosMetadata::Ptr3(k, c) => (4, Some((k, c))),
+            GosMetadata::Ptr4(k, c) => (5, Some((k, c))),
+            GosMetadata::Ptr5(k, c) => (6, Some((k, c))),
+            GosMetadata::Ptr6(k, c) => (7, Some((k, c))),
+            GosMetadata::Ptr7(k, c) => (8, Some((k, c))),
+        };
+        self.u8(tag)?;
+        if let Some((k, c)) = km {
+            self.key(*k)?;
+            self.u8(match c {
+                MetaCategory::Default => 0,
+                MetaCategory::Array => 1,
+                MetaCategory::Type => 2,
+                MetaCategory::ArrayType => 3,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn fields(&mut self, f: &Fields) -> io::Result<()> {
+        self.vec(&f.fields, |w, m| w.meta(m))?;
+        self.index_map(&f.mapping)
+    }
+
+    fn metadata_type(&mut self, t: &MetadataType) -> io::Result<()> {
+        match t {
+            MetadataType::Bool => self.u8(0),
+            MetadataType::Int => self.u8(1),
+            MetadataType::Int8 => self.u8(2),
+            MetadataType::Int16 => self.u8(3),
+            MetadataType::Int32 => self.u8(4),
+            MetadataType::Int64 => self.u8(5),
+            MetadataType::Uint => self.u8(6),
+            MetadataType::Uint8 => self.u8(7),
+            MetadataType::Uint16 => self.u8(8),
+            MetadataType::Uint32 => self.u8(9),
+            MetadataType::Uint64 => self.u8(10),
+            MetadataType::Float32 => self.u8(11),
+            MetadataType::Float64 => self.u8(12),
+            MetadataType::Complex64 => self.u8(13),
+            MetadataType::Complex128 => self.u8(14),
+            MetadataType::Str(v) => {
+                self.u8(15)?;
+                self.value(v)
+            }
+            MetadataType::SliceOrArray(m, size) => {
+                self.u8(16)?;
+                self.meta(m)?;
+                self.usize(*size)
+            }
+            MetadataType::Struct(f, zero) => {
+                self.u8(17)?;
+                self.fields(f)?;
+                self.value(zero)
+            }
+            MetadataType::Signature(sig) => {
+                self.u8(18)?;
+                self.option(sig.recv.as_ref(), |w, m| w.meta(m))?;
+                self.vec(&sig.params, |w, m| w.meta(m))?;
+                self.vec(&sig.results, |w, m| w.meta(m))?;
+                self.option(sig.variadic.as_ref(), |w, (a, b)| {
+                    w.meta(a)?;
+                    w.meta(b)
+                })?;
+                self.vec(&sig.params_type, |w, t| w.value_type(*t))
+            }
+            MetadataType::Map(k, v) => {
+                self.u8(19)?;
+                self.meta(k)?;
+                self.meta(v)
+            }
+            MetadataType::Interface(f) => {
+                self.u8(20)?;
+                self.fields(f)
+            }
+            MetadataType::Channel(t, m) => {
+                self.u8(21)?;
+                self.u8(match t {
+                    ChannelType::Send => 0,
+                    ChannelType::Recv => 1,
+                    ChannelType::SendRecv => 2,
+                })?;
+                self.meta(m)
+            }
+            MetadataType::Named(methods, m) => {
+                self.u8(22)?;
+                self.vec(&methods.members, |w, desc| {
+                    let d = desc.borrow();
+                    w.bool(d.pointer_recv)?;
+                    w.option(d.func, |w, k| w.key(k))
+                })?;
+                self.index_map(&methods.mapping)?;
+                self.meta(m)
+            }
+        }
+    }
+
+    fn value(&mut self, v: &GosValue) -> io::Result<()> {
+        match v {
+            GosValue::Nil(m) => {
+                self.u8(0)?;
+                self.meta(m)
+            }
+            GosValue::Bool(b) => {
+                self.u8(1)?;
+                self.bool(*b)
+            }
+            GosValue::Int(i) => {
+                self.u8(2)?;
+                self.u64(*i as u64)
+            }
+            GosValue::Int8(i) => {
+                self.u8(3)?;
+                self.u8(*i as u8)
+            }
+            GosValue::Int16(i) => {
+                self.u8(4)?;
+                self.u16(*i as u16)
+            }
+            GosValue::Int32(i) => {
+                self.u8(5)?;
+                self.u32(*i as u32)
+            }
+            GosValue::Int64(i) => {
+                self.u8(6)?;
+                self.u64(*i as u64)
+            }
+            GosValue::Uint(i) => {
+                self.u8(7)?;
+                self.u64(*i as u64)
+            }
+            GosValue::Uint8(i) => {
+                self.u8(8)?;
+                self.u8(*i)
+            }
+            GosValue::Uint16(i) => {
+                self.u8(9)?;
+                self.u16(*i)
+            }
+            GosValue::Uint32(i) => {
+                self.u8(10)?;
+                self.u32(*i)
+            }
+            GosValue::Uint64(i) => {
+                self.u8(11)?;
+                self.u64(*i)
+            }
+            GosValue::Float32(f) => {
+                self.u8(12)?;
+                self.u32(f.into_inner().to_bits())
+            }
+            GosValue::Float64(f) => {
+                self.u8(13)?;
+                self.u64(f.into_inner().to_bits())
+            }
+            GosValue::Complex64(r, i) => {
+                self.u8(14)?;
+                self.u32(r.into_inner().to_bits())?;
+                self.u32(i.into_inner().to_bits())
+            }
+            GosValue::Complex128(c) => {
+                self.u8(15)?;
+                self.u64(c.0.into_inner().to_bits())?;
+                self.u64(c.1.into_inner().to_bits())
+            }
+            GosValue::Function(k) => {
+                self.u8(16)?;
+                self.key(*k)
+            }
+            GosValue::Package(k) => {
+                self.u8(17)?;
+                self.key(*k)
+            }
+            GosValue::Metadata(m) => {
+                self.u8(18)?;
+                self.meta(m)
+            }
+            GosValue::Str(s) => {
+                self.u8(19)?;
+                self.str(s.as_str())
+            }
+            GosValue::Array(a) => {
+                self.u8(20)?;
+                self.meta(&a.0.meta)?;
+                let data: Vec<GosValue> =
+                    a.0.borrow_data()
+                        .iter()
+                        .map(|x| x.borrow().clone())
+                        .collect();
+                self.vec(&data, |w, x| w.value(x))
+            }
+            GosValue::Slice(s) => {
+                self.u8(21)?;
+                self.meta(&s.0.meta)?;
+                let data: Option<Vec<GosValue>> = if s.0.is_nil() {
+                    None
+                } else {
+                    Some((0..s.0.len()).map(|i| s.0.get(i).unwrap()).collect())
+                };
+                self.option(data.as_ref(), |w, d| w.vec(d, |w, x| w.value(x)))
+            }
+            GosValue::Map(m) => {
+                self.u8(22)?;
+                self.meta(&m.0.meta)?;
+                self.value(&m.0.default_val())?;
+                let data: Option<Vec<(GosValue, GosValue)>> = if m.0.is_nil() {
+                    None
+                } else {
+                    Some(
+                        m.0.borrow_data()
+                            .iter()
+                            .map(|(k, v)| (k.clone(), v.borrow().clone()))
+                            .collect(),
+                    )
+                };
+                self.option(data.as_ref(), |w, d| {
+                    w.vec(d, |w, (k, v)| {
+                        w.value(k)?;
+                        w.value(v)
+                    })
+                })
+            }
+            GosValue::Struct(s) => {
+                self.u8(23)?;
+                let sobj = s.0.borrow();
+                self.meta(&sobj.meta)?;
+                self.vec(&sobj.fields, |w, x| w.value(x))
+            }
+            GosValue::Named(n) => {
+                self.u8(24)?;
+                self.value(&n.0)?;
+                self.meta(&n.1)
+            }
+            GosValue::Closure(c) => {
+                let cls = c.0.borrow();
+                match (cls.func, &cls.recv, &cls.ffi) {
+                    (Some(f), None, None) => {
+                        self.u8(25)?;
+                        self.key(f)
+                    }
+                    _ => Err(invalid_data("cannot serialize runtime closure".to_string())),
+                }
+            }
+            GosValue::Pointer(_) | GosValue::Interface(_) | GosValue::Channel(_) => {
+                Err(invalid_data(format!(
+                    "cannot serialize runtime value: {:?}",
+                    v.get_type()
+                )))
+            }
+        }
+    }
+
+    fn function(&mut self, f: &FunctionVal) -> io::Result<()> {
+        self.key(f.package)?;
+        self.meta(&f.meta)?;
+        self.str(&f.name)?;
+        self.u8(match f.flag {
+            FuncFlag::Default => 0,
+            FuncFlag::PkgCtor => 1,
+            FuncFlag::HasDefer => 2,
+        })?;
+        self.vec(f.code(), |w, i| w.u64(i.get_u64()))?;
+        self.vec(f.pos(), |w, p| w.option(*p, |w, x| w.usize(x)))?;
+        self.vec(&f.consts, |w, v| w.value(v))?;
+        self.vec(&f.up_ptrs, |w, d| {
+            w.key(d.func)?;
+            w.index(d.index)?;
+            w.value_type(d.typ)?;
+            w.bool(d.is_up_value)
+        })?;
+        self.vec(&f.ret_zeros, |w, v| w.value(v))?;
+        self.vec(&f.local_zeros, |w, v| w.value(v))?;
+        self.usize(f.local_count())
+    }
+
+    fn package(&mut self, p: &PackageVal) -> io::Result<()> {
+        self.str(p.name())?;
+        let (members, indices, var_mapping, var_metas) = p.parts();
+        self.vec(&members, |w, v| w.value(v))?;
+        self.index_map(indices)?;
+        self.option(var_mapping, |w, m| {
+            let mut entries: Vec<(&OpIndex, &OpIndex)> = m.iter().collect();
+            entries.sort();
+            w.vec(&entries, |w, (k, v)| {
+                w.index(**k)?;
+                w.index(**v)
+            })
+        })?;
+        let mut metas: Vec<(&OpIndex, &GosMetadata)> = var_metas.iter().collect();
+        metas.sort_by_key(|(k, _)| **k);
+        self.vec(&metas, |w, (k, m)| {
+            w.index(**k)?;
+            w.meta(m)
+        })
+    }
+
+    fn objects(&mut self, objs: &VMObjects) -> io::Result<()> {
+        let metas: Vec<(MetadataKey, &MetadataType)> = objs.metas.iter().collect();
+        self.vec(&metas, |w, (k, t)| {
+            w.key(*k)?;
+            w.metadata_type(t)
+        })?;
+        let md = &objs.metadata;
+        for m in [
+            &md.mbool,
+            &md.mint,
+            &md.mint8,
+            &md.mint16,
+            &md.mint32,
+            &md.mint64,
+            &md.muint,
+            &md.muint8,
+            &md.muint16,
+            &md.muint32,
+            &md.muint64,
+            &md.mfloat32,
+            &md.mfloat64,
+            &md.mcomplex64,
+            &md.mcomplex128,
+            &md.mstr,
+            &md.default_sig,
+            &md.empty_iface,
+        ]
+        .iter()
+        {
+            self.meta(m)?;
+        }
+        let funcs: Vec<(FunctionKey, &FunctionVal)> = objs.functions.iter().collect();
+        self.vec(&funcs, |w, (k, f)| {
+            w.key(*k)?;
+            w.function(f)
+        })?;
+        let pkgs: Vec<(PackageKey, &PackageVal)> = objs.packages.iter().collect();
+        self.vec(&pkgs, |w, (k, p)| {
+            w.key(*k)?;
+            w.package(p)
+        })
+    }
+
+    fn file_set(&mut self, fs: &FileSet) -> io::Result<()> {
+        let files: Vec<_> = fs.iter().collect();
+        self.vec(&files, |w, f| {
+            w.str(f.name())?;
+            w.usize(f.base())?;
+            w.usize(f.size())?;
+            w.vec(f.lines(), |w, l| w.usize(*l))
+        })
+    }
+}
+
+struct Reader<'a> {
+    r: &'a mut dyn Read,
+    // values created by the loader are static data, like the ones created
+    // by the code generator, they are not managed by the gc of any VM
+    gcv: GcoVec,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0; 1];
+        self.r.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn bool(&mut self) -> io::Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            x => Err(invalid_data(format!("invalid bool: {}", x))),
+        }
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let mut buf = [0; 2];
+        self.r.read_exact(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.r.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        self.r.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn usize(&mut self) -> io::Result<usize> {
+        Ok(self.u64()? as usize)
+    }
+
+    fn index(&mut self) -> io::Result<OpIndex> {
+        Ok(self.u32()? as OpIndex)
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let len = self.usize()?;
+        let mut buf = vec![];
+        (&mut *self.r).take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(buf).map_err(|e| invalid_data(e.to_string()))
+    }
+
+    fn key<K: Key>(&mut self) -> io::Result<K> {
+        Ok(u64_to_key(self.u64()?))
+    }
+
+    fn value_type(&mut self) -> io::Result<ValueType> {
+        let v = self.u8()?;
+        ValueType::from_u8(v).ok_or_else(|| invalid_data(format!("invalid value type: {}", v)))
+    }
+
+    fn option<T, F>(&mut self, f: F) -> io::Result<Option<T>>
+    where
+        F: FnOnce(&mut Self) -> io::Result<T>,
+    {
+        if self.bool()? {
+            Ok(Some(f(self)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn vec<T, F>(&mut self, f: F) -> io::Result<Vec<T>>
+    where
+        F: Fn(&mut Self) -> io::Result<T>,
+    {
+        let len = self.usize()?;
+        // don't trust the length for preallocation
+        let mut result = Vec::with_capacity(len.min(DEFAULT_CAPACITY));
+        for _ in 0..len {
+            result.push(f(self)?);
+        }
+        Ok(result)
+    }
+
+    fn index_map(&mut self) -> io::Result<HashMap<String, OpIndex>> {
+        Ok(self
+            .vec(|r| Ok((r.str()?, r.index()?)))?
+            .into_iter()
+            .collect())
+    }
+
+    fn meta(&mut self) -> io::Result<GosMetadata> {
+        let tag = self.u8()?;
+        if tag == 0 {
+            return Ok(GosMetadata::Untyped);
+        }
+        let k = self.key()?;
+        let c = match self.u8()? {
+            0 => MetaCategory::Default,
+            1 => MetaCategory::Array,
+            2 => MetaCategory::Type,
+            3 => MetaCategory::ArrayType,
+            x => return Err(invalid_data(format!("invalid meta category: {}", x))),
+        };
+        match tag {
+            1 => Ok(GosMetadata::NonPtr(k, c)),
+            2 => Ok(GosMetadata::Ptr1(k, c)),
+            3 => Ok(GosMetadata::Ptr2(k, c)),
+            4 => Ok(GosMetadata::Ptr3(k, c)),
+            5 => Ok(GosMetadata::Ptr4(k, c)),
+            6 => Ok(GosMetadata::Ptr5(k, c)),
+            7 => Ok(GosMetadata::Ptr6(k, c)),
+            8 => Ok(GosMetadata::Ptr7(k, c)),
+            x => Err(invalid_data(format!("invalid metadata: {}", x))),
+        }
+    }
+
+    fn fields(&mut self) -> io::Result<Fields> {
+        let fields = self.vec(|r| r.meta())?;
+        let mapping = self.index_map()?;
+        Ok(Fields::new(fields, mapping))
+    }
+
+    fn metadata_type(&mut self, funcs: &FunctionObjs) -> io::Result<MetadataType> {
+        Ok(match self.u8()? {
+            0 => MetadataType::Bool,
+            1 => MetadataType::Int,
+            2 => MetadataType::Int8,
+            3 => MetadataType::Int16,
+            4 => MetadataType::Int32,
+            5 => MetadataType::Int64,
+            6 => MetadataType::Uint,
+            7 => MetadataType::Uint8,
+            8 => MetadataType::Uint16,
+            9 => MetadataType::Uint32,
+            10 => MetadataType::Uint64,
+            11 => MetadataType::Float32,
+            12 => MetadataType::Float64,
+            13 => MetadataType::Complex64,
+            14 => MetadataType::Complex128,
+            15 => MetadataType::Str(self.value(funcs)?),
+            16 => MetadataType::SliceOrArray(self.meta()?, self.usize()?),
+            17 => MetadataType::Struct(self.fields()?, self.value(funcs)?),
+            18 => MetadataType::Signature(SigMetadata {
+                recv: self.option(|r| r.meta())?,
+                params: self.vec(|r| r.meta())?,
+                results: self.vec(|r| r.meta())?,
+                variadic: self.option(|r| Ok((r.meta()?, r.meta()?)))?,
+                params_type: self.vec(|r| r.value_type())?,
+            }),
+            19 => MetadataType::Map(self.meta()?, self.meta()?),
+            20 => MetadataType::Interface(self.fields()?),
+            21 => {
+                let t = match self.u8()? {
+                    0 => ChannelType::Send,
+                    1 => ChannelType::Recv,
+                    2 => ChannelType::SendRecv,
+                    x => return Err(invalid_data(format!("invalid channel type: {}", x))),
+                };
+                MetadataType::Channel(t, self.meta()?)
+            }
+            22 => {
+                let members = self.vec(|r| {
+                    Ok(Rc::new(RefCell::new(MethodDesc {
+                        pointer_recv: r.bool()?,
+                        func: r.option(|r| r.key())?,
+                    })))
+                })?;
+                let mapping = self.index_map()?;
+                MetadataType::Named(
+                    Methods {
+                        members: members,
+                        mapping: mapping,
+                    },
+                    self.meta()?,
+                )
+            }
+            x => return Err(invalid_data(format!("invalid metadata type: {}", x))),
+        })
+    }
+
+    // closures can only be created when funcs is filled
+    fn value(&mut self, funcs: &FunctionObjs) -> io::Result<GosValue> {
+        Ok(match self.u8()? {
+            0 => GosValue::Nil(self.meta()?),
+            1 => GosValue::Bool(self.bool()?),
+            2 => GosValue::Int(self.u64()? as isize),
+            3 => GosValue::Int8(self.u8()? as i8),
+            4 => GosValue::Int16(self.u16()? as i16),
+            5 => GosValue::Int32(self.u32()? as i32),
+            6 => GosValue::Int64(self.u64()? as i64),
+            7 => GosValue::Uint(self.u64()? as usize),
+            8 => GosValue::Uint8(self.u8()?),
+            9 => GosValue::Uint16(self.u16()?),
+            10 => GosValue::Uint32(self.u32()?),
+            11 => GosValue::Uint64(self.u64()?),
+            12 => GosValue::Float32(f32::from_bits(self.u32()?).into()),
+            13 => GosValue::Float64(f64::from_bits(self.u64()?).into()),
+            14 => GosValue::Complex64(
+                f32::from_bits(self.u32()?).into(),
+                f32::from_bits(self.u32()?).into(),
+            ),
+            15 => GosValue::Complex128(Box::new((
+                f64::from_bits(self.u64()?).into(),
+                f64::from_bits(self.u64()?).into(),
+            ))),
+            16 => GosValue::Function(self.key()?),
+            17 => GosValue::Package(self.key()?),
+            18 => GosValue::Metadata(self.meta()?),
+            19 => GosValue::new_str(self.str()?),
+            20 => {
+                let meta = self.meta()?;
+                let data = self.vec(|r| r.value(funcs))?;
+                GosValue::array_with_val(data, meta, &self.gcv)
+            }
+            21 => {
+                let meta = self.meta()?;
+                match self.option(|r| r.vec(|r| r.value(funcs)))? {
+                    Some(data) => GosValue::slice_with_val(data, meta, &self.gcv),
+                    None => GosValue::new_slice_nil(meta, &self.gcv),
+                }
+            }
+            22 => {
+                let meta = self.meta()?;
+                let default_val = self.value(funcs)?;
+                let data = self.option(|r| r.vec(|r| Ok((r.value(funcs)?, r.value(funcs)?))))?;
+                match data {
+                    Some(entries) => {
+                        let m = GosValue::new_map(meta, default_val, &self.gcv);
+                        for (k, v) in entries.into_iter() {
+                            m.as_map().0.insert(k, v);
+                        }
+                        m
+                    }
+                    None => GosValue::new_map_nil(meta, default_val, &self.gcv),
+                }
+            }
+            23 => {
+                let meta = self.meta()?;
+                let fields = self.vec(|r| r.value(funcs))?;
+                GosValue::new_struct(
+                    StructObj {
+                        meta: meta,
+                        fields: fields,
+                    },
+                    &self.gcv,
+                )
+            }
+            24 => {
+                let val = self.value(funcs)?;
+                GosValue::Named(Box::new((val, self.meta()?)))
+            }
+            25 => {
+                let k: FunctionKey = self.key()?;
+                if !funcs.contains_key(k) {
+                    return Err(invalid_data("closure of unknown function".to_string()));
+                }
+                GosValue::new_closure(k, funcs)
+            }
+            x => return Err(invalid_data(format!("invalid value: {}", x))),
+        })
+    }
+
+    fn function(&mut self, objs: &VMObjects) -> io::Result<FunctionVal> {
+        let package = self.key()?;
+        let meta = self.meta()?;
+        match meta {
+            GosMetadata::NonPtr(k, _) if objs.metas.contains_key(k) => match &objs.metas[k] {
+                MetadataType::Signature(_) => {}
+                _ => return Err(invalid_data("invalid function metadata".to_string())),
+            },
+            _ => return Err(invalid_data("invalid function metadata".to_string())),
+        }
+        let name = self.str()?;
+        let flag = match self.u8()? {
+            0 => FuncFlag::Default,
+            1 => FuncFlag::PkgCtor,
+            2 => FuncFlag::HasDefer,
+            x => return Err(invalid_data(format!("invalid function flag: {}", x))),
+        };
+        let mut f = FunctionVal::new(package, meta, objs, &self.gcv, flag);
+        f.name = name;
+        let code = self.vec(|r| r.u64())?;
+        let pos = self.vec(|r| r.option(|r| r.usize()))?;
+        if code.len() != pos.len() {
+            return Err(invalid_data("invalid debug info".to_string()));
+        }
+        let mut pkg_key_next = false;
+        for (i, p) in code.into_iter().zip(pos.into_iter()) {
+            let inst = Instruction::from_u64(i);
+            // the word after a package instruction is a package key
+            if pkg_key_next {
+                pkg_key_next = false;
+            } else if inst.is_valid() {
+                pkg_key_next = inst.op().takes_pkg_key();
+            } else {
+                return Err(invalid_data(format!("invalid instruction: {:#018x}", i)));
+            }
+            f.push_inst_pos(inst, p);
+        }
+        if pkg_key_next {
+            return Err(invalid_data("missing package key".to_string()));
+        }
+        f.consts = self.vec(|r| r.value(&objs.functions))?;
+        f.up_ptrs = self.vec(|r| {
+            Ok(ValueDesc::new(
+                r.key()?,
+                r.index()?,
+                r.value_type()?,
+                r.bool()?,
+            ))
+        })?;
+        f.ret_zeros = self.vec(|r| r.value(&objs.functions))?;
+        f.local_zeros = self.vec(|r| r.value(&objs.functions))?;
+        let local_count = self.usize()?;
+        if local_count != f.local_zeros.len() {
+            return Err(invalid_data("invalid local count".to_string()));
+        }
+        for _ in 0..(f.param_count() + f.ret_count() + local_count) {
+            f.add_local(None);
+        }
+        Ok(f)
+    }
+
+    fn package(&mut self, funcs: &FunctionObjs) -> io::Result<PackageVal> {
+        let name = self.str()?;
+        let members = self.vec(|r| r.value(funcs))?;
+        let indices = self.index_map()?;
+        let var_mapping = self.option(|r| {
+            Ok(r.vec(|r| Ok((r.index()?, r.index()?)))?
+                .into_iter()
+                .collect())
+        })?;
+        let var_metas = self
+            .vec(|r| Ok((r.index()?, r.meta()?)))?
+            .into_iter()
+            .collect();
+        Ok(PackageVal::from_parts(
+            name,
+            members,
+            indices,
+            var_mapping,
+            var_metas,
+        ))
+    }
+
+    fn objects(&mut self) -> io::Result<Pin<Box<VMObjects>>> {
+        let mut metas = DenseSlotMap::with_capacity_and_key(DEFAULT_CAPACITY);
+        let mut functions = DenseSlotMap::with_capacity_and_key(DEFAULT_CAPACITY);
+        let mut packages = DenseSlotMap::with_capacity_and_key(DEFAULT_CAPACITY);
+
+        let items = self.vec(|r| Ok((r.key()?, r.metadata_type(&functions)?)))?;
+        fill_arena(&mut metas, items, "metadata")?;
+        let metadata = Metadata {
+            mbool: self.meta()?,
+            mint: self.meta()?,
+            mint8: self.meta()?,
+            mint16: self.meta()?,
+            mint32: self.meta()?,
+            mint64: self.meta()?,
+            muint: self.meta()?,
+            muint8: self.meta()?,
+            muint16: self.meta()?,
+            muint32: self.meta()?,
+            muint64: self.meta()?,
+            mfloat32: self.meta()?,
+            mfloat64: self.meta()?,
+            mcomplex64: self.meta()?,
+            mcomplex128: self.meta()?,
+            mstr: self.meta()?,
+            default_sig: self.meta()?,
+            empty_iface: self.meta()?,
+        };
+        let mut objs = VMObjects {
+            metas: metas,
+            functions: DenseSlotMap::with_key(),
+            packages: DenseSlotMap::with_key(),
+            metadata: metadata,
+        };
+
+        let items = self.vec(|r| Ok((r.key()?, r.function(&objs)?)))?;
+        fill_arena(&mut functions, items, "function")?;
+        objs.functions = functions;
+
+        let items = self.vec(|r| Ok((r.key()?, r.package(&objs.functions)?)))?;
+        fill_arena(&mut packages, items, "package")?;
+        objs.packages = packages;
+        Ok(Box::pin(objs))
+    }
+
+    fn file_set(&mut self) -> io::Result<FileSet> {
+        let mut fs = FileSet::new();
+        let files = self.vec(|r| Ok((r.str()?, r.usize()?, r.usize()?, r.vec(|r| r.usize())?)))?;
+        for (name, base, size, lines) in files.into_iter() {
+            if base < fs.base() {
+                return Err(invalid_data("invalid file base".to_string()));
+            }
+            if !fs.add_file(name, Some(base), size).set_lines(lines) {
+                return Err(invalid_data("invalid file lines".to_string()));
+            }
+        }
+        Ok(fs)
+    }
+}
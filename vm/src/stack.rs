@@ -2,6 +2,7 @@
 use super::gc::GcoVec;
 use super::instruction::{Instruction, OpIndex, Opcode, ValueType};
 use super::metadata::GosMetadata;
+use super::objects::ChannelObj;
 use super::value::*;
 use std::cell::RefCell;
 use std::cmp::Ordering;
@@ -54,14 +55,13 @@ macro_rules! store_to_with_op {
         $ri:expr,
         $op:expr,
         $t:expr) => {{
+        // the left operand is the value stored in $to
         if $t.copyable() {
-            let a = $from.get_c($li);
-            let b = $from.get_c($ri);
-            *$to.get_c_mut($li) = GosValue64::binary_op(a, b, $t, $op);
+            let v = GosValue64::binary_op($to.get_c($li), $from.get_c($ri), $t, $op);
+            *$to.get_c_mut($li) = v;
         } else {
-            let a = $from.get_rc($li);
-            let b = $from.get_rc($ri);
-            *$to.get_rc_mut($li) = GosValue::add_str(a, b);
+            let v = GosValue::add_str($to.get_rc($li), $from.get_rc($ri));
+            *$to.get_rc_mut($li) = v;
         }
     }};
 }
@@ -637,77 +637,96 @@ impl Stack {
     }
 }
 
+enum RangeIter {
+    Map(GosHashMapIter<'static>),
+    Slice(SliceEnumIter<'static>),
+    Str(StringEnumIter<'static>),
+    Chan(Rc<ChannelObj>),
+}
+
 /// store iterators for Opcode::RANGE
 pub struct RangeStack {
-    maps: Vec<GosHashMapIter<'static>>,
-    slices: Vec<SliceEnumIter<'static>>,
-    strings: Vec<StringEnumIter<'static>>,
+    // the iterators go with the containers iterated over, which are kept
+    // alive as the iterators borrow them, a temporary one is no longer on
+    // the stack
+    iters: Vec<(RangeIter, GosValue)>,
 }
 
 impl RangeStack {
     pub fn new() -> RangeStack {
-        RangeStack {
-            maps: vec![],
-            slices: vec![],
-            strings: vec![],
-        }
+        RangeStack { iters: vec![] }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.iters.len()
+    }
+
+    /// truncate drops the iterators of the loops left, by Opcode::RANGE_END
+    /// or when a function returns.
+    #[inline]
+    pub fn truncate(&mut self, len: usize) {
+        self.iters.truncate(len);
     }
 
     pub fn range_init(&mut self, target: &GosValue) {
-        match target {
+        let iter = match target {
             GosValue::Map(m) => {
                 let map = m.0.borrow_data();
-                let iter = unsafe { mem::transmute(map.iter()) };
-                self.maps.push(iter);
+                RangeIter::Map(unsafe { mem::transmute(map.iter()) })
+            }
+            // ranging over a nil slice runs zero times
+            GosValue::Slice(sl) if sl.0.is_nil() => {
+                RangeIter::Slice(<&[RefCell<GosValue>]>::default().iter().enumerate())
             }
             GosValue::Slice(sl) => {
                 let slice = sl.0.borrow();
-                let iter = unsafe { mem::transmute(slice.iter().enumerate()) };
-                self.slices.push(iter);
-            }
-            GosValue::Str(s) => {
-                let iter = unsafe { mem::transmute(s.iter().enumerate()) };
-                self.strings.push(iter);
+                RangeIter::Slice(unsafe { mem::transmute(slice.iter().enumerate()) })
             }
+            GosValue::Str(s) => RangeIter::Str(unsafe { mem::transmute(s.iter().enumerate()) }),
+            GosValue::Channel(c) => RangeIter::Chan(c.clone()),
             _ => unreachable!(),
-        }
+        };
+        self.iters.push((iter, target.clone()));
     }
 
-    pub fn range_body(&mut self, typ: ValueType, stack: &mut Stack) -> bool {
-        match typ {
-            ValueType::Map => match self.maps.last_mut().unwrap().next() {
+    /// range_body pushes the next key and value, or returns true if there
+    /// are no more of them.
+    pub fn range_body(&mut self, stack: &mut Stack) -> bool {
+        match &mut self.iters.last_mut().unwrap().0 {
+            RangeIter::Map(iter) => match iter.next() {
                 Some((k, v)) => {
                     stack.push(k.clone());
                     stack.push(v.clone().into_inner());
                     false
                 }
-                None => {
-                    self.maps.pop();
-                    true
-                }
+                None => true,
             },
-            ValueType::Slice => match self.slices.last_mut().unwrap().next() {
+            RangeIter::Slice(iter) => match iter.next() {
                 Some((k, v)) => {
                     stack.push_int(k as isize);
                     stack.push(v.clone().into_inner());
                     false
                 }
-                None => {
-                    self.slices.pop();
-                    true
-                }
+                None => true,
             },
-            ValueType::Str => match self.strings.last_mut().unwrap().next() {
+            RangeIter::Str(iter) => match iter.next() {
                 Some((k, v)) => {
                     stack.push_int(k as isize);
                     stack.push_int(v as isize);
                     false
                 }
-                None => {
-                    self.strings.pop();
-                    true
-                }
+                None => true,
             },
+            RangeIter::Chan(_) => unreachable!(),
+        }
+    }
+
+    /// range_chan returns the channel of the innermost range loop over a
+    /// channel, the VM receives from it, as that has to be awaited.
+    pub fn range_chan(&self) -> Rc<ChannelObj> {
+        match &self.iters.last().unwrap().0 {
+            RangeIter::Chan(c) => c.clone(),
             _ => unreachable!(),
         }
     }
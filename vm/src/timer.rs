@@ -0,0 +1,273 @@
+use super::value::GosValue;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Timers holds the wakers of the sleeping goroutines by their deadlines,
+/// the scheduler wakes them when they are due. It also holds the timers of
+/// the time package, which run a Go function on a new goroutine when they
+/// are due.
+#[derive(Debug, Default)]
+pub struct Timers {
+    next_id: u64,
+    pending: BTreeMap<(Instant, u64), Waker>,
+    // the functions run by the timers, and their periods, the ones with a
+    // period are due again after it
+    events: BTreeMap<(Instant, u64), (GosValue, Option<Duration>)>,
+    // set when a sleep starts waiting, so that the fiber can be shown as
+    // sleeping rather than waiting for an async call
+    started: bool,
+}
+
+impl Timers {
+    pub fn new() -> Timers {
+        Timers::default()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.events.is_empty()
+    }
+
+    /// has_events tells if any timer is to run a function.
+    #[inline]
+    pub fn has_events(&self) -> bool {
+        !self.events.is_empty()
+    }
+
+    /// next_deadline returns the earliest deadline of the pending timers.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let sleep = self.pending.keys().next().map(|(d, _)| *d);
+        let event = self.events.keys().next().map(|(d, _)| *d);
+        match (sleep, event) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// add_event adds a timer running f at deadline, and every period after
+    /// that if there is one, it returns the id of the timer.
+    pub fn add_event(&mut self, deadline: Instant, period: Option<Duration>, f: GosValue) -> u64 {
+        self.next_id += 1;
+        self.events.insert((deadline, self.next_id), (f, period));
+        self.next_id
+    }
+
+    /// remove_event removes the timer with the id, it returns false if the
+    /// timer was not pending.
+    pub fn remove_event(&mut self, id: u64) -> bool {
+        let key = self.events.keys().find(|(_, i)| *i == id).copied();
+        key.map_or(false, |k| self.events.remove(&k).is_some())
+    }
+
+    /// wake_due wakes the timers due at now, and returns the functions the
+    /// ones of the time package run.
+    pub fn wake_due(&mut self, now: Instant) -> Vec<GosValue> {
+        while let Some((&(d, id), _)) = self.pending.iter().next() {
+            if d > now {
+                break;
+            }
+            self.pending.remove(&(d, id)).unwrap().wake();
+        }
+        let mut due = vec![];
+        while let Some((&(d, id), _)) = self.events.iter().next() {
+            if d > now {
+                break;
+            }
+            let (f, period) = self.events.remove(&(d, id)).unwrap();
+            if let Some(p) = period {
+                // the periods keep their phase, the ones missed are dropped
+                let missed = (now - d).as_nanos() / p.as_nanos() + 1;
+                let next = d + Duration::from_nanos((p.as_nanos() * missed) as u64);
+                self.events.insert((next, id), (f.clone(), period));
+            }
+            due.push(f);
+        }
+        due
+    }
+
+    /// take_started tells if a sleep started waiting since the last call.
+    pub fn take_started(&mut self) -> bool {
+        std::mem::replace(&mut self.started, false)
+    }
+}
+
+/// Sleep is a future ready at its deadline, it's woken by the scheduler
+/// owning timers.
+pub struct Sleep {
+    timers: Rc<RefCell<Timers>>,
+    deadline: Instant,
+    key: Option<(Instant, u64)>,
+}
+
+impl Sleep {
+    pub fn new(timers: Rc<RefCell<Timers>>, deadline: Instant) -> Sleep {
+        Sleep {
+            timers: timers,
+            deadline: deadline,
+            key: None,
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            if let Some(key) = self.key.take() {
+                self.timers.borrow_mut().pending.remove(&key);
+            }
+            return Poll::Ready(());
+        }
+        let timers = self.timers.clone();
+        let mut timers = timers.borrow_mut();
+        let key = match self.key {
+            Some(key) => key,
+            None => {
+                timers.next_id += 1;
+                timers.started = true;
+                let key = (self.deadline, timers.next_id);
+                self.key = Some(key);
+                key
+            }
+        };
+        timers.pending.insert(key, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.timers.borrow_mut().pending.remove(&key);
+        }
+    }
+}
+
+// Alarms holds the wakers of the pending alarms by their deadlines, a
+// single thread started by the first alarm wakes them when they are due.
+struct Alarms {
+    next_id: u64,
+    pending: BTreeMap<(Instant, u64), Waker>,
+    started: bool,
+}
+
+static ALARMS: Mutex<Alarms> = Mutex::new(Alarms {
+    next_id: 0,
+    pending: BTreeMap::new(),
+    started: false,
+});
+
+// notified when an alarm is added, so that the thread can sleep until the
+// new earliest deadline
+static ALARMS_ADDED: Condvar = Condvar::new();
+
+fn run_alarms() {
+    let mut alarms = ALARMS.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        let mut due = vec![];
+        while let Some((&(d, id), _)) = alarms.pending.iter().next() {
+            if d > now {
+                break;
+            }
+            due.push(alarms.pending.remove(&(d, id)).unwrap());
+        }
+        if !due.is_empty() {
+            // the wakers are called without the lock, as they may poll or
+            // drop alarms
+            drop(alarms);
+            due.into_iter().for_each(|w| w.wake());
+            alarms = ALARMS.lock().unwrap();
+            continue;
+        }
+        alarms = match alarms.pending.keys().next() {
+            Some(&(d, _)) => ALARMS_ADDED.wait_timeout(alarms, d - now).unwrap().0,
+            None => ALARMS_ADDED.wait(alarms).unwrap(),
+        };
+    }
+}
+
+/// Alarm is a future ready at its deadline, which is woken by the timer
+/// thread shared by all the alarms. The scheduler waits for it when no
+/// goroutine can run before a timer is due.
+pub struct Alarm {
+    deadline: Instant,
+    key: Option<(Instant, u64)>,
+}
+
+impl Alarm {
+    pub fn new(deadline: Instant) -> Alarm {
+        Alarm {
+            deadline: deadline,
+            key: None,
+        }
+    }
+}
+
+impl Future for Alarm {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            if let Some(key) = self.key.take() {
+                ALARMS.lock().unwrap().pending.remove(&key);
+            }
+            return Poll::Ready(());
+        }
+        let mut alarms = ALARMS.lock().unwrap();
+        match self.key {
+            Some(key) => {
+                alarms.pending.insert(key, cx.waker().clone());
+            }
+            None => {
+                alarms.next_id += 1;
+                let key = (self.deadline, alarms.next_id);
+                alarms.pending.insert(key, cx.waker().clone());
+                self.key = Some(key);
+                if !alarms.started {
+                    alarms.started = true;
+                    thread::spawn(run_alarms);
+                }
+                ALARMS_ADDED.notify_one();
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Alarm {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            ALARMS.lock().unwrap().pending.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_lite::future;
+
+    #[test]
+    fn test_alarm() {
+        let start = Instant::now();
+        future::block_on(Alarm::new(start + Duration::from_millis(50)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // a dropped alarm leaves nothing for the timer thread
+        let mut alarm = Alarm::new(start + Duration::from_secs(3600));
+        assert!(future::block_on(future::poll_once(&mut alarm)).is_none());
+        let key = alarm.key.unwrap();
+        assert!(ALARMS.lock().unwrap().pending.contains_key(&key));
+        drop(alarm);
+        assert!(!ALARMS.lock().unwrap().pending.contains_key(&key));
+    }
+}
@@ -439,6 +439,11 @@ impl GosValue {
         unwrap_gos_val!(Int32, self)
     }
 
+    #[inline]
+    pub fn as_int64(&self) -> &i64 {
+        unwrap_gos_val!(Int64, self)
+    }
+
     #[inline]
     pub fn as_int_mut(&mut self) -> &mut isize {
         unwrap_gos_val!(Int, self)
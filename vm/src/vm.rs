@@ -1,23 +1,33 @@
 #![allow(dead_code)]
 use super::channel;
-use super::ffi::FfiFactory;
+use super::ffi::{Chan, FfiCtx, FfiFactory, FfiFuture, FfiResult, FromGos, Runtime, ToGos};
 use super::gc::{gc, GcoVec};
 use super::instruction::*;
 use super::metadata::*;
-use super::objects::{u64_to_key, ClosureObj, GosHashMap};
+use super::objects::{u64_to_key, ClosureObj, FunctionVal, GosHashMap};
 use super::stack::{RangeStack, Stack};
+use super::timer::{Alarm, Sleep, Timers};
 use super::value::*;
 use super::vm_util;
-use async_executor::LocalExecutor;
-use futures_lite::future;
-use goscript_parser::FileSet;
+use async_executor::{LocalExecutor, Task};
+use futures_lite::future::{self, FutureExt};
+use goscript_parser::{FileSet, Position};
+use std::any::Any;
 use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryInto;
+use std::future::Future;
+use std::io::{self, Write};
+use std::mem;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
 use std::ptr;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub struct ByteCode {
@@ -27,6 +37,66 @@ pub struct ByteCode {
     pub entry: FunctionKey,
 }
 
+impl ByteCode {
+    /// missing_natives returns the names of the functions declared without a
+    /// body that are not registered with ffi, sorted.
+    pub fn missing_natives(&self, ffi: &FfiFactory) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .natives()
+            .into_iter()
+            .filter(|(name, _)| !ffi.has_native(name))
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// mismatched_natives checks the signatures of the functions declared
+    /// without a body against the functions registered with ffi, it returns
+    /// why they don't match, sorted by name.
+    pub fn mismatched_natives(&self, ffi: &FfiFactory) -> Vec<String> {
+        let metas = &self.objects.metas;
+        let mut errs: Vec<String> = self
+            .natives()
+            .into_iter()
+            .filter(|(name, _)| ffi.has_native(name))
+            .filter_map(|(name, f)| {
+                let sig = metas[f.meta.as_non_ptr()].as_signature();
+                let params: Vec<GosMetadata> =
+                    sig.recv.iter().chain(sig.params.iter()).copied().collect();
+                ffi.check_native(&name, &params, &sig.results, metas)
+                    .err()
+                    .map(|e| format!("{}: {}", name, e))
+            })
+            .collect();
+        errs.sort();
+        errs.dedup();
+        errs
+    }
+
+    // the functions declared without a body, with the names they are
+    // registered with
+    fn natives(&self) -> Vec<(String, &FunctionVal)> {
+        let mut natives = vec![];
+        for (_, f) in self.objects.functions.iter() {
+            let code = f.code();
+            let mut i = 0;
+            while i < code.len() {
+                let op = code[i].op();
+                if op == Opcode::NATIVE {
+                    let name = f.consts[code[i].imm() as usize].as_str();
+                    natives.push((name.as_str().to_string(), f));
+                } else if op.takes_pkg_key() {
+                    i += 1;
+                }
+                i += 1;
+            }
+        }
+        natives
+    }
+}
+
 #[derive(Clone, Debug)]
 struct Referers {
     typ: ValueType,
@@ -46,6 +116,9 @@ struct CallFrame {
     referred_by: Option<HashMap<OpIndex, Referers>>,
 
     defer_stack: Option<Vec<DeferredCall>>,
+    // the iterators of the range loops of the function are above this on the
+    // range stack
+    range_base: usize,
 }
 
 impl CallFrame {
@@ -57,6 +130,7 @@ impl CallFrame {
             var_ptrs: None,
             referred_by: None,
             defer_stack: None,
+            range_base: 0,
         }
     }
 
@@ -83,6 +157,27 @@ impl CallFrame {
         }
     }
 
+    // points the upvalues of the frame to the variables captured by cls, and
+    // to the local variables of its own that have pointers to them
+    fn set_var_ptrs(&mut self, cls: &ClosureObj, func: &FunctionVal, stack: &Rc<RefCell<Stack>>) {
+        if let Some(uvs) = &cls.uvs {
+            let mut ptrs: Vec<UpValue> = Vec::with_capacity(func.up_ptrs.len());
+            for (i, p) in func.up_ptrs.iter().enumerate() {
+                ptrs.push(if p.is_up_value {
+                    uvs[&i].clone()
+                } else {
+                    // local pointers
+                    let uv = UpValue::new(
+                        p.clone_with_stack(Rc::downgrade(stack), self.stack_base as OpIndex),
+                    );
+                    self.add_referred_by(p.index, p.typ, &uv);
+                    uv
+                });
+            }
+            self.var_ptrs = Some(ptrs);
+        }
+    }
+
     #[inline]
     fn func(&self) -> FunctionKey {
         self.closure.0.borrow().func.unwrap()
@@ -145,65 +240,993 @@ impl PanicData {
     }
 }
 
+/// Frame is a call in the call stack of a goroutine.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    // the fully-qualified Go name of the function, like "main.main",
+    // "main.(*T).Close" or "main.main.func1"
+    pub func: String,
+    // None if no debug info is available
+    pub pos: Option<Position>,
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}()", self.func)?;
+        match &self.pos {
+            Some(pos) => writeln!(f, "\t{}", pos),
+            None => writeln!(f, "\t<no debug info available>"),
+        }
+    }
+}
+
+/// Goroutine is the call stack of a fiber, printed like the goroutines of a
+/// Go traceback.
+#[derive(Clone, Debug)]
+pub struct Goroutine {
+    // like the goroutine ids of Go, the entry fiber is 1
+    pub id: usize,
+    // what it's doing, like "running", "runnable" or "chan receive"
+    pub status: &'static str,
+    // innermost frame first
+    pub call_stack: Vec<Frame>,
+}
+
+impl std::fmt::Display for Goroutine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "goroutine {} [{}]:", self.id, self.status)?;
+        for frame in self.call_stack.iter() {
+            write!(f, "{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// PanicInfo describes a panic that was not recovered, it's what the host gets
+/// back from GosVM::run when a script fails at runtime.
+#[derive(Clone, Debug)]
+pub struct PanicInfo {
+    // the value passed to panic(), or the runtime error as an empty interface
+    pub value: GosValue,
+    pub msg: String,
+    // the fiber that panicked
+    pub goroutine: Goroutine,
+}
+
+impl PanicInfo {
+    fn new(data: PanicData, id: usize, code: &ByteCode, fs: Option<&FileSet>) -> PanicInfo {
+        PanicInfo {
+            msg: data.msg.to_string(),
+            value: data.msg,
+            goroutine: Goroutine {
+                id: id,
+                status: "running",
+                call_stack: frames(&data.call_stack, code, fs),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for PanicInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "panic: {}\n", self.msg)?;
+        write!(f, "{}", self.goroutine)
+    }
+}
+
+/// StopInfo tells where the VM was when it's stopped before the script
+/// finished, for reasons other than a panic.
+#[derive(Clone, Debug)]
+pub struct StopInfo {
+    // all the fibers alive, the one that was running first, then the others
+    // by id
+    pub goroutines: Vec<Goroutine>,
+}
+
+impl std::fmt::Display for StopInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, g) in self.goroutines.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", g)?;
+        }
+        Ok(())
+    }
+}
+
+/// RuntimeError tells the host why the VM did not finish its work.
+#[derive(Clone, Debug)]
+pub enum RuntimeError {
+    // the function to call is not found, or the arguments don't match it
+    InvalidCall(String),
+    // the package member to get or set is not found, or the value doesn't
+    // match its type
+    InvalidGlobal(String),
+    // the host sends on a closed channel, or a value that doesn't match the
+    // channel, or receives one it can't convert
+    Channel(String),
+    // an unrecovered panic
+    Panic(PanicInfo),
+    // the instruction budget set by Limits is used up
+    BudgetExhausted(StopInfo),
+    // stopped by an InterruptHandle, or the timeout set by Limits expired
+    Interrupted(StopInfo),
+    // a Rust panic stopped a fiber, it's a bug of the VM, like an unsupported
+    // instruction, or of the FFI code
+    Internal(String),
+    // os.Exit ended the program with the code, run returns it as the result,
+    // call returns it as this error
+    Exit(i32),
+    // all the fibers are blocked on channels, with none of them waiting for
+    // async FFI calls or timers, so none of them can ever run again
+    Deadlock(Vec<Goroutine>),
+    // functions declared without a body are not registered with the
+    // FfiFactory, with their names, nothing is run
+    MissingNatives(Vec<String>),
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuntimeError::InvalidCall(msg) => writeln!(f, "invalid call: {}", msg),
+            RuntimeError::InvalidGlobal(msg) => writeln!(f, "invalid global: {}", msg),
+            RuntimeError::Channel(msg) => writeln!(f, "channel: {}", msg),
+            RuntimeError::Panic(p) => write!(f, "{}", p),
+            RuntimeError::BudgetExhausted(s) => {
+                writeln!(f, "instruction budget exhausted\n")?;
+                write!(f, "{}", s)
+            }
+            RuntimeError::Interrupted(s) => {
+                writeln!(f, "interrupted\n")?;
+                write!(f, "{}", s)
+            }
+            RuntimeError::Internal(msg) => writeln!(f, "internal error: {}", msg),
+            RuntimeError::Exit(code) => writeln!(f, "exit status {}", code),
+            RuntimeError::Deadlock(fibers) => {
+                writeln!(f, "fatal error: all goroutines are asleep - deadlock!")?;
+                for g in fibers.iter() {
+                    write!(f, "\n{}", g)?;
+                }
+                Ok(())
+            }
+            RuntimeError::MissingNatives(names) => {
+                writeln!(f, "native functions not registered: {}", names.join(", "))
+            }
+        }
+    }
+}
+
+/// Limits restricts the work done by each call to GosVM::run or GosVM::call,
+/// the VM stops all the fibers when any of the limits is reached.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    // the number of instructions executed by all the fibers
+    pub max_instructions: Option<u64>,
+    // the number of instructions executed by any single fiber
+    pub max_fiber_instructions: Option<u64>,
+    // the wall-clock time, it's checked as often as fibers yield, so a
+    // blocking FFI call can't be stopped by it
+    pub timeout: Option<Duration>,
+}
+
+/// InterruptHandle stops a GosVM from another thread, at the next point where
+/// a fiber yields, or right away if all of them are waiting, with
+/// RuntimeError::Interrupted. An interrupt that arrives when the VM is not
+/// running stops the next run or call; it's cleared once it has stopped one.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+    // the wakers of the VM waiting for anything, see wait
+    wakers: Arc<Mutex<Vec<Waker>>>,
+}
+
+impl InterruptHandle {
+    pub fn new() -> InterruptHandle {
+        InterruptHandle::default()
+    }
+
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+        let wakers = mem::take(&mut *self.wakers.lock().unwrap());
+        for w in wakers.into_iter() {
+            w.wake();
+        }
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    fn clear(&self) {
+        self.flag.store(false, Ordering::Relaxed);
+    }
+
+    // waits until interrupt is called
+    async fn wait(&self) {
+        future::poll_fn(|cx| {
+            if self.is_interrupted() {
+                return Poll::Ready(());
+            }
+            {
+                let mut wakers = self.wakers.lock().unwrap();
+                if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+            }
+            // it may have been interrupted before the waker was added
+            match self.is_interrupted() {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+// the (function, pc) pairs of frames, innermost first, the innermost one is
+// at its next instruction if next is true, the others are at their calls
+fn call_pcs(frames: &[CallFrame], next: bool) -> Vec<(FunctionKey, usize)> {
+    let last = frames.len() - 1;
+    frames
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, f)| (f.func(), if next && i == last { f.pc } else { f.pc - 1 }))
+        .collect()
+}
+
+// converts (function, pc) pairs to the frames of a call stack, the generated
+// entry function is left out, like the runtime functions of Go
+fn frames(pcs: &[(FunctionKey, usize)], code: &ByteCode, fs: Option<&FileSet>) -> Vec<Frame> {
+    pcs.iter()
+        .filter(|(fkey, _)| *fkey != code.entry)
+        .map(|(fkey, pc)| {
+            let func = &code.objects.functions[*fkey];
+            let pos = match (fs, func.pos().get(*pc)) {
+                (Some(files), Some(Some(p))) => Some(files.position(*p)),
+                _ => None,
+            };
+            Frame {
+                func: func.name.clone(),
+                pos: pos,
+            }
+        })
+        .collect()
+}
+
+// the type of a value the host passes to a Go function, None when it's nil or
+// can't be told without the stack it was made on, like a pointer to a local
+// variable of a goroutine.
+fn host_value_meta(v: &GosValue, objs: &VMObjects) -> Option<GosMetadata> {
+    match v {
+        GosValue::Nil(_)
+        | GosValue::Channel(_)
+        | GosValue::Function(_)
+        | GosValue::Package(_)
+        | GosValue::Metadata(_) => None,
+        GosValue::Pointer(p) => {
+            let pointee = match &**p {
+                PointerObj::UpVal(uv) => match &*uv.inner.borrow() {
+                    UpValueState::Closed(v) => host_value_meta(v, objs),
+                    UpValueState::Open(_) => None,
+                },
+                PointerObj::StructField(s, i) => {
+                    host_value_meta(&s.0.borrow().fields[*i as usize], objs)
+                }
+                PointerObj::SliceMember(s, i) => {
+                    host_value_meta(&s.0.borrow_data()[*i as usize].borrow(), objs)
+                }
+                PointerObj::PkgMember(_, _) | PointerObj::Released => None,
+                _ => return Some(v.get_meta(objs, &Stack::new())),
+            };
+            pointee.map(|m| m.ptr_to())
+        }
+        _ => Some(v.get_meta(objs, &Stack::new())),
+    }
+}
+
+// whether a value of type val can be passed for a parameter of type param:
+// their underlying types are identical, or param is an interface val has the
+// methods of.
+fn assignable(val: GosMetadata, param: GosMetadata, metas: &MetadataObjs) -> bool {
+    let (val, param) = (val.get_underlying(metas), param.get_underlying(metas));
+    match (val, param) {
+        (GosMetadata::NonPtr(vk, _), GosMetadata::NonPtr(pk, _)) => {
+            match (&metas[vk], &metas[pk]) {
+                (MetadataType::Interface(vf), MetadataType::Interface(pf)) => {
+                    pf.mapping.keys().all(|k| vf.mapping.contains_key(k))
+                }
+                _ => val.semantic_eq(&param, metas),
+            }
+        }
+        _ => val.semantic_eq(&param, metas),
+    }
+}
+
+// checks args against the signature of the Go function cls, which is named
+// name in the errors, returns the stack and the frame to call it with, and
+// the types of the results.
+fn prepare_call(
+    objs: &VMObjects,
+    cls: &GosValue,
+    args: Vec<GosValue>,
+    name: &str,
+) -> std::result::Result<(Stack, CallFrame, Vec<GosMetadata>), RuntimeError> {
+    let cls = match cls {
+        GosValue::Closure(c) if c.0.borrow().func.is_some() => c,
+        _ => {
+            let msg = format!("not a Go function: {}", name);
+            return Err(RuntimeError::InvalidCall(msg));
+        }
+    };
+    let cobj = cls.0.borrow();
+    let fval = &objs.functions[cobj.func.unwrap()];
+    let sig = objs.metas[fval.meta.as_non_ptr()].as_signature();
+    if args.len() != sig.params_type.len() {
+        let msg = format!(
+            "{} expects {} arguments, got {}",
+            name,
+            sig.params_type.len(),
+            args.len()
+        );
+        return Err(RuntimeError::InvalidCall(msg));
+    }
+    for (i, (arg, t)) in args.iter().zip(sig.params_type.iter()).enumerate() {
+        let at = arg.get_type();
+        if at != *t && !(at == ValueType::Nil && !t.copyable()) {
+            let msg = format!("argument {} of {} should be {:?}, got {:?}", i, name, t, at);
+            return Err(RuntimeError::InvalidCall(msg));
+        }
+        if let Some(m) = host_value_meta(arg, objs) {
+            if !assignable(m, sig.params[i], &objs.metas) {
+                let msg = format!(
+                    "argument {} of {} doesn't have the type of the parameter",
+                    i, name
+                );
+                return Err(RuntimeError::InvalidCall(msg));
+            }
+        }
+    }
+
+    let mut stack = Stack::new();
+    stack.append(&mut fval.ret_zeros.clone());
+    if let Some(r) = &cobj.recv {
+        stack.push(r.clone());
+    }
+    for arg in args.into_iter() {
+        stack.push(arg);
+    }
+    let frame = CallFrame::with_closure(cls.clone(), 0);
+    Ok((stack, frame, sig.results.clone()))
+}
+
+// returns the value held by the interface iface, and its dynamic type. The
+// dynamic type of an FFI object is the interface type it's created as, and the
+// value is an interface of that type.
+fn dynamic_value(
+    iface: Rc<RefCell<InterfaceObj>>,
+    objs: &VMObjects,
+    stack: &Stack,
+    gcv: &GcoVec,
+) -> (GosValue, GosMetadata) {
+    let val = match iface.borrow().underlying() {
+        IfaceUnderlying::Gos(v, _) => v.copy_semantic(gcv),
+        IfaceUnderlying::Ffi(ffi) => {
+            let meta = ffi.meta;
+            let val = vm_util::cast_iface(&GosValue::Interface(iface.clone()), meta, objs, stack);
+            return (val, meta);
+        }
+        IfaceUnderlying::None => GosValue::new_nil(),
+    };
+    let meta = val.get_meta(objs, stack);
+    (val, meta)
+}
+
+// reads the return values of a finished call from the bottom of stack
+fn get_results(stack: &Stack, results: &[GosMetadata], objs: &VMObjects) -> Vec<GosValue> {
+    results
+        .iter()
+        .enumerate()
+        .map(|(i, m)| stack.get_with_type(i, m.get_value_type(&objs.metas)))
+        .collect()
+}
+
 #[derive(Clone)]
 struct Context<'a> {
-    exec: Rc<LocalExecutor<'a>>,
+    // the executor is owned by run_frame_async, dropping it drops the fibers
+    // left when the program is done
+    exec: Weak<LocalExecutor<'a>>,
     code: &'a ByteCode,
+    packages: &'a PackageInstances,
     gcv: &'a GcoVec,
     ffi_factory: &'a FfiFactory,
     fs: Option<&'a FileSet>,
+    limits: Limits,
+    interrupt: &'a InterruptHandle,
+    deadline: Option<Instant>,
+    // where fmt.Println and friends write
+    stdout: Rc<RefCell<dyn Write>>,
+    // where the print builtins and the reports of unrecovered panics go
+    stderr: Rc<RefCell<dyn Write>>,
+    // the number of instructions executed by all the fibers
+    inst_count: Rc<Cell<u64>>,
+    // the first error of any fiber, like an unrecovered panic, it terminates
+    // the whole program
+    error: Rc<RefCell<Option<RuntimeError>>>,
+    // the number of fibers waiting for async FFI calls, the program is not
+    // done while any of them is waiting, even if no fiber can run
+    waiting: Rc<Cell<usize>>,
+    // the number of fibers alive, and the id of the last one created
+    fibers: Rc<Cell<usize>>,
+    last_id: Rc<Cell<usize>>,
+    // the call stacks of the fibers alive, by id, with what they were doing
+    // when they last stopped running, they are dumped on fatal errors
+    stacks: Rc<RefCell<BTreeMap<usize, (&'static str, Vec<(FunctionKey, usize)>)>>>,
+    // the timers of the sleeping fibers, and how many fibers are sleeping,
+    // they are not blocked as the timers get them going again
+    timers: Rc<RefCell<Timers>>,
+    sleeping: Rc<Cell<usize>>,
+    // the depth of the atomic sections of the running fiber, it's not
+    // preempted while it's in any of them
+    atomic: Rc<Cell<usize>>,
+    // the program is run by an executor of the host, which may use the
+    // channels shared with it meanwhile, see wait_chan
+    host_async: bool,
 }
 
 impl<'a> Context<'a> {
     fn new(
-        exec: Rc<LocalExecutor<'a>>,
+        exec: Weak<LocalExecutor<'a>>,
         code: &'a ByteCode,
+        packages: &'a PackageInstances,
         gcv: &'a GcoVec,
         ffi_factory: &'a FfiFactory,
         fs: Option<&'a FileSet>,
+        limits: Limits,
+        interrupt: &'a InterruptHandle,
+        stdout: Rc<RefCell<dyn Write>>,
+        stderr: Rc<RefCell<dyn Write>>,
+        host_async: bool,
     ) -> Context<'a> {
         Context {
             exec: exec,
             code: code,
+            packages: packages,
             gcv: gcv,
             ffi_factory: ffi_factory,
             fs: fs,
+            limits: limits,
+            interrupt: interrupt,
+            deadline: limits.timeout.map(|t| Instant::now() + t),
+            stdout: stdout,
+            stderr: stderr,
+            inst_count: Rc::new(Cell::new(0)),
+            error: Rc::new(RefCell::new(None)),
+            waiting: Rc::new(Cell::new(0)),
+            fibers: Rc::new(Cell::new(0)),
+            last_id: Rc::new(Cell::new(0)),
+            stacks: Rc::new(RefCell::new(BTreeMap::new())),
+            timers: Rc::new(RefCell::new(Timers::new())),
+            sleeping: Rc::new(Cell::new(0)),
+            atomic: Rc::new(Cell::new(0)),
+            host_async: host_async,
+        }
+    }
+
+    // records where the fiber id is when it stops running
+    fn park(&self, id: usize, status: &'static str, pcs: Vec<(FunctionKey, usize)>) {
+        self.stacks.borrow_mut().insert(id, (status, pcs));
+    }
+
+    // the fibers alive, except the one with the id running, by id
+    fn goroutines(&self, running: usize) -> Vec<Goroutine> {
+        self.stacks
+            .borrow()
+            .iter()
+            .filter(|(id, _)| **id != running)
+            .map(|(id, (status, pcs))| Goroutine {
+                id: *id,
+                status: status,
+                call_stack: frames(pcs, self.code, self.fs),
+            })
+            .collect()
+    }
+
+    // waits for the future of an async FFI call of the fiber id, which is at
+    // frames, other fibers run meanwhile. The fiber is sleeping rather than
+    // waiting if the call waits for a timer.
+    async fn wait_ffi(
+        &self,
+        id: usize,
+        frames: &[CallFrame],
+        mut fut: FfiFuture<'_>,
+    ) -> FfiResult<Vec<GosValue>> {
+        self.timers.borrow_mut().take_started();
+        if let Some(result) = future::poll_once(&mut fut).await {
+            return result;
+        }
+        let count = match self.timers.borrow_mut().take_started() {
+            true => {
+                self.park(id, "sleep", call_pcs(frames, false));
+                &self.sleeping
+            }
+            false => {
+                self.park(id, "async call", call_pcs(frames, false));
+                &self.waiting
+            }
+        };
+        count.set(count.get() + 1);
+        let result = fut.await;
+        count.set(count.get() - 1);
+        result
+    }
+
+    // wakes the fibers whose timers are due, and runs the functions of the
+    // timers of the time package that are due on new fibers
+    fn fire_timers(&self) {
+        let due = {
+            let mut timers = self.timers.borrow_mut();
+            if timers.is_empty() {
+                return;
+            }
+            timers.wake_due(Instant::now())
+        };
+        for f in due.iter() {
+            match prepare_call(&self.code.objects, f, vec![], "the timer function") {
+                Ok((stack, frame, _)) => {
+                    self.spawn_fiber(stack, frame);
+                }
+                Err(e) => self.set_error(e),
+            }
+        }
+    }
+
+    // tells if a timer is to get a sleeping fiber going, or to start one, a
+    // stopped timer is not
+    fn timer_pending(&self) -> bool {
+        self.sleeping.get() > 0 || self.timers.borrow().has_events()
+    }
+
+    // waits until a timer is due
+    async fn wait_timer(&self) {
+        let next = self.timers.borrow().next_deadline();
+        if let Some(d) = next {
+            Alarm::new(d).await;
+        }
+        self.fire_timers();
+    }
+
+    // waits until the program is interrupted, or it has run for too long
+    async fn wait_interrupt(&self) {
+        match self.deadline {
+            Some(d) => self.interrupt.wait().or(Alarm::new(d)).await,
+            None => self.interrupt.wait().await,
+        }
+    }
+
+    // waits until any task of exec can run, a timer is due, or the program
+    // is interrupted, which the caller checks
+    async fn wait_ready(&self, exec: &LocalExecutor<'a>) {
+        let ready = exec.tick().or(self.wait_interrupt());
+        if self.timers.borrow().is_empty() {
+            ready.await;
+        } else {
+            ready.or(self.wait_timer()).await;
+        }
+    }
+
+    // stops the program if it's interrupted, or it has run for too long,
+    // with all the fibers
+    fn check_interrupt(&self) -> bool {
+        if !self.interrupted() {
+            return false;
         }
+        self.set_error(RuntimeError::Interrupted(StopInfo {
+            goroutines: self.goroutines(0),
+        }));
+        true
     }
 
-    fn new_entry_frame(&self, entry: FunctionKey) -> CallFrame {
-        let cls = GosValue::new_closure(entry, &self.code.objects.functions);
-        CallFrame::with_closure(cls.as_closure().clone(), 0)
+    // awaits fut, a channel operation of the fiber id, which is blocked at
+    // frames if fut is not ready right away. A fiber blocked on a channel
+    // shared with the host is counted as waiting when the host runs the
+    // program on its executor, as the host may get it going again any time.
+    // Otherwise the host can't do anything before the program is done, so
+    // the channel is like any other.
+    async fn wait_chan<T>(
+        &self,
+        id: usize,
+        reason: &'static str,
+        frames: &[CallFrame],
+        host: bool,
+        fut: impl Future<Output = T>,
+    ) -> T {
+        futures_lite::pin!(fut);
+        if let Some(v) = future::poll_once(&mut fut).await {
+            return v;
+        }
+        self.park(id, reason, call_pcs(frames, false));
+        if !(host && self.host_async) {
+            return fut.await;
+        }
+        self.waiting.set(self.waiting.get() + 1);
+        let v = fut.await;
+        self.waiting.set(self.waiting.get() - 1);
+        v
+    }
+
+    // stops the program with all the fibers, which are blocked
+    fn deadlock(&self) {
+        // the ids start from 1, none is left out
+        let err = RuntimeError::Deadlock(self.goroutines(0));
+        let _ = write!(self.stderr.borrow_mut(), "{}", err);
+        self.set_error(err);
+    }
+
+    fn exec(&self) -> Rc<LocalExecutor<'a>> {
+        self.exec.upgrade().unwrap()
+    }
+
+    // runs the fibers until the entry fiber is done, like a Go program ends
+    // when main returns, or any of them stops the program, or they are
+    // deadlocked, that is, none of them can run and none is waiting for the
+    // host or a timer. It yields to the executor running it now and then, so
+    // that the other tasks of the executor, like the ones async FFI calls
+    // wait for, can run.
+    async fn run_fibers(&self, entry: &Task<()>) {
+        let exec = self.exec();
+        let mut ticks: u32 = 0;
+        loop {
+            if self.error.borrow().is_some() || entry.is_finished() {
+                break;
+            }
+            if self.check_interrupt() {
+                break;
+            }
+            self.fire_timers();
+            if exec.try_tick() {
+                ticks = ticks.wrapping_add(1);
+                if ticks % 64 == 0 {
+                    future::yield_now().await;
+                }
+            } else {
+                // nothing can run, but the entry fiber is not done
+                if self.waiting.get() == 0 && !self.timer_pending() {
+                    self.deadlock();
+                    break;
+                }
+                self.wait_ready(&exec).await;
+            }
+        }
+    }
+
+    // keeps the first error, it's the one that stops the program
+    fn set_error(&self, err: RuntimeError) {
+        let mut slot = self.error.borrow_mut();
+        if slot.is_none() {
+            *slot = Some(err);
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.error.borrow().is_some()
+    }
+
+    fn interrupted(&self) -> bool {
+        self.interrupt.is_interrupted() || self.deadline.map_or(false, |d| Instant::now() >= d)
     }
 
-    fn spawn_fiber(&self, stack: Stack, first_frame: CallFrame) {
+    // returns how many instructions a fiber that has executed fiber_count
+    // instructions can execute before reaching the limits, None if unlimited
+    fn budget_left(&self, fiber_count: u64) -> Option<u64> {
+        let total = self
+            .limits
+            .max_instructions
+            .map(|m| m.saturating_sub(self.inst_count.get()));
+        let fiber = self
+            .limits
+            .max_fiber_instructions
+            .map(|m| m.saturating_sub(fiber_count));
+        match (total, fiber) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // runs cls on a new fiber until it returns, while the fiber calling it is
+    // blocked in an FFI call, the other fibers run as usual meanwhile. An
+    // unrecovered panic of cls is returned instead of stopping the program,
+    // see Runtime::call_closure for when cls can't wait.
+    fn run_closure(
+        &self,
+        cls: &GosValue,
+        args: Vec<GosValue>,
+    ) -> std::result::Result<Vec<GosValue>, RuntimeError> {
+        let objs: &VMObjects = &self.code.objects;
+        let (stack, frame, results) = prepare_call(objs, cls, args, "the callback")?;
+        let mut f = Fiber::new(self.clone(), stack, frame);
+        let panic = Rc::new(RefCell::new(None));
+        f.callback_panic = Some(panic.clone());
+        let stack = f.stack.clone();
+        let exec = self.exec();
+        let task = exec.spawn(async move { f.run().await });
+        while !task.is_finished() {
+            // stopped by the limits, an interrupt, or another fiber
+            self.check_interrupt();
+            if let Some(e) = self.error.borrow().as_ref() {
+                return Err(e.clone());
+            }
+            self.fire_timers();
+            if !exec.try_tick() {
+                if self.waiting.get() == 0 && !self.timer_pending() {
+                    let msg = "deadlock: the callback is blocked by the fiber calling it";
+                    return Err(RuntimeError::InvalidCall(msg.to_string()));
+                }
+                // the fiber calling back is blocked in a sync FFI call, so
+                // the thread is blocked until an async FFI call or a timer is
+                // ready, which can't be done to the executor of the host
+                if self.host_async {
+                    let msg = "the callback waits for a timer or the host under run_async";
+                    return Err(RuntimeError::InvalidCall(msg.to_string()));
+                }
+                future::block_on(self.wait_ready(&exec));
+            }
+        }
+        if let Some(e) = self.error.borrow().as_ref() {
+            return Err(e.clone());
+        }
+        if let Some(p) = panic.borrow_mut().take() {
+            return Err(RuntimeError::Panic(p));
+        }
+        let results = get_results(&stack.borrow(), &results, objs);
+        Ok(results)
+    }
+
+    fn spawn_fiber(&self, stack: Stack, first_frame: CallFrame) -> Rc<RefCell<Stack>> {
         let mut f = Fiber::new(self.clone(), stack, first_frame);
-        self.exec
+        let stack = f.stack.clone();
+        self.exec()
             .spawn(async move {
                 // let parent fiber go first
                 future::yield_now().await;
-                f.main_loop().await;
+                f.run().await;
             })
             .detach();
+        stack
+    }
+}
+
+impl<'a> Runtime for Context<'a> {
+    fn call_closure(&self, cls: &GosValue, args: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+        self.run_closure(cls, args).map_err(|e| match e {
+            RuntimeError::Panic(p) => p.msg,
+            e => e.to_string(),
+        })
+    }
+
+    fn exit(&self, code: i32) {
+        self.set_error(RuntimeError::Exit(code));
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()>>> {
+        Box::pin(Sleep::new(self.timers.clone(), deadline))
+    }
+
+    fn start_timer(&self, deadline: Instant, period: Option<Duration>, f: GosValue) -> u64 {
+        self.timers.borrow_mut().add_event(deadline, period, f)
+    }
+
+    fn stop_timer(&self, id: u64) -> bool {
+        self.timers.borrow_mut().remove_event(id)
+    }
+
+    fn stdout(&self) -> Rc<RefCell<dyn Write>> {
+        self.stdout.clone()
+    }
+
+    fn load(&self, ptr: &GosValue) -> FfiResult<GosValue> {
+        load_pointer(ptr, self.packages)
+    }
+
+    fn store(&self, ptr: &GosValue, val: GosValue) -> FfiResult<()> {
+        store_pointer(ptr, val, self.packages)
+    }
+
+    fn set_atomic(&self, on: bool) {
+        let depth = self.atomic.get();
+        self.atomic.set(if on {
+            depth + 1
+        } else {
+            depth.saturating_sub(1)
+        });
     }
 }
 
+// lets the values converted by set_global hold Go closures called back later
+impl<'a> Runtime for GosVM<'a> {
+    fn call_closure(&self, cls: &GosValue, args: Vec<GosValue>) -> FfiResult<Vec<GosValue>> {
+        GosVM::call_closure(self, cls, args).map_err(|e| match e {
+            RuntimeError::Panic(p) => p.msg,
+            e => e.to_string(),
+        })
+    }
+
+    // no program is running, so there is nothing to end
+    fn exit(&self, _code: i32) {}
+
+    // nor any other goroutine to run meanwhile
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()>>> {
+        let now = Instant::now();
+        if deadline > now {
+            std::thread::sleep(deadline - now);
+        }
+        Box::pin(future::ready(()))
+    }
+
+    // no timer runs outside of a run or call, as no goroutine does
+    fn start_timer(&self, _deadline: Instant, _period: Option<Duration>, _f: GosValue) -> u64 {
+        0
+    }
+
+    fn stop_timer(&self, _id: u64) -> bool {
+        false
+    }
+
+    fn stdout(&self) -> Rc<RefCell<dyn Write>> {
+        self.stdout.clone()
+    }
+
+    fn load(&self, ptr: &GosValue) -> FfiResult<GosValue> {
+        load_pointer(ptr, &self.packages)
+    }
+
+    fn store(&self, ptr: &GosValue, val: GosValue) -> FfiResult<()> {
+        store_pointer(ptr, val, &self.packages)
+    }
+
+    // no other fiber runs anyway
+    fn set_atomic(&self, _on: bool) {}
+}
+
+// the pointers the FFI code can load and store through, the ones to
+// variables, the others point to values of composite types
+fn load_pointer(ptr: &GosValue, pkgs: &PackageInstances) -> FfiResult<GosValue> {
+    let p: &PointerObj = match ptr {
+        GosValue::Pointer(p) => p,
+        _ => return Err("not a pointer".to_string()),
+    };
+    Ok(match p {
+        PointerObj::UpVal(uv) => match &*uv.inner.borrow() {
+            UpValueState::Open(desc) => {
+                let index = (desc.stack_base + desc.index) as usize;
+                let stack = desc.stack.upgrade().ok_or("dangling pointer")?;
+                let val = stack.borrow().get_with_type(index, desc.typ);
+                val
+            }
+            UpValueState::Closed(val) => val.clone(),
+        },
+        PointerObj::SliceMember(s, index) => {
+            s.0.get(*index as usize).ok_or("index out of range")?
+        }
+        PointerObj::StructField(s, index) => s.0.borrow().fields[*index as usize].clone(),
+        PointerObj::PkgMember(pkg, index) => pkgs[*pkg].member(*index).clone(),
+        _ => return Err("not a pointer to a variable".to_string()),
+    })
+}
+
+fn store_pointer(ptr: &GosValue, val: GosValue, pkgs: &PackageInstances) -> FfiResult<()> {
+    let p: &PointerObj = match ptr {
+        GosValue::Pointer(p) => p,
+        _ => return Err("not a pointer".to_string()),
+    };
+    match p {
+        PointerObj::UpVal(uv) => match &mut *uv.inner.borrow_mut() {
+            UpValueState::Open(desc) => {
+                let index = (desc.stack_base + desc.index) as usize;
+                let stack = desc.stack.upgrade().ok_or("dangling pointer")?;
+                stack.borrow_mut().set(index, val);
+            }
+            UpValueState::Closed(v) => *v = val,
+        },
+        PointerObj::SliceMember(s, index) => {
+            let data = s.0.borrow_data();
+            let target = data
+                .get(s.0.begin() + *index as usize)
+                .ok_or("index out of range")?;
+            *target.borrow_mut() = val;
+        }
+        PointerObj::StructField(s, index) => s.0.borrow_mut().fields[*index as usize] = val,
+        PointerObj::PkgMember(pkg, index) => *pkgs[*pkg].member_mut(*index) = val,
+        _ => return Err("not a pointer to a variable".to_string()),
+    }
+    Ok(())
+}
+
 pub struct Fiber<'a> {
+    id: usize,
     stack: Rc<RefCell<Stack>>,
     rstack: RangeStack,
     frames: Vec<CallFrame>,
     next_frames: Vec<CallFrame>,
     context: Context<'a>,
+    // the number of instructions executed by this fiber
+    inst_count: u64,
+    // where an unrecovered panic goes if the fiber runs a callback, instead
+    // of stopping the program
+    callback_panic: Option<Rc<RefCell<Option<PanicInfo>>>>,
+}
+
+impl<'a> Drop for Fiber<'a> {
+    fn drop(&mut self) {
+        self.context.fibers.set(self.context.fibers.get() - 1);
+        self.context.stacks.borrow_mut().remove(&self.id);
+    }
 }
 
 impl<'a> Fiber<'a> {
-    fn new(c: Context<'a>, stack: Stack, first_frame: CallFrame) -> Fiber<'a> {
+    fn new(c: Context<'a>, stack: Stack, mut first_frame: CallFrame) -> Fiber<'a> {
+        let stack = Rc::new(RefCell::new(stack));
+        if first_frame.var_ptrs.is_none() {
+            let cls = first_frame.closure().clone();
+            let cls = cls.0.borrow();
+            if let Some(key) = cls.func {
+                first_frame.set_var_ptrs(&cls, &c.code.objects.functions[key], &stack);
+            }
+        }
+        let id = c.last_id.get() + 1;
+        c.last_id.set(id);
+        c.fibers.set(c.fibers.get() + 1);
+        c.park(id, "runnable", vec![(first_frame.func(), first_frame.pc)]);
         Fiber {
-            stack: Rc::new(RefCell::new(stack)),
+            id: id,
+            stack: stack,
             rstack: RangeStack::new(),
             frames: vec![first_frame],
             next_frames: Vec::new(),
             context: c,
+            inst_count: 0,
+            callback_panic: None,
+        }
+    }
+
+    // tells if the channel is shared with the host, see Context::wait_chan
+    fn host_chan(chan: &GosValue) -> bool {
+        match chan {
+            GosValue::Channel(c) => c.host.get(),
+            _ => false,
+        }
+    }
+
+    // runs main_loop, a Rust panic in it, of the VM or the FFI code, stops the
+    // program with RuntimeError::Internal instead of going unnoticed
+    async fn run(&mut self) {
+        if let Err(e) = AssertUnwindSafe(self.main_loop()).catch_unwind().await {
+            let msg: &dyn Any = &*e;
+            let msg = match (msg.downcast_ref::<&str>(), msg.downcast_ref::<String>()) {
+                (Some(s), _) => s.to_string(),
+                (_, Some(s)) => s.clone(),
+                _ => "unknown panic".to_string(),
+            };
+            self.context.set_error(RuntimeError::Internal(msg));
+        }
+        // an atomic section ends with the fiber, so that the others can run
+        self.context.atomic.set(0);
+    }
+
+    // where the fiber is, the next instruction of the innermost frame, and the
+    // calls of the others, followed by the other fibers
+    fn stop_info(&self) -> StopInfo {
+        let ctx = &self.context;
+        let pcs = call_pcs(&self.frames, true);
+        let mut goroutines = vec![Goroutine {
+            id: self.id,
+            status: "running",
+            call_stack: frames(&pcs, ctx.code, ctx.fs),
+        }];
+        goroutines.append(&mut ctx.goroutines(self.id));
+        StopInfo {
+            goroutines: goroutines,
         }
     }
 
@@ -213,6 +1236,7 @@ impl<'a> Fiber<'a> {
         let objs: &VMObjects = &ctx.code.objects;
         let metadata: &Metadata = &objs.metadata;
         let pkgs = &ctx.code.packages;
+        let pkg_objs = ctx.packages;
         let ifaces = &ctx.code.ifaces;
         let frame = self.frames.last_mut().unwrap();
         let mut func = &objs.functions[frame.func()];
@@ -227,13 +1251,26 @@ impl<'a> Fiber<'a> {
         let mut stack_base = frame.stack_base;
         let mut frame_height = self.frames.len();
 
-        let mut total_inst = 0;
         //let mut stats: HashMap<Opcode, usize> = HashMap::new();
         loop {
+            if ctx.interrupted() {
+                let info = self.stop_info();
+                ctx.set_error(RuntimeError::Interrupted(info));
+                break;
+            }
+            let mut yield_unit = 1024;
+            if let Some(left) = ctx.budget_left(self.inst_count) {
+                if left == 0 {
+                    let info = self.stop_info();
+                    ctx.set_error(RuntimeError::BudgetExhausted(info));
+                    break;
+                }
+                yield_unit = yield_unit.min(left);
+            }
             let mut frame = self.frames.last_mut().unwrap();
             let mut result: Result = Result::Continue;
             let mut panic: Option<PanicData> = None;
-            let yield_unit = 1024;
+            let mut total_inst = 0;
             for _ in 0..yield_unit {
                 let inst = code[frame.pc];
                 let inst_op = inst.op();
@@ -346,13 +1383,13 @@ impl<'a> Fiber<'a> {
                     Opcode::LOAD_FIELD => {
                         let ind = stack.pop_with_type(inst.t1());
                         let val = stack.pop_with_type(inst.t0());
-                        stack.push(vm_util::load_field(&val, &ind, objs));
+                        stack.push(vm_util::load_field(&val, &ind, pkg_objs));
                     }
                     Opcode::LOAD_STRUCT_FIELD => {
                         let ind = inst.imm();
                         let mut target = stack.pop_with_type(inst.t0());
                         if let GosValue::Pointer(_) = &target {
-                            target = deref_value!(target, self, stack, self.frames, objs);
+                            target = deref_value!(target, self, stack, self.frames, pkg_objs);
                             frame = self.frames.last_mut().unwrap();
                         }
                         let val = match &target {
@@ -426,7 +1463,8 @@ impl<'a> Fiber<'a> {
                         let target = stack.get_with_type(s_index, inst.t1());
                         match target {
                             GosValue::Pointer(_) => {
-                                let unboxed = deref_value!(target, self, stack, self.frames, objs);
+                                let unboxed =
+                                    deref_value!(target, self, stack, self.frames, pkg_objs);
                                 frame = self.frames.last_mut().unwrap();
                                 vm_util::store_field(
                                     stack,
@@ -455,7 +1493,7 @@ impl<'a> Fiber<'a> {
                         let s_index = Stack::offset(stack.len(), index);
                         let mut target = stack.get_with_type(s_index, inst.t1());
                         if let GosValue::Pointer(_) = &target {
-                            target = deref_value!(target, self, stack, self.frames, objs);
+                            target = deref_value!(target, self, stack, self.frames, pkg_objs);
                             frame = self.frames.last_mut().unwrap();
                         }
                         match &target {
@@ -477,12 +1515,12 @@ impl<'a> Fiber<'a> {
                     Opcode::LOAD_PKG_FIELD => {
                         let index = inst.imm();
                         let pkg_key = read_imm_pkg!(code, frame, objs);
-                        let pkg = &objs.packages[pkg_key];
+                        let pkg = &pkg_objs[pkg_key];
                         stack.push(pkg.member(index).clone());
                     }
                     Opcode::STORE_PKG_FIELD => {
                         let (rhs_index, imm) = inst.imm824();
-                        let pkg = &objs.packages[read_imm_pkg!(code, frame, objs)];
+                        let pkg = &pkg_objs[read_imm_pkg!(code, frame, objs)];
                         stack.store_val(&mut pkg.member_mut(imm), rhs_index, inst.t0(), gcv);
                     }
                     Opcode::STORE_DEREF => {
@@ -540,7 +1578,7 @@ impl<'a> Fiber<'a> {
                                     }
                                     PointerObj::PkgMember(p, index) => {
                                         let target: &mut GosValue =
-                                            &mut objs.packages[*p].member_mut(*index);
+                                            &mut pkg_objs[*p].member_mut(*index);
                                         stack.store_val(target, rhs_index, inst.t0(), gcv);
                                     }
                                     PointerObj::Released => unreachable!(),
@@ -553,6 +1591,12 @@ impl<'a> Fiber<'a> {
                         let (target, mapping) = inst.imm824();
                         let rhs_s_index = Stack::offset(stack.len(), target);
                         match inst.t0() {
+                            ValueType::Interface if inst.t1() == ValueType::Interface => {
+                                let meta = ifaces[mapping as usize].0;
+                                let from = stack.get_with_type(rhs_s_index, inst.t1());
+                                let val = vm_util::cast_iface(&from, meta, objs, stack);
+                                stack.set(rhs_s_index, val);
+                            }
                             ValueType::Interface => {
                                 let iface = ifaces[mapping as usize].clone();
                                 let under = stack.get_with_type(rhs_s_index, inst.t1());
@@ -579,18 +1623,16 @@ impl<'a> Fiber<'a> {
                                         match inst.t2() {
                                             ValueType::Int32 => slice
                                                 .0
-                                                .borrow_data()
+                                                .get_vec()
                                                 .iter()
-                                                .map(|x| {
-                                                    vm_util::char_from_i32(*(x.borrow().as_int32()))
-                                                })
+                                                .map(|x| vm_util::char_from_i32(*x.as_int32()))
                                                 .collect(),
                                             ValueType::Uint8 => {
                                                 let buf: Vec<u8> = slice
                                                     .0
-                                                    .borrow_data()
+                                                    .get_vec()
                                                     .iter()
-                                                    .map(|x| *(x.borrow().as_uint8()))
+                                                    .map(|x| *x.as_uint8())
                                                     .collect();
                                                 // todo: error handling
                                                 str::from_utf8(&buf).unwrap().to_string()
@@ -675,7 +1717,19 @@ impl<'a> Fiber<'a> {
                         let val = stack.pop_with_type(inst.t0());
                         let chan = stack.pop_rc();
                         drop(stack_mut_ref);
-                        let re = chan.as_channel().send(&val).await;
+                        let (id, frames, host) = (self.id, &self.frames, Fiber::host_chan(&chan));
+                        let re = match &chan {
+                            GosValue::Nil(_) => {
+                                let reason = "chan send (nil chan)";
+                                ctx.wait_chan(id, reason, frames, host, future::pending())
+                                    .await
+                            }
+                            c => {
+                                let fut = c.as_channel().send(&val);
+                                ctx.wait_chan(id, "chan send", frames, host, fut).await
+                            }
+                        };
+                        frame = self.frames.last_mut().unwrap();
                         restore_stack_ref!(self, stack, stack_mut_ref);
                         if let Err(e) = re {
                             go_panic_str!(panic, metadata, e, frame, code);
@@ -683,10 +1737,23 @@ impl<'a> Fiber<'a> {
                     }
                     Opcode::RECV => {
                         let chan_val = stack.pop_rc();
-                        let chan = chan_val.as_channel();
                         drop(stack_mut_ref);
-                        let val = chan.recv().await;
+                        let (id, frames) = (self.id, &self.frames);
+                        let host = Fiber::host_chan(&chan_val);
+                        let val = match &chan_val {
+                            GosValue::Nil(_) => {
+                                let reason = "chan receive (nil chan)";
+                                ctx.wait_chan(id, reason, frames, host, future::pending())
+                                    .await
+                            }
+                            c => {
+                                let fut = c.as_channel().recv();
+                                ctx.wait_chan(id, "chan receive", frames, host, fut).await
+                            }
+                        };
+                        frame = self.frames.last_mut().unwrap();
                         restore_stack_ref!(self, stack, stack_mut_ref);
+                        let chan = chan_val.as_channel();
                         let (unwrapped, ok) = unwrap_recv_val!(chan, val, objs.metas, gcv);
                         stack.push(unwrapped);
                         if inst.t1() == ValueType::FlagA {
@@ -723,7 +1790,12 @@ impl<'a> Fiber<'a> {
                         )));
                     }
                     Opcode::REF_STRUCT_FIELD => {
-                        let struct_ = stack.pop_with_type(inst.t0());
+                        let mut struct_ = stack.pop_with_type(inst.t0());
+                        // &p.f where p is a pointer to the struct
+                        if let GosValue::Pointer(_) = &struct_ {
+                            struct_ = deref_value!(struct_, self, stack, self.frames, pkg_objs);
+                            frame = self.frames.last_mut().unwrap();
+                        }
                         let struct_ = match &struct_ {
                             GosValue::Named(n) => n.0.clone(),
                             GosValue::Struct(_) => struct_,
@@ -749,7 +1821,7 @@ impl<'a> Fiber<'a> {
                     }
                     Opcode::DEREF => {
                         let boxed = stack.pop_with_type(inst.t0());
-                        let val = deref_value!(boxed, self, stack, self.frames, objs);
+                        let val = deref_value!(boxed, self, stack, self.frames, pkg_objs);
                         stack.push(val);
                         frame = self.frames.last_mut().unwrap();
                     }
@@ -782,34 +1854,30 @@ impl<'a> Fiber<'a> {
                             let sig = &objs.metas[cls.meta.as_non_ptr()].as_signature();
                             let (meta, v_meta) = sig.variadic.unwrap();
                             let vt = v_meta.get_value_type(&objs.metas);
-                            let index =
-                                nframe.stack_base + sig.params.len() + sig.results.len() - 1;
-                            stack.pack_variadic(index, meta, vt, gcv);
+                            // there are no slots for the results of ffi calls
+                            let ret_count = if cls.func.is_some() {
+                                sig.results.len()
+                            } else {
+                                0
+                            };
+                            let index = nframe.stack_base + sig.params.len() + ret_count - 1;
+                            if index < stack.len() {
+                                stack.pack_variadic(index, meta, vt, gcv);
+                            } else {
+                                // no arguments for the variadic parameter
+                                stack.push(GosValue::slice_with_val(vec![], meta, gcv));
+                            }
                         }
                         match cls.func {
                             Some(key) => {
                                 let nfunc = &objs.functions[key];
-                                if let Some(uvs) = &cls.uvs {
-                                    let mut ptrs: Vec<UpValue> =
-                                        Vec::with_capacity(nfunc.up_ptrs.len());
-                                    for (i, p) in nfunc.up_ptrs.iter().enumerate() {
-                                        ptrs.push(if p.is_up_value {
-                                            uvs[&i].clone()
-                                        } else {
-                                            // local pointers
-                                            let uv = UpValue::new(p.clone_with_stack(
-                                                Rc::downgrade(&self.stack),
-                                                nframe.stack_base as OpIndex,
-                                            ));
-                                            nframe.add_referred_by(p.index, p.typ, &uv);
-                                            uv
-                                        });
-                                    }
-                                    nframe.var_ptrs = Some(ptrs);
+                                if call_style != ValueType::FlagB {
+                                    nframe.set_var_ptrs(cls, nfunc, &self.stack);
                                 }
                                 match call_style {
                                     ValueType::Zero => {
                                         // default call
+                                        nframe.range_base = self.rstack.len();
                                         self.frames.push(nframe);
                                         frame_height += 1;
                                         frame = self.frames.last_mut().unwrap();
@@ -831,7 +1899,10 @@ impl<'a> Fiber<'a> {
                                         self.context.spawn_fiber(nstack, nframe);
                                     }
                                     ValueType::FlagB => {
-                                        let (c, rc) = stack.pop_n(nfunc.param_count());
+                                        // the result slots are saved along with the arguments,
+                                        // the stack may have grown by the time the call runs
+                                        let (c, rc) =
+                                            stack.pop_n(nfunc.ret_count() + nfunc.param_count());
                                         let deferred = DeferredCall {
                                             frame: nframe,
                                             stack_c: c,
@@ -844,15 +1915,38 @@ impl<'a> Fiber<'a> {
                             }
                             None => {
                                 let call = cls.ffi.as_ref().unwrap();
-                                let ptypes = &objs.metas[call.meta.as_non_ptr()]
-                                    .as_signature()
-                                    .params_type;
-                                let params = stack.pop_with_type_n(ptypes);
+                                let sig = objs.metas[call.meta.as_non_ptr()].as_signature();
+                                let params = stack.pop_with_type_n(&sig.params_type);
                                 // release stack so that code in ffi can yield
                                 drop(stack_mut_ref);
-                                let mut returns = call.ffi.borrow().call(&call.func_name, params);
+                                let ffi_ctx = FfiCtx {
+                                    func_name: &call.func_name,
+                                    metas: &objs.metas,
+                                    gcv: gcv,
+                                    results: &sig.results,
+                                    runtime: ctx,
+                                };
+                                let ffi = call.ffi.borrow();
+                                let returns = if ffi.is_async(&call.func_name) {
+                                    let fut = ffi.call_async(&ffi_ctx, params);
+                                    ctx.wait_ffi(self.id, &self.frames, fut).await
+                                } else {
+                                    ffi.call(&ffi_ctx, params)
+                                };
+                                drop(ffi);
                                 restore_stack_ref!(self, stack, stack_mut_ref);
-                                stack.append(&mut returns);
+                                frame = self.frames.last_mut().unwrap();
+                                // like os.Exit, the call may have ended the program
+                                if ctx.stopped() {
+                                    result = Result::End;
+                                    break;
+                                }
+                                match returns {
+                                    Ok(mut v) => stack.append(&mut v),
+                                    Err(e) => {
+                                        go_panic_str!(panic, metadata, e, frame, code);
+                                    }
+                                }
                             }
                         }
                     }
@@ -870,12 +1964,13 @@ impl<'a> Fiber<'a> {
                             ValueType::FlagA => {
                                 let index = inst.imm() as usize;
                                 let pkey = pkgs[index];
-                                let pkg = &objs.packages[pkey];
+                                let pkg = &pkg_objs[pkey];
                                 let count = pkg.var_count();
                                 // remove garbage first
                                 debug_assert!(stack.len() == stack_base + count);
                                 // the var values left on the stack are for pkg members
                                 stack.init_pkg_vars(pkg, count);
+                                pkg.set_inited();
                             }
                             // func with deferred calls
                             ValueType::FlagB => {
@@ -884,14 +1979,18 @@ impl<'a> Fiber<'a> {
                                         // run Opcode::RETURN to check if deferred_stack is empty
                                         frame.pc -= 1;
 
+                                        let mut nframe = call.frame;
+                                        nframe.stack_base = stack.len();
+                                        nframe.range_base = self.rstack.len();
                                         stack.push_n(call.stack_c, call.stack_rc);
-                                        let nframe = call.frame;
+                                        let cls_rc = nframe.closure().clone();
+                                        let fkey = cls_rc.0.borrow().func.unwrap();
+                                        func = &objs.functions[fkey];
+                                        nframe.set_var_ptrs(&cls_rc.0.borrow(), func, &self.stack);
 
                                         self.frames.push(nframe);
                                         frame_height += 1;
                                         frame = self.frames.last_mut().unwrap();
-                                        let fkey = frame.closure.0.borrow().func.unwrap();
-                                        func = &objs.functions[fkey];
                                         stack_base = frame.stack_base;
                                         consts = &func.consts;
                                         code = func.code();
@@ -908,11 +2007,12 @@ impl<'a> Fiber<'a> {
                         }
 
                         frame.on_drop(&stack);
+                        self.rstack.truncate(frame.range_base);
                         drop(frame);
                         self.frames.pop();
                         frame_height -= 1;
                         if self.frames.is_empty() {
-                            dbg!(total_inst);
+                            //dbg!(self.inst_count);
                             /* dbg!
                             let mut s = stats
                                 .iter()
@@ -957,6 +2057,14 @@ impl<'a> Fiber<'a> {
                     }
                     Opcode::SELECT => {
                         let blocks = inst.imm();
+                        if blocks == 0 {
+                            drop(stack_mut_ref);
+                            let reason = "select (no cases)";
+                            let fut = future::pending::<()>();
+                            ctx.wait_chan(self.id, reason, &self.frames, false, fut)
+                                .await;
+                            unreachable!();
+                        }
                         let begin = frame.pc - 1;
                         let mut end = begin + blocks as usize;
                         let end_code = &code[end - 1];
@@ -991,7 +2099,14 @@ impl<'a> Fiber<'a> {
                         let selector = channel::Selector::new(comms, default_offset);
 
                         drop(stack_mut_ref);
-                        let re = selector.select().await;
+                        let host = selector.comms.iter().any(|c| match c {
+                            channel::SelectComm::Send(c, _, _) => Fiber::host_chan(c),
+                            channel::SelectComm::Recv(c, _, _) => Fiber::host_chan(c),
+                        });
+                        let re = ctx
+                            .wait_chan(self.id, "select", &self.frames, host, selector.select())
+                            .await;
+                        frame = self.frames.last_mut().unwrap();
                         restore_stack_ref!(self, stack, stack_mut_ref);
 
                         match re {
@@ -1039,17 +2154,36 @@ impl<'a> Fiber<'a> {
                     // Opcode::RANGE assumes a container and an int(as the cursor) on the stack
                     Opcode::RANGE => {
                         let offset = inst.imm();
-                        if self.rstack.range_body(inst.t0(), stack) {
+                        if inst.t0() == ValueType::Channel {
+                            let chan = self.rstack.range_chan();
+                            drop(stack_mut_ref);
+                            let host = chan.host.get();
+                            let fut = chan.recv();
+                            let val = ctx
+                                .wait_chan(self.id, "chan receive", &self.frames, host, fut)
+                                .await;
+                            frame = self.frames.last_mut().unwrap();
+                            restore_stack_ref!(self, stack, stack_mut_ref);
+                            match val {
+                                // pushed twice as there is no second value
+                                Some(v) => {
+                                    stack.push(v.clone());
+                                    stack.push(v);
+                                }
+                                None => frame.pc = Stack::offset(frame.pc, offset),
+                            }
+                        } else if self.rstack.range_body(stack) {
                             frame.pc = Stack::offset(frame.pc, offset);
                         }
                     }
+                    // the loop is left, imm is the number of range loops it is in
+                    Opcode::RANGE_END => {
+                        self.rstack.truncate(frame.range_base + inst.imm() as usize);
+                    }
 
                     Opcode::TYPE_ASSERT => {
-                        let val = match stack.pop_interface().borrow().underlying() {
-                            IfaceUnderlying::Gos(v, _) => v.copy_semantic(gcv),
-                            _ => GosValue::new_nil(),
-                        };
-                        let meta = GosValue::Metadata(val.get_meta(objs, stack));
+                        let (val, meta) = dynamic_value(stack.pop_interface(), objs, stack, gcv);
+                        let meta = GosValue::Metadata(meta);
                         stack.push(val);
                         let ok = &consts[inst.imm() as usize] == &meta;
                         let do_try = inst.t2_as_index() > 0;
@@ -1063,11 +2197,8 @@ impl<'a> Fiber<'a> {
                         }
                     }
                     Opcode::TYPE => {
-                        let val = match stack.pop_interface().borrow().underlying() {
-                            IfaceUnderlying::Gos(v, _) => v.copy_semantic(gcv),
-                            _ => GosValue::new_nil(),
-                        };
-                        stack.push(GosValue::Metadata(val.get_meta(objs, stack)));
+                        let (val, meta) = dynamic_value(stack.pop_interface(), objs, stack, gcv);
+                        stack.push(GosValue::Metadata(meta));
                         if inst.t2_as_index() > 0 {
                             let index = inst.imm();
                             let s_index = Stack::offset(stack_base, index);
@@ -1076,7 +2207,7 @@ impl<'a> Fiber<'a> {
                     }
                     Opcode::IMPORT => {
                         let pkey = pkgs[inst.imm() as usize];
-                        stack.push(GosValue::Bool(!objs.packages[pkey].inited()));
+                        stack.push(GosValue::Bool(!pkg_objs[pkey].inited()));
                     }
                     Opcode::SLICE | Opcode::SLICE_FULL => {
                         let max = if inst_op == Opcode::SLICE_FULL {
@@ -1110,20 +2241,42 @@ impl<'a> Fiber<'a> {
                                 if let Some(uvs) = &mut val.uvs {
                                     drop(frame);
                                     for (_, uv) in uvs.iter_mut() {
-                                        let r: &mut UpValueState = &mut uv.inner.borrow_mut();
-                                        if let UpValueState::Open(d) = r {
+                                        let mut shared = None;
+                                        if let UpValueState::Open(d) = &mut *uv.inner.borrow_mut() {
                                             // get frame index, and add_referred_by
-                                            for i in 1..frame_height {
+                                            let mut found = false;
+                                            for i in 1..=frame_height {
                                                 let index = frame_height - i;
                                                 if self.frames[index].func() == d.func {
                                                     let upframe = &mut self.frames[index];
                                                     d.stack = Rc::downgrade(&self.stack);
                                                     d.stack_base = upframe.stack_base as OpIndex;
                                                     upframe.add_referred_by(d.index, d.typ, uv);
-                                                    // if not found, the upvalue is already closed, nothing to be done
+                                                    found = true;
                                                     break;
                                                 }
                                             }
+                                            // the var is not on this fiber's stack, either it's
+                                            // closed or it belongs to another goroutine, the
+                                            // enclosing closure has captured it already
+                                            if !found {
+                                                let cur = &self.frames[frame_height - 1];
+                                                let cur_func = &objs.functions[cur.func()];
+                                                shared = cur_func
+                                                    .up_ptrs
+                                                    .iter()
+                                                    .position(|p| {
+                                                        p.is_up_value
+                                                            && p.func == d.func
+                                                            && p.index == d.index
+                                                    })
+                                                    .map(|i| {
+                                                        cur.var_ptrs.as_ref().unwrap()[i].clone()
+                                                    });
+                                            }
+                                        }
+                                        if let Some(s) = shared {
+                                            *uv = s;
                                         }
                                         //dbg!(&desc, &upframe);
                                     }
@@ -1279,15 +2432,35 @@ impl<'a> Fiber<'a> {
                         stack.push(GosValue::Int(l as isize));
                     }
                     Opcode::APPEND => {
-                        let index = Stack::offset(stack.len(), inst.imm());
-                        let a = stack.get_with_type(index - 2, ValueType::Slice);
+                        // the slice appended to is followed by the values,
+                        // or by a slice of them if they are passed with ...
+                        let (a_index, index) = match inst.t1() {
+                            ValueType::Zero => (stack.len() - 2, stack.len()),
+                            _ => {
+                                let index = Stack::offset(stack.len(), inst.imm());
+                                (index - 1, index)
+                            }
+                        };
+                        let a = stack.get_with_type(a_index, ValueType::Slice);
                         let vala = a.as_slice();
-                        stack.pack_variadic(index, vala.0.meta, inst.t1(), gcv);
+                        if index < stack.len() {
+                            stack.pack_variadic(index, vala.0.meta, inst.t1(), gcv);
+                        } else if inst.t1() != ValueType::Zero {
+                            stack.push(GosValue::slice_with_val(vec![], vala.0.meta, gcv));
+                        }
                         let b = stack.pop_with_type(ValueType::Slice);
                         let valb = b.as_slice();
-                        vala.0
-                            .borrow_data_mut()
-                            .append(&mut valb.0.borrow_data().clone());
+                        if vala.0.is_nil() {
+                            // appending to a nil slice allocates a new one
+                            let new = GosValue::slice_with_val(valb.0.get_vec(), vala.0.meta, gcv);
+                            stack.set(a_index, new);
+                        } else {
+                            // the result shares the array of a while it has room
+                            let mut result = vala.0.clone();
+                            let mut vals = valb.0.get_vec().into_iter().map(RefCell::new).collect();
+                            result.append(&mut vals);
+                            stack.set(a_index, GosValue::Slice(Rc::new((result, Cell::new(0)))));
+                        }
                     }
                     Opcode::CLOSE => {
                         let chan = stack.pop_with_type(ValueType::Channel);
@@ -1319,15 +2492,19 @@ impl<'a> Fiber<'a> {
                             .as_signature()
                             .params_type[2..];
                         let params = stack.pop_with_type_n(ptypes);
+                        // the interface type and the name
+                        stack.pop_discard_n(2);
                         let v = match self.context.ffi_factory.create_by_name(name_str, params) {
                             Ok(v) => {
+                                let (k, _) = itype.as_meta().unwrap_non_ptr();
+                                let dyn_meta = GosMetadata::NonPtr(k, MetaCategory::Default);
                                 let meta = itype.as_meta().get_underlying(&objs.metas).clone();
                                 let info = objs.metas[meta.as_non_ptr()]
                                     .as_interface()
                                     .iface_ffi_info();
                                 GosValue::new_iface(
                                     meta,
-                                    IfaceUnderlying::Ffi(UnderlyingFfi::new(v, info)),
+                                    IfaceUnderlying::Ffi(UnderlyingFfi::new(v, info, dyn_meta)),
                                 )
                             }
                             Err(e) => {
@@ -1337,6 +2514,75 @@ impl<'a> Fiber<'a> {
                         };
                         stack.push(v);
                     }
+                    Opcode::NATIVE => {
+                        // the parameters are the locals after the results,
+                        // which are set before returning
+                        let name = consts[inst.imm() as usize].as_str();
+                        let sig = objs.metas[func.meta.as_non_ptr()].as_signature();
+                        let ret_count = sig.results.len();
+                        let recv_type = sig.recv.map(|r| r.get_value_type(&objs.metas));
+                        let params: Vec<GosValue> = recv_type
+                            .iter()
+                            .chain(sig.params_type.iter())
+                            .enumerate()
+                            .map(|(i, t)| stack.get_with_type(stack_base + ret_count + i, *t))
+                            .collect();
+                        // release stack so that code in ffi can yield
+                        drop(stack_mut_ref);
+                        let ffi_ctx = FfiCtx {
+                            func_name: name.as_str(),
+                            metas: &objs.metas,
+                            gcv: gcv,
+                            results: &sig.results,
+                            runtime: ctx,
+                        };
+                        let factory = ctx.ffi_factory;
+                        let returns = if factory.is_async_native(ffi_ctx.func_name) {
+                            let fut = factory.call_native_async(&ffi_ctx, params);
+                            ctx.wait_ffi(self.id, &self.frames, fut).await
+                        } else {
+                            factory.call_native(&ffi_ctx, params)
+                        };
+                        restore_stack_ref!(self, stack, stack_mut_ref);
+                        frame = self.frames.last_mut().unwrap();
+                        if ctx.stopped() {
+                            result = Result::End;
+                            break;
+                        }
+                        let returns = returns.and_then(|v| match v.len() == ret_count {
+                            true => Ok(v),
+                            false => Err(format!(
+                                "native function {} returned {} values, want {}",
+                                name.as_str(),
+                                v.len(),
+                                ret_count
+                            )),
+                        });
+                        match returns {
+                            Ok(v) => {
+                                for (i, v) in v.into_iter().enumerate() {
+                                    stack.set(stack_base + i, v);
+                                }
+                            }
+                            Err(e) => {
+                                go_panic_str!(panic, metadata, e, frame, code);
+                            }
+                        }
+                    }
+                    Opcode::PRINT | Opcode::PRINTLN => {
+                        let meta = stack.pop_with_type(ValueType::Metadata);
+                        let ptypes = &objs.metas[meta.as_meta().as_non_ptr()]
+                            .as_signature()
+                            .params_type;
+                        let params = stack.pop_with_type_n(ptypes);
+                        let strs: Vec<String> = params.iter().map(|x| x.to_string()).collect();
+                        // like in Go, the output is not buffered and the errors are ignored
+                        let mut w = ctx.stderr.borrow_mut();
+                        let _ = match inst_op {
+                            Opcode::PRINT => write!(w, "{}", strs.join("")),
+                            _ => writeln!(w, "{}", strs.join(" ")),
+                        };
+                    }
                     _ => {
                         dbg!(inst_op);
                         unimplemented!();
@@ -1344,34 +2590,27 @@ impl<'a> Fiber<'a> {
                 };
                 //dbg!(inst_op, stack.len());
             } //yield unit
+            self.inst_count += total_inst;
+            ctx.inst_count.set(ctx.inst_count.get() + total_inst);
             match result {
                 Result::End => {
                     if let Some(p) = panic {
-                        println!("panic: {}", p.msg);
-                        if let Some(files) = self.context.fs {
-                            for (fkey, pc) in p.call_stack.iter() {
-                                let func = &objs.functions[*fkey];
-                                if let Some(p) = func.pos()[*pc] {
-                                    println!("{}", files.position(p));
-                                } else {
-                                    println!("<no debug info available>");
-                                }
-                            }
-                        }
-
-                        // a hack to make the test case fail
-                        if let GosValue::Str(s) =
-                            p.msg.as_interface().borrow().underlying_value().unwrap()
-                        {
-                            if s.as_str().starts_with("Opcode::ASSERT") {
-                                panic!("ASSERT");
+                        let info = PanicInfo::new(p, self.id, ctx.code, ctx.fs);
+                        match &self.callback_panic {
+                            Some(slot) => *slot.borrow_mut() = Some(info),
+                            None => {
+                                let _ = write!(ctx.stderr.borrow_mut(), "{}", info);
+                                self.context.set_error(RuntimeError::Panic(info));
                             }
                         }
                     }
                     break;
                 }
+                // the fiber keeps running in an atomic section
+                Result::Continue if ctx.atomic.get() > 0 => {}
                 Result::Continue => {
                     drop(stack_mut_ref);
+                    ctx.park(self.id, "runnable", call_pcs(&self.frames, true));
                     future::yield_now().await;
                     restore_stack_ref!(self, stack, stack_mut_ref);
                 }
@@ -1383,36 +2622,385 @@ impl<'a> Fiber<'a> {
     }
 }
 
+/// GosVM runs a ByteCode, which is never modified, so that any number of VMs
+/// can be created from the same ByteCode. Each VM has its own package level
+/// state, which persists across calls to run and call.
 pub struct GosVM<'a> {
-    code: ByteCode,
+    code: &'a ByteCode,
+    packages: PackageInstances,
     gcv: GcoVec,
     ffi: &'a FfiFactory,
     fs: Option<&'a FileSet>,
+    limits: Limits,
+    interrupt: InterruptHandle,
+    stdout: Rc<RefCell<dyn Write>>,
+    stderr: Rc<RefCell<dyn Write>>,
+    // the functions declared without a body that ffi doesn't have, nothing
+    // is run unless it's empty
+    missing_natives: Vec<String>,
 }
 
 impl<'a> GosVM<'a> {
-    pub fn new(bc: ByteCode, ffi: &'a FfiFactory, fs: Option<&'a FileSet>) -> GosVM<'a> {
+    pub fn new(bc: &'a ByteCode, ffi: &'a FfiFactory, fs: Option<&'a FileSet>) -> GosVM<'a> {
+        let packages = bc
+            .packages
+            .iter()
+            .map(|&k| (k, bc.objects.packages[k].instantiate()))
+            .collect();
         GosVM {
             code: bc,
+            packages: packages,
             gcv: GcoVec::new(),
             ffi: ffi,
             fs: fs,
+            limits: Limits::default(),
+            interrupt: InterruptHandle::new(),
+            stdout: Rc::new(RefCell::new(io::stdout())),
+            stderr: Rc::new(RefCell::new(io::stderr())),
+            missing_natives: bc.missing_natives(ffi),
         }
     }
 
-    pub fn run(&self) {
-        let exec = Rc::new(LocalExecutor::new());
-        let ctx = Context::new(exec.clone(), &self.code, &self.gcv, self.ffi, self.fs);
-        let entry = ctx.new_entry_frame(self.code.entry);
-        ctx.spawn_fiber(Stack::new(), entry);
+    /// set_stdout sets where fmt.Println and friends write, it's the stdout
+    /// of the process by default.
+    pub fn set_stdout(&mut self, w: Rc<RefCell<dyn Write>>) {
+        self.stdout = w;
+    }
 
-        future::block_on(async {
-            loop {
-                if !exec.try_tick() {
-                    break;
+    /// set_stderr sets where the print and println builtins write, and where
+    /// unrecovered panics are reported, it's the stderr of the process by
+    /// default.
+    pub fn set_stderr(&mut self, w: Rc<RefCell<dyn Write>>) {
+        self.stderr = w;
+    }
+
+    /// set_limits sets the limits applied to every following run or call.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// interrupt_handle returns a handle that can stop this VM from any thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// run executes the entry function until it returns, the other fibers
+    /// left then are dropped without running their deferred calls. It returns
+    /// the exit code, 0, or the one given to os.Exit. It fails if any fiber
+    /// panics without recovering, or the limits are reached, or it's
+    /// interrupted.
+    pub fn run(&self) -> std::result::Result<i32, RuntimeError> {
+        future::block_on(self.run_impl(false))
+    }
+
+    /// run_async is the async version of run, for running the VM in an
+    /// executor of the host, which must be single-threaded. The futures of
+    /// async FFI calls are awaited on it. The goroutines waiting for the
+    /// channels shared with the host are not deadlocked, as the host may use
+    /// them meanwhile.
+    pub async fn run_async(&self) -> std::result::Result<i32, RuntimeError> {
+        self.run_impl(true).await
+    }
+
+    async fn run_impl(&self, host_async: bool) -> std::result::Result<i32, RuntimeError> {
+        let entry = GosValue::new_closure(self.code.entry, &self.code.objects.functions);
+        let frame = CallFrame::with_closure(entry.as_closure().clone(), 0);
+        match self.run_frame_async(Stack::new(), frame, host_async).await {
+            Ok(_) => Ok(0),
+            Err(RuntimeError::Exit(code)) => Ok(code),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// call calls the package level function func of the package named pkg,
+    /// and returns its results. The package is initialized by the first call
+    /// if it's not yet, its state is kept across calls.
+    pub fn call(
+        &self,
+        pkg: &str,
+        func: &str,
+        args: Vec<GosValue>,
+    ) -> std::result::Result<Vec<GosValue>, RuntimeError> {
+        future::block_on(self.call_impl(pkg, func, args, false))
+    }
+
+    /// call_async is the async version of call, see run_async.
+    pub async fn call_async(
+        &self,
+        pkg: &str,
+        func: &str,
+        args: Vec<GosValue>,
+    ) -> std::result::Result<Vec<GosValue>, RuntimeError> {
+        self.call_impl(pkg, func, args, true).await
+    }
+
+    async fn call_impl(
+        &self,
+        pkg: &str,
+        func: &str,
+        args: Vec<GosValue>,
+        host_async: bool,
+    ) -> std::result::Result<Vec<GosValue>, RuntimeError> {
+        let objs: &VMObjects = &self.code.objects;
+        let pkey = self
+            .find_package(pkg)
+            .ok_or_else(|| RuntimeError::InvalidCall(format!("package not found: {}", pkg)))?;
+        let pkg_val = &self.packages[pkey];
+        let cls = match pkg_val.get_member_index(func) {
+            Some(i) if *i > 0 => pkg_val.member(*i).clone(),
+            _ => {
+                let msg = format!("function not found: {}.{}", pkg, func);
+                return Err(RuntimeError::InvalidCall(msg));
+            }
+        };
+        let (stack, frame, results) = prepare_call(objs, &cls, args, &format!("{}.{}", pkg, func))?;
+
+        self.init_package(pkey, host_async).await?;
+
+        let stack = self.run_frame_async(stack, frame, host_async).await?;
+        let results = get_results(&stack.borrow(), &results, objs);
+        Ok(results)
+    }
+
+    /// get_global returns the package level variable, constant or function
+    /// name of the package named pkg, converted to T. The package is
+    /// initialized first if it's not yet, like by call.
+    pub fn get_global<T: FromGos>(
+        &self,
+        pkg: &str,
+        name: &str,
+    ) -> std::result::Result<T, RuntimeError> {
+        let (pkey, index) = self.find_global(pkg, name)?;
+        future::block_on(self.init_package(pkey, false))?;
+        let val = self.packages[pkey].member(index).clone();
+        T::from_gos_typed(val, &self.code.objects.metas)
+            .map_err(|e| RuntimeError::InvalidGlobal(format!("{}.{}: {}", pkg, name, e)))
+    }
+
+    /// set_global sets the package level variable name of the package named
+    /// pkg to val, which is converted to the type of the variable. The
+    /// package is initialized first if it's not yet, so that the value set
+    /// before run is not overwritten by the initializer of the variable.
+    pub fn set_global<T: ToGos>(
+        &self,
+        pkg: &str,
+        name: &str,
+        val: T,
+    ) -> std::result::Result<(), RuntimeError> {
+        let (pkey, index) = self.find_global(pkg, name)?;
+        let err = |msg: String| RuntimeError::InvalidGlobal(format!("{}.{}: {}", pkg, name, msg));
+        let meta = self.packages[pkey]
+            .var_meta(index)
+            .ok_or_else(|| err("not a variable".to_string()))?;
+        let val = self.to_gos_checked(val, meta, name).map_err(err)?;
+        future::block_on(self.init_package(pkey, false))?;
+        *self.packages[pkey].member_mut(index) = val;
+        Ok(())
+    }
+
+    /// try_send sends val to the channel ch if it can without blocking, it
+    /// returns false if the channel is full. val is converted to the type of
+    /// the elements, ch must have been converted to Go, and not be closed.
+    pub fn try_send<T: ToGos>(&self, ch: &Chan, val: T) -> std::result::Result<bool, RuntimeError> {
+        let val = self.chan_value(ch, val)?;
+        match ch.channel().try_send(val) {
+            Ok(()) => Ok(true),
+            Err(async_channel::TrySendError::Full(_)) => Ok(false),
+            Err(async_channel::TrySendError::Closed(_)) => {
+                Err(RuntimeError::Channel("send on closed channel".to_string()))
+            }
+        }
+    }
+
+    /// send sends val to the channel ch, waiting until there is room for it,
+    /// it's to be run on the executor running the VM, see run_async.
+    pub async fn send<T: ToGos>(&self, ch: &Chan, val: T) -> std::result::Result<(), RuntimeError> {
+        let val = self.chan_value(ch, val)?;
+        ch.channel()
+            .send(&val)
+            .await
+            .map_err(|_| RuntimeError::Channel("send on closed channel".to_string()))
+    }
+
+    /// try_recv receives a value from the channel ch if there is one, converted
+    /// to T, it returns None if there is none, Chan::is_closed tells if it's
+    /// because ch is closed.
+    pub fn try_recv<T: FromGos>(&self, ch: &Chan) -> std::result::Result<Option<T>, RuntimeError> {
+        match ch.channel().try_recv() {
+            Ok(v) => T::from_gos_typed(v, &self.code.objects.metas)
+                .map(Some)
+                .map_err(RuntimeError::Channel),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// recv receives a value from the channel ch, converted to T, waiting
+    /// until there is one. It returns None if ch is closed, like a range loop
+    /// over the channel ends.
+    pub async fn recv<T: FromGos>(
+        &self,
+        ch: &Chan,
+    ) -> std::result::Result<Option<T>, RuntimeError> {
+        match ch.channel().recv().await {
+            Some(v) => T::from_gos_typed(v, &self.code.objects.metas)
+                .map(Some)
+                .map_err(RuntimeError::Channel),
+            None => Ok(None),
+        }
+    }
+
+    /// call_closure calls the Go closure cls with args and returns its results,
+    /// like call. It's how the host calls back a func value it has been given
+    /// by an FFI call that has returned.
+    pub fn call_closure(
+        &self,
+        cls: &GosValue,
+        args: Vec<GosValue>,
+    ) -> std::result::Result<Vec<GosValue>, RuntimeError> {
+        let objs: &VMObjects = &self.code.objects;
+        let (stack, frame, results) = prepare_call(objs, cls, args, "the closure")?;
+        let stack = self.run_frame(stack, frame)?;
+        let results = get_results(&stack.borrow(), &results, objs);
+        Ok(results)
+    }
+
+    // converts val to the type meta of a value the host gives to Go code, and
+    // checks its type, name is the name of the value in the errors
+    fn to_gos_checked<T: ToGos>(
+        &self,
+        val: T,
+        meta: GosMetadata,
+        name: &str,
+    ) -> std::result::Result<GosValue, String> {
+        let objs: &VMObjects = &self.code.objects;
+        let ctx = FfiCtx {
+            func_name: name,
+            metas: &objs.metas,
+            gcv: &self.gcv,
+            results: &[],
+            runtime: self,
+        };
+        let val = val.to_gos(&ctx, meta)?;
+        let vt = meta.get_value_type(&objs.metas);
+        let ok = match &val {
+            GosValue::Nil(_) => !vt.copyable(),
+            GosValue::Named(n) => n.1 == meta,
+            v => v.get_type() == vt,
+        };
+        match ok {
+            true => Ok(val),
+            false => Err(format!("should be {:?}, got {:?}", vt, val.get_type())),
+        }
+    }
+
+    // converts val to the type of the elements of ch
+    fn chan_value<T: ToGos>(
+        &self,
+        ch: &Chan,
+        val: T,
+    ) -> std::result::Result<GosValue, RuntimeError> {
+        let meta = ch
+            .elem_meta(&self.code.objects.metas)
+            .ok_or_else(|| RuntimeError::Channel("channel not passed to Go yet".to_string()))?;
+        self.to_gos_checked(val, meta, "the channel")
+            .map_err(RuntimeError::Channel)
+    }
+
+    fn find_package(&self, pkg: &str) -> Option<PackageKey> {
+        self.code
+            .packages
+            .iter()
+            .find(|&&k| self.packages[k].name() == pkg)
+            .copied()
+    }
+
+    // returns the package and the member index of pkg.name, the unnamed
+    // constructor is not a global
+    fn find_global(
+        &self,
+        pkg: &str,
+        name: &str,
+    ) -> std::result::Result<(PackageKey, OpIndex), RuntimeError> {
+        let pkey = self
+            .find_package(pkg)
+            .ok_or_else(|| RuntimeError::InvalidGlobal(format!("package not found: {}", pkg)))?;
+        match self.packages[pkey].get_member_index(name) {
+            Some(i) if *i > 0 => Ok((pkey, *i)),
+            _ => {
+                let msg = format!("global not found: {}.{}", pkg, name);
+                Err(RuntimeError::InvalidGlobal(msg))
+            }
+        }
+    }
+
+    // runs the constructor of the package if it's not yet
+    async fn init_package(
+        &self,
+        pkey: PackageKey,
+        host_async: bool,
+    ) -> std::result::Result<(), RuntimeError> {
+        let pkg_val = &self.packages[pkey];
+        if !pkg_val.inited() {
+            let ctor = pkg_val.member(0).clone();
+            let frame = CallFrame::with_closure(ctor.as_closure().clone(), 0);
+            self.run_frame_async(Stack::new(), frame, host_async)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // runs a new fiber starting with frame until all fibers are done, returns
+    // the stack of the fiber, which holds the return values.
+    fn run_frame(
+        &self,
+        stack: Stack,
+        frame: CallFrame,
+    ) -> std::result::Result<Rc<RefCell<Stack>>, RuntimeError> {
+        future::block_on(self.run_frame_async(stack, frame, false))
+    }
+
+    // host_async tells if it's run by an executor of the host, see
+    // Context::wait_chan
+    async fn run_frame_async(
+        &self,
+        stack: Stack,
+        frame: CallFrame,
+        host_async: bool,
+    ) -> std::result::Result<Rc<RefCell<Stack>>, RuntimeError> {
+        if !self.missing_natives.is_empty() {
+            return Err(RuntimeError::MissingNatives(self.missing_natives.clone()));
+        }
+        let exec = Rc::new(LocalExecutor::new());
+        let ctx = Context::new(
+            Rc::downgrade(&exec),
+            self.code,
+            &self.packages,
+            &self.gcv,
+            self.ffi,
+            self.fs,
+            self.limits,
+            &self.interrupt,
+            self.stdout.clone(),
+            self.stderr.clone(),
+            host_async,
+        );
+        let mut f = Fiber::new(ctx.clone(), stack, frame);
+        let stack = f.stack.clone();
+        let entry = exec.spawn(async move { f.run().await });
+        ctx.run_fibers(&entry).await;
+        // the fibers left are dropped with the executor
+        drop(entry);
+        drop(exec);
+        let result = match ctx.error.borrow_mut().take() {
+            Some(e) => {
+                if let RuntimeError::Interrupted(_) = e {
+                    self.interrupt.clear();
                 }
+                Err(e)
             }
-        });
+            None => Ok(stack),
+        };
+        result
     }
 }
 
@@ -2,9 +2,11 @@
 //use super::opcode::OpIndex;
 use super::gc::GcoVec;
 use super::instruction::*;
-use super::objects::MetadataObjs;
+use super::metadata::{GosMetadata, MetadataType};
+use super::objects::{IfaceUnderlying, MetadataObjs, PackageInstances, UnderlyingFfi};
 use super::stack::Stack;
 use super::value::{EmptyResult, GosValue, GosValue64, RtValueResult, VMObjects};
+use std::rc::Rc;
 
 // restore stack_ref after drop to allow code in block call yield
 macro_rules! restore_stack_ref {
@@ -112,7 +114,7 @@ macro_rules! store_up_value {
 }
 
 macro_rules! deref_value {
-    ($pointers:expr, $self_:ident, $stack:ident, $frames:expr, $objs:expr) => {{
+    ($pointers:expr, $self_:ident, $stack:ident, $frames:expr, $pkgs:expr) => {{
         match $pointers {
             GosValue::Pointer(b) => {
                 let r: &PointerObj = &b;
@@ -138,9 +140,7 @@ macro_rules! deref_value {
                     PointerObj::StructField(s, index) => {
                         s.0.borrow().fields[*index as usize].clone()
                     }
-                    PointerObj::PkgMember(pkg, index) => {
-                        $objs.packages[*pkg].member(*index).clone()
-                    }
+                    PointerObj::PkgMember(pkg, index) => $pkgs[*pkg].member(*index).clone(),
                     PointerObj::Released => unreachable!(),
                 }
             }
@@ -225,14 +225,14 @@ pub fn load_index_int(val: &GosValue, i: usize) -> RtValueResult {
 }
 
 #[inline]
-pub fn load_field(val: &GosValue, ind: &GosValue, objs: &VMObjects) -> GosValue {
+pub fn load_field(val: &GosValue, ind: &GosValue, pkgs: &PackageInstances) -> GosValue {
     match val {
         GosValue::Struct(sval) => match &ind {
             GosValue::Int(i) => sval.0.borrow().fields[*i as usize].clone(),
             _ => unreachable!(),
         },
         GosValue::Package(pkey) => {
-            let pkg = &objs.packages[*pkey];
+            let pkg = &pkgs[*pkey];
             pkg.member(*ind.as_int() as OpIndex).clone()
         }
         _ => unreachable!(),
@@ -350,3 +350,51 @@ pub fn push_index_comma_ok(stack: &mut Stack, map: &GosValue, index: &GosValue)
     stack.push(v);
     stack.push_bool(b);
 }
+
+/// cast_iface converts the interface value val to another interface type meta,
+/// the methods of what val holds are looked up again for meta.
+pub fn cast_iface(val: &GosValue, meta: GosMetadata, objs: &VMObjects, stack: &Stack) -> GosValue {
+    let imeta = match &objs.metas[meta.as_non_ptr()] {
+        MetadataType::Named(_, u) => *u,
+        _ => meta,
+    };
+    let fields = objs.metas[imeta.as_non_ptr()].as_interface();
+    let iface = match val {
+        GosValue::Named(n) => &n.0,
+        _ => val,
+    };
+    let iface = match iface {
+        GosValue::Interface(i) => i.borrow(),
+        // a nil interface
+        _ => return GosValue::new_nil(),
+    };
+    let under = match iface.underlying() {
+        IfaceUnderlying::None => IfaceUnderlying::None,
+        IfaceUnderlying::Gos(v, _) => {
+            let named = match v.get_meta(objs, stack) {
+                GosMetadata::NonPtr(k, _) | GosMetadata::Ptr1(k, _) => Some(k),
+                _ => None,
+            };
+            let funcs = match named.map(|k| &objs.metas[k]) {
+                Some(MetadataType::Named(m, _)) if !fields.fields.is_empty() => Some(Rc::new(
+                    fields
+                        .iface_named_mapping(m)
+                        .iter()
+                        .map(|x| x.borrow().func.unwrap())
+                        .collect(),
+                )),
+                _ => None,
+            };
+            IfaceUnderlying::Gos(v.clone(), funcs)
+        }
+        IfaceUnderlying::Ffi(ffi) => IfaceUnderlying::Ffi(UnderlyingFfi {
+            methods: fields.iface_ffi_info(),
+            ..ffi.clone()
+        }),
+    };
+    let v = GosValue::new_iface(imeta, under);
+    match imeta == meta {
+        true => v,
+        false => GosValue::Named(Box::new((v, meta))),
+    }
+}